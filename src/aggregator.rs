@@ -1,143 +1,1613 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
 use std::time;
 
-use actix::fut::wrap_future;
+use actix::fut::{self, wrap_future, wrap_stream};
 use actix::prelude::*;
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, Utc};
 use failure::Error;
-use futures::{future, stream, Future, Stream};
-use itertools::{flatten, Itertools};
+use futures::sync::mpsc;
+use futures::{future, stream, Future};
+use itertools::Itertools;
 use smallvec::SmallVec;
 
-use apis::{WeatherData, WeatherDataVec, WeatherQuery};
+use apis::{HourlyData, WeatherData, WeatherDataVec, WeatherQuery, WeatherReport};
+use weather_api::RateLimited;
+
+/// Агрегированный прогноз вместе с названиями API, из ответов которых
+/// он собран, временем последнего обновления и временем, до которого
+/// он хранится в кэше. `utc_offset` - смещение местного времени города,
+/// если его сообщил хотя бы один API.
+#[derive(Clone, Debug)]
+pub struct Forecast {
+    pub query: WeatherQuery,
+    pub data: WeatherDataVec,
+    pub sources: Vec<String>,
+    pub utc_offset: Option<FixedOffset>,
+    pub fetched_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Forecast {
+    /// Сегодняшняя дата в городе. Если смещение неизвестно, то по UTC.
+    pub fn today(&self) -> NaiveDate {
+        let now = Utc::now();
+
+        match self.utc_offset {
+            Some(offset) => now.with_timezone(&offset).naive_local().date(),
+            None => now.naive_utc().date(),
+        }
+    }
+}
+
+/// Запрос агрегированного прогноза по городу.
+pub struct ForecastQuery(pub WeatherQuery);
+
+impl Message for ForecastQuery {
+    type Result = Result<Forecast, Error>;
+}
+
+/// Сведения о закэшированном запросе: возраст самого старого результата
+/// в секундах, число API с результатами и общее число записей прогноза.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CacheKeyInfo {
+    pub query: WeatherQuery,
+    pub age: i64,
+    pub providers: usize,
+    pub size: usize,
+}
+
+/// Статистика кэша с момента запуска. Вытеснениями считаются результаты
+/// API, удалённые из кэша при очистке или сбросе.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub size: usize,
+}
+
+/// Запрос списка закэшированных запросов.
+pub struct ListCache;
+
+impl Message for ListCache {
+    type Result = Vec<CacheKeyInfo>;
+}
+
+/// Сброс кэша по одному запросу или целиком. Возвращает число
+/// удалённых результатов API.
+pub struct PurgeCache(pub Option<WeatherQuery>);
+
+impl Message for PurgeCache {
+    type Result = usize;
+}
+
+/// Принудительное обновление кэша по запросу у всех API.
+pub struct RefreshCache(pub WeatherQuery);
+
+impl Message for RefreshCache {
+    type Result = Result<CacheKeyInfo, Error>;
+}
+
+/// Запрос статистики кэша.
+pub struct GetCacheStats;
+
+impl Message for GetCacheStats {
+    type Result = CacheStats;
+}
+
+/// Результат одного API по городу из кэша агрегатора.
+#[derive(Clone, Debug)]
+pub struct ProviderForecast {
+    pub name: String,
+    pub data: WeatherDataVec,
+    pub fetched_at: DateTime<Utc>,
+}
+
+/// Запрос закэшированных результатов каждого API по городу. Сами API
+/// не опрашиваются, для этого сначала нужно запросить `ForecastQuery`.
+pub struct GetProviderForecasts(pub WeatherQuery);
+
+impl Message for GetProviderForecasts {
+    type Result = Vec<ProviderForecast>;
+}
+
+/// Запрос почасового прогноза по городу из кэша. Значения включённых API
+/// на один и тот же час сводятся так же, как прогнозы по дням. Почасовой
+/// прогноз дают не все API, а сами API здесь не опрашиваются.
+pub struct GetHourlyForecast(pub WeatherQuery);
+
+impl Message for GetHourlyForecast {
+    type Result = Vec<HourlyData>;
+}
+
+/// Число последних обращений к API, по которым считаются доля ошибок
+/// и время ответа.
+const HEALTH_WINDOW: usize = 20;
+
+/// Сколько последних записей журнала изменений хранится.
+const AUDIT_LOG_SIZE: usize = 100;
+
+/// Как часто по умолчанию кэш по списку наблюдения дозагружается, если
+/// какие-то результаты API из него пропали: были удалены или не пришли
+/// из-за ошибки.
+const PREFETCH_INTERVAL: time::Duration = time::Duration::from_secs(15 * 60);
+
+/// Состояние погодного API по последним обращениям к нему. API считается
+/// здоровым, пока последнее обращение к нему не завершилось ошибкой.
+/// `error_rate` и `latency_ms` (среднее время ответа) считаются
+/// по последним `HEALTH_WINDOW` обращениям.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ProviderHealth {
+    pub name: String,
+    pub healthy: bool,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_failure: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub consecutive_failures: u32,
+    pub error_rate: f32,
+    pub latency_ms: Option<u64>,
+    #[serde(skip)]
+    recent: VecDeque<(bool, time::Duration)>,
+}
+
+impl ProviderHealth {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            healthy: true,
+            ..Default::default()
+        }
+    }
+
+    fn succeeded(&mut self, at: DateTime<Utc>, latency: time::Duration) {
+        self.healthy = true;
+        self.last_success = Some(at);
+        self.consecutive_failures = 0;
+        self.record(true, latency);
+    }
+
+    fn failed(&mut self, at: DateTime<Utc>, latency: time::Duration, err: String) {
+        self.healthy = false;
+        self.last_failure = Some(at);
+        self.last_error = Some(err);
+        self.consecutive_failures += 1;
+        self.record(false, latency);
+    }
+
+    fn record(&mut self, succeeded: bool, latency: time::Duration) {
+        if self.recent.len() == HEALTH_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((succeeded, latency));
+
+        let count = self.recent.len() as u32;
+        let failures = self.recent.iter().filter(|(succeeded, _)| !succeeded).count();
+        let total: time::Duration = self.recent.iter().map(|(_, latency)| *latency).sum();
+        let average = total / count;
+
+        self.error_rate = failures as f32 / count as f32;
+        self.latency_ms = Some(average.as_secs() * 1000 + u64::from(average.subsec_millis()));
+    }
+}
+
+/// Запрос состояния всех погодных API.
+pub struct GetProviderHealth;
+
+impl Message for GetProviderHealth {
+    type Result = Vec<ProviderHealth>;
+}
+
+/// Погодный API агрегатора, его вес и то, опрашивается ли он сейчас.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProviderInfo {
+    pub name: String,
+    pub weight: f32,
+    pub enabled: bool,
+}
+
+/// Запрос списка погодных API, которые опрашивает агрегатор.
+pub struct ListProviders;
+
+impl Message for ListProviders {
+    type Result = Vec<ProviderInfo>;
+}
+
+/// Добавление погодного API на ходу. Отвечает добавленным API.
+pub struct AddApi {
+    pub name: String,
+    pub api: Recipient<WeatherQuery>,
+    pub weight: f32,
+    pub enabled: bool,
+    pub request_id: Option<String>,
+}
+
+impl Message for AddApi {
+    type Result = Result<ProviderInfo, Error>;
+}
+
+/// Удаление погодного API вместе с его результатами в кэше. Запросы,
+/// уже отправленные API, дорабатывают, но их ответы отбрасываются.
+/// Отвечает удалённым API.
+pub struct RemoveApi {
+    pub name: String,
+    pub request_id: Option<String>,
+}
+
+impl Message for RemoveApi {
+    type Result = Result<ProviderInfo, Error>;
+}
+
+/// Включение или отключение погодного API. Отключённый API не опрашивается,
+/// а его результаты в кэше не учитываются в агрегате, пока API не включат
+/// снова. Отвечает изменённым API.
+pub struct SetApiEnabled {
+    pub name: String,
+    pub enabled: bool,
+    pub request_id: Option<String>,
+}
+
+impl Message for SetApiEnabled {
+    type Result = Result<ProviderInfo, Error>;
+}
+
+/// Новый вес погодного API. Отвечает изменённым API.
+pub struct SetApiWeight {
+    pub name: String,
+    pub weight: f32,
+    pub request_id: Option<String>,
+}
+
+impl Message for SetApiWeight {
+    type Result = Result<ProviderInfo, Error>;
+}
+
+/// Запись журнала изменений набора погодных API: что сделано, с каким API
+/// и в ходе какого запроса.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub at: DateTime<Utc>,
+    pub action: String,
+    pub provider: Option<String>,
+    pub details: String,
+    pub request_id: Option<String>,
+}
+
+/// Запрос журнала изменений, от старых записей к новым.
+pub struct GetAuditLog;
+
+impl Message for GetAuditLog {
+    type Result = Vec<AuditEntry>;
+}
+
+/// Новый набор погодных API (имя, актор и вес) и настройки агрегатора
+/// после перезагрузки конфигурации. Отвечает списком API, которые
+/// агрегатор опрашивает после замены.
+pub struct Reconfigure {
+    pub apis: Vec<(String, Recipient<WeatherQuery>, f32)>,
+    pub aggregation: Aggregation,
+    pub watchlist: Vec<WeatherQuery>,
+    pub not_found_ttl: Duration,
+    pub prefetch_spacing: time::Duration,
+    pub prefetch_interval: time::Duration,
+}
+
+impl Message for Reconfigure {
+    type Result = Vec<ProviderInfo>;
+}
+
+/// Запрос числа сообщений, отправленных актору каждого API, на которые
+/// он ещё не ответил.
+pub struct GetPendingRequests;
+
+impl Message for GetPendingRequests {
+    type Result = Vec<(String, usize)>;
+}
+
+/// Событие потока прогноза по городу.
+#[derive(Debug)]
+pub enum ForecastEvent {
+    /// Данные одного API (`None`, если он не ответил) и агрегат по всем
+    /// результатам, закэшированным на этот момент. Свежие результаты
+    /// из кэша отдаются сразу, остальные - по мере ответа API.
+    Provider {
+        name: String,
+        data: Option<Vec<WeatherData>>,
+        aggregate: Forecast,
+    },
+    /// Итоговый агрегат, последнее событие потока.
+    Done(Result<Forecast, Error>),
+}
+
+/// Запрос прогноза по городу в виде потока событий. API, результатов
+/// которых нет в кэше, опрашиваются так же, как и для `ForecastQuery`.
+pub struct StreamForecast(pub WeatherQuery);
+
+impl Message for StreamForecast {
+    type Result = mpsc::UnboundedReceiver<ForecastEvent>;
+}
+
+/// Изменение прогноза на один день. `None` - прогноза на этот день
+/// не было или больше нет.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DayChange {
+    pub date: NaiveDate,
+    pub previous: Option<f32>,
+    pub current: Option<f32>,
+}
+
+/// Уведомление подписчика об обновлении закэшированного прогноза
+/// по городу вместе с изменениями по дням.
+#[derive(Clone, Debug)]
+pub struct ForecastUpdated {
+    pub forecast: Forecast,
+    pub changes: Vec<DayChange>,
+}
+
+impl Message for ForecastUpdated {
+    type Result = ();
+}
+
+/// Подписка на обновления прогноза по городу. Возвращает идентификатор
+/// подписки и текущий закэшированный агрегат. Если в кэше не хватает
+/// результатов каких-то API, то они запрашиваются сразу.
+pub struct Subscribe {
+    pub query: WeatherQuery,
+    pub subscriber: Recipient<ForecastUpdated>,
+}
+
+impl Message for Subscribe {
+    type Result = (usize, Forecast);
+}
+
+/// Отмена подписки по её идентификатору.
+pub struct Unsubscribe(pub usize);
+
+impl Message for Unsubscribe {
+    type Result = ();
+}
+
+/// У агрегатора нет погодного API с таким именем.
+#[derive(Fail, Debug)]
+#[fail(display = "weather API {} not found", _0)]
+pub struct UnknownProvider(pub String);
+
+/// Погодный API с таким именем уже есть.
+#[derive(Fail, Debug)]
+#[fail(display = "weather API {} already exists", _0)]
+pub struct ProviderExists(pub String);
+
+/// Ни один из ответивших API не знает запрошенный город.
+#[derive(Fail, Debug)]
+#[fail(display = "location not found - {}", _0)]
+pub struct UnknownLocation(pub WeatherQuery);
+
+/// Способ свести прогнозы разных API на один день в одно значение.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregation {
+    /// Среднее арифметическое.
+    #[default]
+    Mean,
+    /// Медиана: сильно ошибающийся API не сдвигает прогноз.
+    Median,
+    /// Среднее, взвешенное по весам API.
+    WeightedMean,
+}
+
+impl FromStr for Aggregation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mean" => Ok(Aggregation::Mean),
+            "median" => Ok(Aggregation::Median),
+            "weighted_mean" => Ok(Aggregation::WeightedMean),
+            _ => Err(format_err!(
+                "unknown aggregation strategy {:?}, expected \"mean\", \"median\" or \"weighted_mean\"",
+                s
+            )),
+        }
+    }
+}
+
+impl Aggregation {
+    /// Сводит температуры с весами их API в одну.
+    fn combine(self, mut values: SmallVec<[(f32, f32); 32]>) -> f32 {
+        match self {
+            Aggregation::Mean => {
+                values.iter().map(|(temperature, _)| temperature).sum::<f32>()
+                    / values.len() as f32
+            }
+            Aggregation::Median => {
+                values.sort_unstable_by(|(t1, _), (t2, _)| t1.total_cmp(t2));
+
+                // При нечётном числе значений оба индекса указывают на середину.
+                let (lower, upper) = ((values.len() - 1) / 2, values.len() / 2);
+                (values[lower].0 + values[upper].0) / 2.0
+            }
+            Aggregation::WeightedMean => {
+                let total: f32 = values.iter().map(|(_, weight)| weight).sum();
+                if total <= 0.0 {
+                    return Aggregation::Mean.combine(values);
+                }
+
+                values
+                    .iter()
+                    .map(|(temperature, weight)| temperature * weight)
+                    .sum::<f32>()
+                    / total
+            }
+        }
+    }
+}
+
+/// Результат одного погодного API по конкретному запросу.
+struct ProviderEntry {
+    data: WeatherDataVec,
+    hourly: Vec<HourlyData>,
+    utc_offset: Option<FixedOffset>,
+    fetched_at: DateTime<Utc>,
+}
 
 /// Актор, агрегирующий результаты запросов в погодным API. Хранит кэш
-/// таких запросов, который очищается каждый день в полночь по UTC.
+/// результатов каждого API по отдельности, а агрегат вычисляет при чтении.
+/// Так если какой-то API не ответил или его данные устарели, то повторно
+/// запрашивается только он. Кэш очищается каждый день в полночь по UTC.
+///
+/// Для запросов из списка наблюдения (`watch`) кэш прогревается заранее -
+/// при старте, сразу после каждой очистки и затем раз в `prefetch_interval`
+/// (по умолчанию 15 минут), если результатов каких-то API в нём не хватает.
+/// Запросы разнесены во времени на `prefetch_spacing`, чтобы не упираться
+/// в ограничения API.
+///
+/// Города, которых не знает ни один из ответивших API, запоминаются
+/// отдельно на `not_found_ttl`, чтобы опечатки в запросах не приводили
+/// к обращениям ко всем API каждый раз. Пока хоть один API не отвечает,
+/// город неизвестным не считается.
+///
+/// Подписчики (`Subscribe`) получают агрегат после каждого обновления
+/// кэша по их городу. Кэш по городам с подписчиками прогревается так же,
+/// как и по списку наблюдения.
+///
+/// Прогнозы API на один день сводятся способом `aggregation`; для
+/// взвешенного среднего у каждого API есть вес (по умолчанию `1`).
+///
+/// Набор API и настройки можно заменить на ходу сообщением `Reconfigure`:
+/// кэш сохраняется, запросы, уже отправленные старым акторам API,
+/// дорабатывают до конца, а отключённые API остаются отключёнными.
+/// Отдельные API можно добавлять (`AddApi`), удалять (`RemoveApi`),
+/// отключать (`SetApiEnabled`) и перевзвешивать (`SetApiWeight`); все
+/// изменения набора пишутся в журнал (`GetAuditLog`) и в лог с источником
+/// `audit`.
 pub struct Aggregator {
-    weather_apis: SmallVec<[Recipient<WeatherQuery>; 32]>,
-    cache: HashMap<WeatherQuery, WeatherDataVec>,
+    weather_apis: SmallVec<[(String, Recipient<WeatherQuery>); 32]>,
+    weights: SmallVec<[f32; 32]>,
+    enabled: SmallVec<[bool; 32]>,
+    aggregation: Aggregation,
+    health: SmallVec<[ProviderHealth; 32]>,
+    pending: SmallVec<[usize; 32]>,
+    cache: HashMap<WeatherQuery, HashMap<usize, ProviderEntry>>,
+    not_found: HashMap<WeatherQuery, DateTime<Utc>>,
+    not_found_ttl: Duration,
+    watchlist: Vec<WeatherQuery>,
+    prefetch_spacing: time::Duration,
+    prefetch_interval: time::Duration,
+    prefetch_timer: Option<SpawnHandle>,
+    stats: CacheStats,
+    subscribers: HashMap<usize, (WeatherQuery, Recipient<ForecastUpdated>)>,
+    next_subscriber: usize,
+    audit_log: VecDeque<AuditEntry>,
+}
+
+unsafe impl Sync for Aggregator {}
+
+impl Aggregator {
+    pub fn new() -> Self {
+        Self {
+            weather_apis: SmallVec::new(),
+            weights: SmallVec::new(),
+            enabled: SmallVec::new(),
+            aggregation: Aggregation::default(),
+            health: SmallVec::new(),
+            pending: SmallVec::new(),
+            cache: HashMap::new(),
+            not_found: HashMap::new(),
+            not_found_ttl: Duration::minutes(5),
+            watchlist: Vec::new(),
+            prefetch_spacing: time::Duration::from_secs(0),
+            prefetch_interval: PREFETCH_INTERVAL,
+            prefetch_timer: None,
+            stats: CacheStats::default(),
+            subscribers: HashMap::new(),
+            next_subscriber: 0,
+            audit_log: VecDeque::new(),
+        }
+    }
+
+    pub fn add_api(mut self, name: &str, api: Recipient<WeatherQuery>) -> Self {
+        self.weather_apis.push((name.to_string(), api));
+        self.weights.push(1.0);
+        self.enabled.push(true);
+        self.health.push(ProviderHealth::new(name));
+        self.pending.push(0);
+
+        self
+    }
+
+    /// Вес уже добавленного API `name` для взвешенного среднего.
+    pub fn weight(mut self, name: &str, weight: f32) -> Self {
+        if let Some(idx) = self.api_index(name) {
+            self.weights[idx] = weight;
+        }
+
+        self
+    }
+
+    pub fn watch(mut self, query: WeatherQuery) -> Self {
+        self.watchlist.push(query);
+
+        self
+    }
+
+    pub fn prefetch_spacing(mut self, spacing: time::Duration) -> Self {
+        self.prefetch_spacing = spacing;
+
+        self
+    }
+
+    pub fn prefetch_interval(mut self, interval: time::Duration) -> Self {
+        self.prefetch_interval = interval;
+
+        self
+    }
+
+    pub fn not_found_ttl(mut self, ttl: Duration) -> Self {
+        self.not_found_ttl = ttl;
+
+        self
+    }
+
+    pub fn aggregation(mut self, aggregation: Aggregation) -> Self {
+        self.aggregation = aggregation;
+
+        self
+    }
+
+    /// Заменяет набор API (имя, актор, вес и включён ли API). Для API,
+    /// которые остались в наборе, по имени сохраняются результаты в кэше,
+    /// состояние и число запросов, ещё не получивших ответа; результаты
+    /// убранных API удаляются из кэша.
+    fn replace_apis<I>(&mut self, apis: I)
+    where
+        I: IntoIterator<Item = (String, Recipient<WeatherQuery>, f32, bool)>,
+    {
+        let mut weather_apis = SmallVec::new();
+        let mut weights = SmallVec::new();
+        let mut enabled_apis = SmallVec::new();
+        let mut health = SmallVec::new();
+        let mut pending = SmallVec::new();
+        let mut moved = HashMap::new();
+
+        for (name, api, weight, enabled) in apis {
+            match self.api_index(&name) {
+                Some(idx) => {
+                    moved.insert(idx, weather_apis.len());
+                    health.push(self.health[idx].clone());
+                    pending.push(self.pending[idx]);
+                }
+                None => {
+                    health.push(ProviderHealth::new(&name));
+                    pending.push(0);
+                }
+            }
+
+            weather_apis.push((name, api));
+            weights.push(weight);
+            enabled_apis.push(enabled);
+        }
+
+        let mut evicted = 0;
+        for entries in self.cache.values_mut() {
+            let before = entries.len();
+            *entries = entries
+                .drain()
+                .filter_map(|(idx, entry)| moved.get(&idx).map(|idx| (*idx, entry)))
+                .collect();
+            evicted += before - entries.len();
+        }
+        self.cache.retain(|_, entries| !entries.is_empty());
+        self.stats.evictions += evicted as u64;
+
+        self.weather_apis = weather_apis;
+        self.weights = weights;
+        self.enabled = enabled_apis;
+        self.health = health;
+        self.pending = pending;
+    }
+
+    fn api_index(&self, name: &str) -> Option<usize> {
+        self.weather_apis.iter().position(|(api, _)| api == name)
+    }
+
+    /// Индекс API `name` или ошибка `UnknownProvider`.
+    fn known_api(&self, name: &str) -> Result<usize, Error> {
+        self.api_index(name)
+            .ok_or_else(|| Error::from(UnknownProvider(name.to_string())))
+    }
+
+    /// Индексы включённых API.
+    fn enabled_apis(&self) -> SmallVec<[usize; 32]> {
+        (0..self.weather_apis.len())
+            .filter(|idx| self.enabled[*idx])
+            .collect()
+    }
+
+    fn provider(&self, idx: usize) -> ProviderInfo {
+        ProviderInfo {
+            name: self.weather_apis[idx].0.clone(),
+            weight: self.weights[idx],
+            enabled: self.enabled[idx],
+        }
+    }
+
+    fn providers(&self) -> Vec<ProviderInfo> {
+        (0..self.weather_apis.len())
+            .map(|idx| self.provider(idx))
+            .collect()
+    }
+
+    /// Добавляет запись в журнал изменений и пишет её в лог.
+    fn audit(
+        &mut self,
+        action: &str,
+        provider: Option<&str>,
+        details: String,
+        request_id: Option<String>,
+    ) {
+        info!(
+            target: "audit",
+            action = action,
+            provider = provider,
+            request_id = request_id.as_deref();
+            "[{}] {}",
+            request_id.as_deref().unwrap_or("-"),
+            details
+        );
+
+        if self.audit_log.len() == AUDIT_LOG_SIZE {
+            self.audit_log.pop_front();
+        }
+        self.audit_log.push_back(AuditEntry {
+            at: Utc::now(),
+            action: action.to_string(),
+            provider: provider.map(str::to_string),
+            details,
+            request_id,
+        });
+    }
+
+    /// Сводит прогнозы по дням. Каждый прогноз идёт с весом API,
+    /// который его дал.
+    fn aggregate(
+        aggregation: Aggregation,
+        mut weather_data: Vec<(f32, WeatherData)>,
+    ) -> WeatherDataVec {
+        weather_data.sort_unstable_by(|(_, entry1), (_, entry2)| entry1.date.cmp(&entry2.date));
+
+        weather_data
+            .iter()
+            .group_by(|(_, entry)| entry.date)
+            .into_iter()
+            .map(|(day, data)| WeatherData {
+                date: day,
+                temperature: aggregation.combine(
+                    data.map(|(weight, data)| (data.temperature, *weight)).collect(),
+                ),
+            }).collect::<WeatherDataVec>()
+    }
+
+    /// Сводит почасовые прогнозы так же, как прогнозы по дням.
+    fn aggregate_hourly(
+        aggregation: Aggregation,
+        mut hourly: Vec<(f32, HourlyData)>,
+    ) -> Vec<HourlyData> {
+        hourly.sort_unstable_by_key(|(_, entry)| entry.time);
+
+        hourly
+            .iter()
+            .group_by(|(_, entry)| entry.time)
+            .into_iter()
+            .map(|(time, data)| HourlyData {
+                time,
+                temperature: aggregation.combine(
+                    data.map(|(weight, data)| (data.temperature, *weight)).collect(),
+                ),
+            }).collect()
+    }
+
+    /// Закэшированные результаты включённых API по запросу.
+    fn enabled_entries<'a>(
+        &'a self,
+        query: &WeatherQuery,
+    ) -> impl Iterator<Item = (&'a usize, &'a ProviderEntry)> + Clone + 'a {
+        self.cache
+            .get(query)
+            .into_iter()
+            .flat_map(|entries| entries.iter())
+            .filter(move |(idx, _)| self.enabled[**idx])
+    }
+
+    /// Агрегирует все закэшированные результаты включённых API по запросу.
+    fn cached_aggregate(&self, query: &WeatherQuery) -> Forecast {
+        let entries = self.enabled_entries(query);
+
+        let all_data = entries
+            .clone()
+            .flat_map(|(idx, entry)| {
+                let weight = self.weights[*idx];
+                entry.data.iter().map(move |data| (weight, data.clone()))
+            }).collect();
+
+        let fetched_at = entries
+            .clone()
+            .map(|(_, entry)| entry.fetched_at)
+            .max()
+            .unwrap_or_else(Utc::now);
+
+        let sources = entries
+            .clone()
+            .filter(|(_, entry)| !entry.data.is_empty())
+            .map(|(idx, _)| self.weather_apis[*idx].0.clone())
+            .sorted();
+
+        let utc_offset = entries
+            .filter_map(|(idx, entry)| entry.utc_offset.map(|offset| (*idx, offset)))
+            .min_by_key(|(idx, _)| *idx)
+            .map(|(_, offset)| offset);
+
+        Forecast {
+            query: query.clone(),
+            data: Self::aggregate(self.aggregation, all_data),
+            sources,
+            utc_offset,
+            fetched_at,
+            expires_at: Self::next_midnight(),
+        }
+    }
+
+    fn key_info(&self, query: &WeatherQuery) -> CacheKeyInfo {
+        let now = Utc::now();
+        let entries = self.cache.get(query);
+
+        let oldest = entries
+            .into_iter()
+            .flat_map(|entries| entries.values())
+            .map(|entry| entry.fetched_at)
+            .min()
+            .unwrap_or(now);
+
+        CacheKeyInfo {
+            query: query.clone(),
+            age: now.signed_duration_since(oldest).num_seconds(),
+            providers: entries.map_or(0, |entries| entries.len()),
+            size: entries.map_or(0, |entries| {
+                entries.values().map(|entry| entry.data.len()).sum()
+            }),
+        }
+    }
+
+    /// Удаляет из кэша результаты по запросу или все результаты,
+    /// если запрос не указан. Возвращает число удалённых результатов.
+    fn purge(&mut self, query: Option<&WeatherQuery>) -> usize {
+        let evicted = match query {
+            Some(query) => {
+                self.not_found.remove(query);
+                self.cache.remove(query).map_or(0, |entries| entries.len())
+            }
+            None => {
+                self.not_found.clear();
+                let evicted = self.cache.values().map(|entries| entries.len()).sum();
+                self.cache.clear();
+                self.cache.shrink_to_fit();
+                evicted
+            }
+        };
+
+        self.stats.evictions += evicted as u64;
+
+        evicted
+    }
+
+    /// Индексы включённых API, для которых нет свежего результата
+    /// по запросу - либо API ещё не спрашивали, либо он ответил ошибкой,
+    /// либо результат получен не сегодня.
+    fn stale_apis(&self, query: &WeatherQuery) -> SmallVec<[usize; 32]> {
+        let today = Utc::now().naive_utc().date();
+        let entries = self.cache.get(query);
+
+        self.enabled_apis()
+            .into_iter()
+            .filter(|idx| {
+                match entries.and_then(|entries| entries.get(idx)) {
+                    Some(entry) => entry.fetched_at.naive_utc().date() != today,
+                    None => true,
+                }
+            }).collect()
+    }
+
+    /// Сохраняет ответ API в кэш и обновляет состояние API.
+    /// Возвращает `false`, если API не ответил.
+    fn store(
+        &mut self,
+        query: &WeatherQuery,
+        idx: usize,
+        result: Result<Result<WeatherReport, Error>, MailboxError>,
+        latency: time::Duration,
+    ) -> bool {
+        let fetched_at = Utc::now();
+        // API могли убрать и добавить снова, пока запрос к нему выполнялся.
+        self.pending[idx] = self.pending[idx].saturating_sub(1);
+
+        let err = match result {
+            Ok(Ok(WeatherReport {
+                data,
+                hourly,
+                utc_offset,
+            })) => {
+                self.health[idx].succeeded(fetched_at, latency);
+                self.cache
+                    .entry(query.clone())
+                    .or_insert_with(HashMap::new)
+                    .insert(
+                        idx,
+                        ProviderEntry {
+                            data,
+                            hourly,
+                            utc_offset,
+                            fetched_at,
+                        },
+                    );
+                return true;
+            }
+            // Запрос не отправлен из-за нашего же ограничения частоты, так
+            // что о состоянии API и о городе ничего не известно.
+            Ok(Err(ref err)) if err.downcast_ref::<RateLimited>().is_some() => {
+                info!(
+                    request_id = query.request_id(),
+                    provider = self.weather_apis[idx].0.as_str(),
+                    city = query.city();
+                    "[{}] Skipped weather API {}: {}",
+                    query.request_id().unwrap_or("-"),
+                    self.weather_apis[idx].0,
+                    err
+                );
+                return false;
+            }
+            Ok(Err(err)) => {
+                warn!(
+                    request_id = query.request_id(),
+                    provider = self.weather_apis[idx].0.as_str(),
+                    city = query.city();
+                    "[{}] Weather API {} failed: {}",
+                    query.request_id().unwrap_or("-"),
+                    self.weather_apis[idx].0,
+                    err
+                );
+                err.to_string()
+            }
+            Err(err) => {
+                warn!(
+                    request_id = query.request_id(),
+                    provider = self.weather_apis[idx].0.as_str(),
+                    city = query.city();
+                    "[{}] Weather API {} is unavailable: {}",
+                    query.request_id().unwrap_or("-"),
+                    self.weather_apis[idx].0,
+                    err
+                );
+                err.to_string()
+            }
+        };
+
+        self.health[idx].failed(fetched_at, latency, err);
+
+        false
+    }
+
+    /// Событие потока о результате API с агрегатом по всем результатам,
+    /// закэшированным на этот момент.
+    fn provider_event(&self, query: &WeatherQuery, idx: usize) -> ForecastEvent {
+        let data = self
+            .cache
+            .get(query)
+            .and_then(|entries| entries.get(&idx))
+            .map(|entry| entry.data.to_vec());
+
+        ForecastEvent::Provider {
+            name: self.weather_apis[idx].0.clone(),
+            data,
+            aggregate: self.cached_aggregate(query),
+        }
+    }
+
+    /// Запрашивает у указанных API данные, сохраняет успешные ответы
+    /// в кэш и возвращает агрегат по всем закэшированным результатам.
+    /// Если задан `progress`, то в него по мере поступления отправляется
+    /// событие о каждом ответе.
+    fn fetch(
+        &mut self,
+        query: WeatherQuery,
+        apis: SmallVec<[usize; 32]>,
+        progress: Option<mpsc::UnboundedSender<ForecastEvent>>,
+    ) -> ResponseActFuture<Self, Forecast, Error> {
+        let previous = self.cached_aggregate(&query).data;
+
+        for idx in &apis {
+            self.pending[*idx] += 1;
+        }
+
+        let requests = apis.into_iter().map(|idx| {
+            let started = time::Instant::now();
+            let (ref name, ref api) = self.weather_apis[idx];
+            let name = name.clone();
+
+            api.send(query.clone())
+                .then(move |res| future::ok::<_, Error>((name, res, started.elapsed())))
+        });
+
+        let results = {
+            let query = query.clone();
+
+            wrap_stream::<_, Self>(stream::futures_unordered(requests)).fold(
+                (false, false),
+                move |(answered, failed), (name, result, latency), actor, _ctx| {
+                    // Пока запрос выполнялся, набор API могли заменить:
+                    // ответ убранного API отбрасывается.
+                    let idx = match actor.api_index(&name) {
+                        Some(idx) => idx,
+                        None => return fut::ok::<_, Error, Self>((answered, failed)),
+                    };
+                    let succeeded = actor.store(&query, idx, result, latency);
+
+                    if let Some(ref progress) = progress {
+                        let _ = progress.unbounded_send(actor.provider_event(&query, idx));
+                    }
+
+                    fut::ok::<_, Error, Self>((answered || succeeded, failed || !succeeded))
+                },
+            )
+        };
+
+        let update_self = results.and_then(move |(answered, failed), actor, _ctx| {
+            let forecast = actor.cached_aggregate(&query);
+
+            // Все опрошенные API ответили, но ни у одного нет данных -
+            // значит, такого города нет. Запоминаем это отдельно от
+            // прогнозов. Если хоть один API не ответил, город может
+            // найтись у него при следующем запросе.
+            if answered && !failed && forecast.data.is_empty() {
+                actor.cache.remove(&query);
+                actor
+                    .not_found
+                    .insert(query.clone(), Utc::now() + actor.not_found_ttl);
+
+                return fut::err(Error::from(UnknownLocation(query)));
+            }
+
+            actor.notify_subscribers(&previous, &forecast);
+
+            fut::ok(forecast)
+        });
+
+        Box::new(update_self)
+    }
+
+    /// Отправляет обновлённый агрегат подписчикам на город. Подписчики,
+    /// которые больше не принимают сообщения, удаляются.
+    fn notify_subscribers(&mut self, previous: &[WeatherData], forecast: &Forecast) {
+        let changes = Self::changes(previous, &forecast.data);
+
+        self.subscribers.retain(|_, (query, subscriber)| {
+            if *query != forecast.query {
+                return true;
+            }
+
+            let update = ForecastUpdated {
+                forecast: forecast.clone(),
+                changes: changes.clone(),
+            };
+
+            // Подписчик, которого уже нет, удаляется, а переполненный
+            // просто пропускает это обновление.
+            !matches!(subscriber.do_send(update), Err(SendError::Closed(_)))
+        });
+    }
+
+    /// Дни, прогноз на которые отличается в двух агрегатах.
+    fn changes(previous: &[WeatherData], current: &[WeatherData]) -> Vec<DayChange> {
+        let temperature = |data: &[WeatherData], date| {
+            data.iter()
+                .find(|entry| entry.date == date)
+                .map(|entry| entry.temperature)
+        };
+
+        previous
+            .iter()
+            .chain(current)
+            .map(|entry| entry.date)
+            .sorted()
+            .into_iter()
+            .dedup()
+            .map(|date| DayChange {
+                date,
+                previous: temperature(previous, date),
+                current: temperature(current, date),
+            }).filter(|change| change.previous != change.current)
+            .collect()
+    }
+
+    /// Известно ли, что такого города нет. Устаревшие записи удаляются.
+    fn known_missing(&mut self, query: &WeatherQuery) -> bool {
+        match self.not_found.get(query).cloned() {
+            Some(expires_at) if expires_at > Utc::now() => true,
+            Some(_) => {
+                self.not_found.remove(query);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Планирует обновление всех запросов из списка наблюдения
+    /// и запросов, на которые есть подписчики.
+    fn schedule_prefetch(&self, ctx: &mut Context<Self>) {
+        let subscribed = self.subscribers.values().map(|(query, _)| query);
+
+        for (i, query) in self.watchlist.iter().chain(subscribed).unique().enumerate() {
+            ctx.notify_later(Prefetch(query.clone()), self.prefetch_spacing * i as u32);
+        }
+    }
+
+    /// Запускает обновление раз в `prefetch_interval` вместо запущенного
+    /// раньше.
+    fn run_prefetch_timer(&mut self, ctx: &mut Context<Self>) {
+        if let Some(timer) = self.prefetch_timer.take() {
+            ctx.cancel_future(timer);
+        }
+
+        let timer = ctx.run_interval(self.prefetch_interval, |act, ctx| act.schedule_prefetch(ctx));
+        self.prefetch_timer = Some(timer);
+    }
+
+    fn next_midnight() -> DateTime<Utc> {
+        (Utc::now() + Duration::days(1)).date().and_hms(0, 0, 0)
+    }
+
+    fn duration_til_next_midnight(&mut self) -> time::Duration {
+        let now = Utc::now();
+        let next_midnignt = Self::next_midnight();
+
+        next_midnignt.signed_duration_since(now).to_std().unwrap()
+    }
+}
+
+#[derive(Message)]
+struct CacheCleanup;
+
+/// Загрузка в кэш результатов API, которых в нём нет или которые
+/// устарели, по запросу из списка наблюдения.
+#[derive(Message)]
+struct Prefetch(WeatherQuery);
+
+impl Actor for Aggregator {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.schedule_prefetch(ctx);
+        self.run_prefetch_timer(ctx);
+
+        let at_midnight = self.duration_til_next_midnight();
+        ctx.notify_later(CacheCleanup, at_midnight);
+    }
+}
+
+impl Handler<CacheCleanup> for Aggregator {
+    type Result = ();
+
+    fn handle(&mut self, _msg: CacheCleanup, ctx: &mut Self::Context) -> Self::Result {
+        self.purge(None);
+
+        self.schedule_prefetch(ctx);
+
+        let at_midnight = self.duration_til_next_midnight();
+        ctx.notify_later(CacheCleanup, at_midnight);
+    }
+}
+
+impl Handler<Prefetch> for Aggregator {
+    type Result = ();
+
+    fn handle(&mut self, msg: Prefetch, ctx: &mut Self::Context) -> Self::Result {
+        let Prefetch(query) = msg;
+        let apis = self.stale_apis(&query);
+        if apis.is_empty() {
+            return;
+        }
+
+        debug!("Prefetching {}", query);
+
+        ctx.spawn(self.fetch(query, apis, None).map(|_, _, _| ()).map_err(|err, _, _| {
+            warn!("Prefetch failed: {}", err);
+        }));
+    }
+}
+
+impl Handler<ForecastQuery> for Aggregator {
+    type Result = ResponseActFuture<Self, Forecast, Error>;
+
+    fn handle(&mut self, msg: ForecastQuery, _ctx: &mut Self::Context) -> Self::Result {
+        let ForecastQuery(msg) = msg;
+
+        if self.known_missing(&msg) {
+            self.stats.hits += 1;
+
+            let not_found = fut::err(Error::from(UnknownLocation(msg)));
+            return Box::new(not_found);
+        }
+
+        let stale = self.stale_apis(&msg);
+        let request_id = msg.request_id().unwrap_or("-");
+
+        if stale.is_empty() {
+            self.stats.hits += 1;
+            debug!(
+                request_id = msg.request_id(),
+                city = msg.city();
+                "[{}] Serving {} from the cache",
+                request_id,
+                msg
+            );
+
+            let entry_fut = future::ok(self.cached_aggregate(&msg));
+            Box::new(wrap_future(entry_fut))
+        } else {
+            self.stats.misses += 1;
+            debug!(
+                request_id = msg.request_id(),
+                city = msg.city();
+                "[{}] Fetching {} from {} weather APIs",
+                request_id,
+                msg,
+                stale.len()
+            );
+
+            self.fetch(msg, stale, None)
+        }
+    }
+}
+
+impl Handler<StreamForecast> for Aggregator {
+    type Result = MessageResult<StreamForecast>;
+
+    fn handle(&mut self, msg: StreamForecast, ctx: &mut Self::Context) -> Self::Result {
+        let StreamForecast(query) = msg;
+        let (sender, receiver) = mpsc::unbounded();
+
+        if self.known_missing(&query) {
+            self.stats.hits += 1;
+
+            let not_found = Err(Error::from(UnknownLocation(query)));
+            let _ = sender.unbounded_send(ForecastEvent::Done(not_found));
+            return MessageResult(receiver);
+        }
+
+        let stale = self.stale_apis(&query);
+
+        for idx in self.enabled_apis().into_iter().filter(|idx| !stale.contains(idx)) {
+            let _ = sender.unbounded_send(self.provider_event(&query, idx));
+        }
+
+        if stale.is_empty() {
+            self.stats.hits += 1;
+
+            let _ = sender.unbounded_send(ForecastEvent::Done(Ok(self.cached_aggregate(&query))));
+        } else {
+            self.stats.misses += 1;
+
+            let fetch = self
+                .fetch(query, stale, Some(sender.clone()))
+                .then(move |res, _actor, _ctx| {
+                    let _ = sender.unbounded_send(ForecastEvent::Done(res));
+                    fut::ok(())
+                });
+            ctx.spawn(fetch);
+        }
+
+        MessageResult(receiver)
+    }
+}
+
+impl Handler<Subscribe> for Aggregator {
+    type Result = MessageResult<Subscribe>;
+
+    fn handle(&mut self, msg: Subscribe, ctx: &mut Self::Context) -> Self::Result {
+        let Subscribe { query, subscriber } = msg;
+
+        let id = self.next_subscriber;
+        self.next_subscriber += 1;
+
+        let stale = self.stale_apis(&query);
+        if !stale.is_empty() && !self.known_missing(&query) {
+            debug!("Fetching {} for a new subscriber", query);
+
+            ctx.spawn(self.fetch(query.clone(), stale, None).map(|_, _, _| ()).map_err(
+                |err, _, _| {
+                    warn!("Fetch for a new subscriber failed: {}", err);
+                },
+            ));
+        }
+
+        let forecast = self.cached_aggregate(&query);
+        self.subscribers.insert(id, (query, subscriber));
+
+        MessageResult((id, forecast))
+    }
+}
+
+impl Handler<Unsubscribe> for Aggregator {
+    type Result = ();
+
+    fn handle(&mut self, msg: Unsubscribe, _ctx: &mut Self::Context) -> Self::Result {
+        let Unsubscribe(id) = msg;
+
+        self.subscribers.remove(&id);
+    }
+}
+
+impl Handler<ListCache> for Aggregator {
+    type Result = MessageResult<ListCache>;
+
+    fn handle(&mut self, _msg: ListCache, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.cache.keys().map(|query| self.key_info(query)).collect())
+    }
 }
 
-unsafe impl Sync for Aggregator {}
+impl Handler<PurgeCache> for Aggregator {
+    type Result = MessageResult<PurgeCache>;
 
-impl Aggregator {
-    pub fn new() -> Self {
-        Self {
-            weather_apis: SmallVec::new(),
-            cache: HashMap::new(),
-        }
+    fn handle(&mut self, msg: PurgeCache, _ctx: &mut Self::Context) -> Self::Result {
+        let PurgeCache(query) = msg;
+
+        MessageResult(self.purge(query.as_ref()))
     }
+}
 
-    pub fn add_api(mut self, api: Recipient<WeatherQuery>) -> Self {
-        self.weather_apis.push(api);
+impl Handler<RefreshCache> for Aggregator {
+    type Result = ResponseActFuture<Self, CacheKeyInfo, Error>;
 
-        self
+    fn handle(&mut self, msg: RefreshCache, _ctx: &mut Self::Context) -> Self::Result {
+        let RefreshCache(query) = msg;
+        let apis = self.enabled_apis();
+
+        let refresh = self
+            .fetch(query.clone(), apis, None)
+            .map(move |_, actor, _ctx| actor.key_info(&query));
+
+        Box::new(refresh)
     }
+}
 
-    fn aggregate(mut weather_data: WeatherDataVec) -> WeatherDataVec {
-        weather_data.sort_unstable_by(|entry1, entry2| entry1.date.cmp(&entry2.date));
+impl Handler<GetCacheStats> for Aggregator {
+    type Result = MessageResult<GetCacheStats>;
 
-        weather_data
-            .iter()
-            .group_by(|entry| entry.date)
+    fn handle(&mut self, _msg: GetCacheStats, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(CacheStats {
+            size: self.cache.len(),
+            ..self.stats.clone()
+        })
+    }
+}
+
+impl Handler<GetProviderForecasts> for Aggregator {
+    type Result = MessageResult<GetProviderForecasts>;
+
+    fn handle(&mut self, msg: GetProviderForecasts, _ctx: &mut Self::Context) -> Self::Result {
+        let GetProviderForecasts(query) = msg;
+
+        let forecasts = self
+            .cache
+            .get(&query)
+            .into_iter()
+            .flat_map(|entries| entries.iter())
+            .sorted_by_key(|(idx, _)| **idx)
+            .into_iter()
+            .map(|(idx, entry)| ProviderForecast {
+                name: self.weather_apis[*idx].0.clone(),
+                data: entry.data.clone(),
+                fetched_at: entry.fetched_at,
+            }).collect();
+
+        MessageResult(forecasts)
+    }
+}
+
+impl Handler<GetHourlyForecast> for Aggregator {
+    type Result = MessageResult<GetHourlyForecast>;
+
+    fn handle(&mut self, msg: GetHourlyForecast, _ctx: &mut Self::Context) -> Self::Result {
+        let GetHourlyForecast(query) = msg;
+
+        let hourly = self
+            .enabled_entries(&query)
+            .flat_map(|(idx, entry)| {
+                let weight = self.weights[*idx];
+                entry.hourly.iter().map(move |data| (weight, data.clone()))
+            }).collect();
+
+        MessageResult(Self::aggregate_hourly(self.aggregation, hourly))
+    }
+}
+
+impl Handler<GetProviderHealth> for Aggregator {
+    type Result = MessageResult<GetProviderHealth>;
+
+    fn handle(&mut self, _msg: GetProviderHealth, _ctx: &mut Self::Context) -> Self::Result {
+        let health = self
+            .enabled_apis()
+            .into_iter()
+            .map(|idx| self.health[idx].clone())
+            .collect();
+
+        MessageResult(health)
+    }
+}
+
+impl Handler<ListProviders> for Aggregator {
+    type Result = MessageResult<ListProviders>;
+
+    fn handle(&mut self, _msg: ListProviders, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.providers())
+    }
+}
+
+impl Handler<Reconfigure> for Aggregator {
+    type Result = MessageResult<Reconfigure>;
+
+    fn handle(&mut self, msg: Reconfigure, ctx: &mut Self::Context) -> Self::Result {
+        let Reconfigure {
+            apis,
+            aggregation,
+            watchlist,
+            not_found_ttl,
+            prefetch_spacing,
+            prefetch_interval,
+        } = msg;
+
+        // API, отключённые на ходу, остаются отключёнными.
+        let previous = self.providers();
+        let apis: Vec<_> = apis
             .into_iter()
-            .map(|(day, data)| {
-                let (temperature_sum, points_count) = data
-                    .fold((0.0, 0.0), |(sum, count), data| {
-                        (sum + data.temperature, count + 1.0)
-                    });
+            .map(|(name, api, weight)| {
+                let enabled = self.api_index(&name).is_none_or(|idx| self.enabled[idx]);
+                (name, api, weight, enabled)
+            }).collect();
 
-                let avg_temperature = temperature_sum / points_count;
+        self.replace_apis(apis);
+        self.aggregation = aggregation;
+        self.watchlist = watchlist;
+        self.not_found_ttl = not_found_ttl;
+        self.prefetch_spacing = prefetch_spacing;
+        if self.prefetch_interval != prefetch_interval {
+            self.prefetch_interval = prefetch_interval;
+            self.run_prefetch_timer(ctx);
+        }
+
+        let names: Vec<&str> = self.weather_apis.iter().map(|(name, _)| name.as_str()).collect();
+        let details = format!("configuration reloaded, weather APIs: {}", names.join(", "));
+        self.audit("reload", None, details, None);
 
-                WeatherData {
-                    date: day,
-                    temperature: avg_temperature,
+        for old in &previous {
+            match self.api_index(&old.name) {
+                None => {
+                    let details = format!("removed {} on reload", old.name);
+                    self.audit("remove", Some(&old.name), details, None);
                 }
-            }).collect::<WeatherDataVec>()
+                Some(idx) if self.weights[idx] != old.weight => {
+                    let details = format!(
+                        "changed weight of {} from {} to {} on reload",
+                        old.name, old.weight, self.weights[idx]
+                    );
+                    self.audit("weight", Some(&old.name), details, None);
+                }
+                Some(_) => {}
+            }
+        }
+        for new in self.providers() {
+            if previous.iter().all(|old| old.name != new.name) {
+                let details = format!("added {} with weight {} on reload", new.name, new.weight);
+                self.audit("add", Some(&new.name), details, None);
+            }
+        }
+
+        MessageResult(self.providers())
     }
+}
 
-    fn duration_til_next_midnight(&mut self) -> time::Duration {
-        let now = Utc::now();
-        let next_midnignt = (now + Duration::days(1)).date().and_hms(0, 0, 0);
+impl Handler<AddApi> for Aggregator {
+    type Result = Result<ProviderInfo, Error>;
 
-        next_midnignt.signed_duration_since(now).to_std().unwrap()
+    fn handle(&mut self, msg: AddApi, _ctx: &mut Self::Context) -> Self::Result {
+        let AddApi {
+            name,
+            api,
+            weight,
+            enabled,
+            request_id,
+        } = msg;
+
+        if self.api_index(&name).is_some() {
+            return Err(Error::from(ProviderExists(name)));
+        }
+
+        self.weather_apis.push((name.clone(), api));
+        self.weights.push(weight);
+        self.enabled.push(enabled);
+        self.health.push(ProviderHealth::new(&name));
+        self.pending.push(0);
+
+        let details = if enabled {
+            format!("added {} with weight {}", name, weight)
+        } else {
+            format!("added disabled {} with weight {}", name, weight)
+        };
+        self.audit("add", Some(&name), details, request_id);
+
+        Ok(self.provider(self.weather_apis.len() - 1))
     }
 }
 
-#[derive(Message)]
-struct CacheCleanup;
+impl Handler<RemoveApi> for Aggregator {
+    type Result = Result<ProviderInfo, Error>;
 
-impl Actor for Aggregator {
-    type Context = Context<Self>;
+    fn handle(&mut self, msg: RemoveApi, _ctx: &mut Self::Context) -> Self::Result {
+        let RemoveApi { name, request_id } = msg;
+        let removed = self.provider(self.known_api(&name)?);
 
-    fn started(&mut self, ctx: &mut Self::Context) {
-        let at_midnight = self.duration_til_next_midnight();
-        ctx.notify_later(CacheCleanup, at_midnight);
+        let remaining: Vec<_> = self
+            .weather_apis
+            .iter()
+            .zip(self.weights.iter().zip(self.enabled.iter()))
+            .filter(|((api, _), _)| *api != name)
+            .map(|((api, recipient), (weight, enabled))| {
+                (api.clone(), recipient.clone(), *weight, *enabled)
+            }).collect();
+        self.replace_apis(remaining);
+
+        let details = format!("removed {}", name);
+        self.audit("remove", Some(&name), details, request_id);
+
+        Ok(removed)
     }
 }
 
-impl Handler<CacheCleanup> for Aggregator {
-    type Result = ();
+impl Handler<SetApiEnabled> for Aggregator {
+    type Result = Result<ProviderInfo, Error>;
 
-    fn handle(&mut self, _msg: CacheCleanup, ctx: &mut Self::Context) -> Self::Result {
-        self.cache.clear();
-        self.cache.shrink_to_fit();
+    fn handle(&mut self, msg: SetApiEnabled, _ctx: &mut Self::Context) -> Self::Result {
+        let SetApiEnabled {
+            name,
+            enabled,
+            request_id,
+        } = msg;
+        let idx = self.known_api(&name)?;
 
-        let at_midnight = self.duration_til_next_midnight();
-        ctx.notify_later(CacheCleanup, at_midnight);
+        self.enabled[idx] = enabled;
+
+        let (action, details) = if enabled {
+            ("enable", format!("enabled {}", name))
+        } else {
+            ("disable", format!("disabled {}", name))
+        };
+        self.audit(action, Some(&name), details, request_id);
+
+        Ok(self.provider(idx))
     }
 }
 
-impl Handler<WeatherQuery> for Aggregator {
-    type Result = ResponseActFuture<Self, WeatherDataVec, Error>;
+impl Handler<SetApiWeight> for Aggregator {
+    type Result = Result<ProviderInfo, Error>;
 
-    fn handle(&mut self, msg: WeatherQuery, _ctx: &mut Self::Context) -> Self::Result {
-        match self.cache.get(&msg) {
-            Some(entry) => {
-                let entry_fut = future::ok((*entry).clone());
-                Box::new(wrap_future(entry_fut))
-            }
-            None => {
-                let requests = self.weather_apis.iter().map(|api| api.send(msg.clone()));
+    fn handle(&mut self, msg: SetApiWeight, _ctx: &mut Self::Context) -> Self::Result {
+        let SetApiWeight {
+            name,
+            weight,
+            request_id,
+        } = msg;
+        let idx = self.known_api(&name)?;
 
-                let aggregated_data = stream::futures_unordered(requests)
-                    .collect()
-                    .map(|results| {
-                        let all_data_iter = results
-                            .into_iter()
-                            .filter(|result| result.is_ok())
-                            .map(|result| result.unwrap().into_iter());
+        let previous = self.weights[idx];
+        self.weights[idx] = weight;
 
-                        let all_data = flatten(all_data_iter).collect();
+        let details = format!("changed weight of {} from {} to {}", name, previous, weight);
+        self.audit("weight", Some(&name), details, request_id);
 
-                        Self::aggregate(all_data)
-                    }).map_err(|err| Error::from(err));
+        Ok(self.provider(idx))
+    }
+}
 
-                let msg = msg.clone();
-                let update_self =
-                    wrap_future::<_, Self>(aggregated_data).map(move |result, actor, _ctx| {
-                        actor.cache.insert(msg, result.clone());
-                        result
-                    });
+impl Handler<GetAuditLog> for Aggregator {
+    type Result = MessageResult<GetAuditLog>;
 
-                Box::new(update_self)
-            }
-        }
+    fn handle(&mut self, _msg: GetAuditLog, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.audit_log.iter().cloned().collect())
+    }
+}
+
+impl Handler<GetPendingRequests> for Aggregator {
+    type Result = MessageResult<GetPendingRequests>;
+
+    fn handle(&mut self, _msg: GetPendingRequests, _ctx: &mut Self::Context) -> Self::Result {
+        let pending = self
+            .weather_apis
+            .iter()
+            .zip(self.pending.iter())
+            .map(|((name, _), pending)| (name.clone(), *pending))
+            .collect();
+
+        MessageResult(pending)
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Instant;
 
     use super::*;
     use chrono::{Duration, Utc};
+    use failure::err_msg;
+    use futures::Stream;
+    use tokio::timer::Delay;
+
+    /// Тестовый API, считающий обращения к себе. Первые `failures`
+    /// запросов завершаются ошибкой.
+    struct CountingWeatherActor {
+        calls: Arc<AtomicUsize>,
+        failures: usize,
+    }
+
+    impl Actor for CountingWeatherActor {
+        type Context = SyncContext<Self>;
+    }
+
+    impl Handler<WeatherQuery> for CountingWeatherActor {
+        type Result = Result<WeatherReport, Error>;
+
+        fn handle(&mut self, _msg: WeatherQuery, _ctx: &mut Self::Context) -> Self::Result {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+
+            if call < self.failures {
+                return Err(err_msg("test"));
+            }
+
+            let data: WeatherDataVec = smallvec![WeatherData {
+                date: Utc::now().naive_utc().date(),
+                temperature: 10.0,
+            }];
+
+            Ok(data.into())
+        }
+    }
 
     #[test]
     fn aggregates_results() {
         let now = Utc::now().naive_utc().date();
         let tomorrow = now + Duration::days(2);
 
-        let results = smallvec![
+        let results: WeatherDataVec = smallvec![
             WeatherData {
                 date: now,
                 temperature: 1.0,
@@ -156,10 +1626,682 @@ mod test {
             }
         ];
 
-        let aggregated = Aggregator::aggregate(results);
+        let aggregated = Aggregator::aggregate(
+            Aggregation::Mean,
+            results.iter().cloned().map(|data| (1.0, data)).collect(),
+        );
 
         assert_eq!(aggregated.len(), 2);
         assert_eq!(aggregated[0].temperature, 1.5);
         assert_eq!(aggregated[1].temperature, 8.0);
+
+        let weights = [1.0, 3.0, 1.0, 0.0];
+        let weighted = results.iter().cloned().zip(weights.iter()).map(|(data, weight)| (*weight, data));
+        let aggregated = Aggregator::aggregate(Aggregation::WeightedMean, weighted.collect());
+
+        assert_eq!(aggregated[0].temperature, 1.75);
+        assert_eq!(aggregated[1].temperature, 6.0);
+
+        let mut median = results.clone();
+        median.push(WeatherData {
+            date: tomorrow,
+            temperature: 30.0,
+        });
+        let aggregated = Aggregator::aggregate(
+            Aggregation::Median,
+            median.into_iter().map(|data| (1.0, data)).collect(),
+        );
+
+        assert_eq!(aggregated[0].temperature, 1.5);
+        assert_eq!(aggregated[1].temperature, 10.0);
+
+        // NaN от API не роняет агрегатор, а уходит в конец.
+        let median =
+            Aggregation::Median.combine(smallvec![(1.0, 1.0), (f32::NAN, 1.0), (2.0, 1.0)]);
+        assert_eq!(median, 2.0);
+    }
+
+    /// Тестовый API, не знающий ни одного города.
+    struct UnknownLocationActor {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Actor for UnknownLocationActor {
+        type Context = SyncContext<Self>;
+    }
+
+    impl Handler<WeatherQuery> for UnknownLocationActor {
+        type Result = Result<WeatherReport, Error>;
+
+        fn handle(&mut self, _msg: WeatherQuery, _ctx: &mut Self::Context) -> Self::Result {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+
+            Ok(WeatherDataVec::new().into())
+        }
+    }
+
+    #[test]
+    fn refetches_only_failed_apis() {
+        let mut sys = System::new("test");
+
+        let reliable_calls = Arc::new(AtomicUsize::new(0));
+        let flaky_calls = Arc::new(AtomicUsize::new(0));
+
+        let reliable = {
+            let calls = reliable_calls.clone();
+            SyncArbiter::start(1, move || CountingWeatherActor {
+                calls: calls.clone(),
+                failures: 0,
+            })
+        };
+        let flaky = {
+            let calls = flaky_calls.clone();
+            SyncArbiter::start(1, move || CountingWeatherActor {
+                calls: calls.clone(),
+                failures: 1,
+            })
+        };
+
+        let aggregator = Aggregator::new()
+            .add_api("reliable", reliable.recipient())
+            .add_api("flaky", flaky.recipient())
+            .start();
+
+        let query = WeatherQuery::new("UK".to_string(), "London".to_string());
+
+        let first = sys
+            .block_on(aggregator.send(ForecastQuery(query.clone())))
+            .expect("Aggregator is unavailable")
+            .expect("Aggregator failed");
+        assert_eq!(first.data.len(), 1);
+        assert_eq!(reliable_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(flaky_calls.load(Ordering::SeqCst), 1);
+
+        let health = sys
+            .block_on(aggregator.send(GetProviderHealth))
+            .expect("Aggregator is unavailable");
+        assert!(health[0].healthy);
+        assert!(!health[1].healthy);
+        assert_eq!(health[1].consecutive_failures, 1);
+        assert_eq!(health[0].error_rate, 0.0);
+        assert_eq!(health[1].error_rate, 1.0);
+        assert!(health[1].latency_ms.is_some());
+
+        let second = sys
+            .block_on(aggregator.send(ForecastQuery(query.clone())))
+            .expect("Aggregator is unavailable")
+            .expect("Aggregator failed");
+        assert_eq!(second.data.len(), 1);
+        assert_eq!(reliable_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(flaky_calls.load(Ordering::SeqCst), 2);
+
+        let health = sys
+            .block_on(aggregator.send(GetProviderHealth))
+            .expect("Aggregator is unavailable");
+        assert!(health.iter().all(|provider| provider.healthy));
+        assert_eq!(health[1].error_rate, 0.5);
+
+        let forecasts = sys
+            .block_on(aggregator.send(GetProviderForecasts(query.clone())))
+            .expect("Aggregator is unavailable");
+        let names: Vec<_> = forecasts.iter().map(|forecast| forecast.name.as_str()).collect();
+        assert_eq!(names, ["reliable", "flaky"]);
+
+        sys.block_on(aggregator.send(ForecastQuery(query)))
+            .expect("Aggregator is unavailable")
+            .expect("Aggregator failed");
+        assert_eq!(reliable_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(flaky_calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn does_not_blame_rate_limited_apis() {
+        let mut sys = System::new("test");
+
+        struct LimitedWeatherActor;
+
+        impl Actor for LimitedWeatherActor {
+            type Context = SyncContext<Self>;
+        }
+
+        impl Handler<WeatherQuery> for LimitedWeatherActor {
+            type Result = Result<WeatherReport, Error>;
+
+            fn handle(&mut self, _msg: WeatherQuery, _ctx: &mut Self::Context) -> Self::Result {
+                Err(Error::from(RateLimited("limited", 1)))
+            }
+        }
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let reliable = SyncArbiter::start(1, move || CountingWeatherActor {
+            calls: calls.clone(),
+            failures: 0,
+        });
+        let aggregator = Aggregator::new()
+            .add_api("reliable", reliable.recipient())
+            .add_api("limited", SyncArbiter::start(1, || LimitedWeatherActor).recipient())
+            .start();
+
+        let query = WeatherQuery::new("UK".to_string(), "London".to_string());
+        sys.block_on(aggregator.send(ForecastQuery(query)))
+            .expect("Aggregator is unavailable")
+            .expect("Aggregator failed");
+
+        let health = sys
+            .block_on(aggregator.send(GetProviderHealth))
+            .expect("Aggregator is unavailable");
+        assert!(health[1].healthy);
+        assert_eq!(health[1].consecutive_failures, 0);
+        assert!(health[1].last_error.is_none());
+    }
+
+    #[test]
+    fn streams_provider_results() {
+        let mut sys = System::new("test");
+
+        let reliable_calls = Arc::new(AtomicUsize::new(0));
+        let reliable = {
+            let calls = reliable_calls.clone();
+            SyncArbiter::start(1, move || CountingWeatherActor {
+                calls: calls.clone(),
+                failures: 0,
+            })
+        };
+        let flaky = SyncArbiter::start(1, move || CountingWeatherActor {
+            calls: Arc::new(AtomicUsize::new(0)),
+            failures: 1,
+        });
+
+        let aggregator = Aggregator::new()
+            .add_api("reliable", reliable.recipient())
+            .add_api("flaky", flaky.recipient())
+            .start();
+
+        let query = WeatherQuery::new("UK".to_string(), "London".to_string());
+
+        let events = sys
+            .block_on(aggregator.send(StreamForecast(query.clone())))
+            .expect("Aggregator is unavailable");
+        let events = sys.block_on(events.collect()).expect("Stream failed");
+        assert_eq!(events.len(), 3);
+
+        for event in &events[..2] {
+            match event {
+                ForecastEvent::Provider { name, data, .. } if name == "reliable" => {
+                    assert_eq!(data.as_ref().map(|data| data.len()), Some(1))
+                }
+                ForecastEvent::Provider { name, data, .. } if name == "flaky" => {
+                    assert!(data.is_none())
+                }
+                _ => panic!("Unexpected event {:?}", event),
+            }
+        }
+        match events[2] {
+            ForecastEvent::Done(Ok(ref forecast)) => assert_eq!(forecast.sources, ["reliable"]),
+            ref event => panic!("Unexpected event {:?}", event),
+        }
+
+        // Результат надёжного API берётся из кэша и отдаётся первым.
+        let events = sys
+            .block_on(aggregator.send(StreamForecast(query)))
+            .expect("Aggregator is unavailable");
+        let events = sys.block_on(events.collect()).expect("Stream failed");
+        assert_eq!(events.len(), 3);
+        assert_eq!(reliable_calls.load(Ordering::SeqCst), 1);
+
+        match events[0] {
+            ForecastEvent::Provider { ref name, .. } => assert_eq!(name, "reliable"),
+            ref event => panic!("Unexpected event {:?}", event),
+        }
+        match events[1] {
+            ForecastEvent::Provider { ref aggregate, .. } => {
+                assert_eq!(aggregate.sources, ["flaky", "reliable"])
+            }
+            ref event => panic!("Unexpected event {:?}", event),
+        }
+        match events[2] {
+            ForecastEvent::Done(Ok(_)) => {}
+            ref event => panic!("Unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn computes_daily_changes() {
+        let today = Utc::now().naive_utc().date();
+        let tomorrow = today + Duration::days(1);
+        let day_after = today + Duration::days(2);
+
+        let previous = [
+            WeatherData {
+                date: today,
+                temperature: 10.0,
+            },
+            WeatherData {
+                date: tomorrow,
+                temperature: 12.0,
+            },
+        ];
+        let current = [
+            WeatherData {
+                date: tomorrow,
+                temperature: 12.0,
+            },
+            WeatherData {
+                date: day_after,
+                temperature: 14.0,
+            },
+        ];
+
+        assert_eq!(
+            Aggregator::changes(&previous, &current),
+            [
+                DayChange {
+                    date: today,
+                    previous: Some(10.0),
+                    current: None,
+                },
+                DayChange {
+                    date: day_after,
+                    previous: None,
+                    current: Some(14.0),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn prefetches_watched_queries() {
+        let mut sys = System::new("test");
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let api = {
+            let calls = calls.clone();
+            SyncArbiter::start(1, move || CountingWeatherActor {
+                calls: calls.clone(),
+                failures: 0,
+            })
+        };
+
+        let query = WeatherQuery::new("UK".to_string(), "London".to_string());
+
+        let aggregator = Aggregator::new()
+            .add_api("test", api.recipient())
+            .watch(query.clone())
+            .prefetch_interval(time::Duration::from_millis(300))
+            .start();
+
+        let wait = Delay::new(Instant::now() + time::Duration::from_millis(100));
+        sys.block_on(wait).expect("Timer failed");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        sys.block_on(aggregator.send(ForecastQuery(query.clone())))
+            .expect("Aggregator is unavailable")
+            .expect("Aggregator failed");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Удалённый из кэша результат загружается снова по расписанию,
+        // а свежий повторно не запрашивается.
+        sys.block_on(aggregator.send(PurgeCache(Some(query))))
+            .expect("Aggregator is unavailable");
+        let wait = Delay::new(Instant::now() + time::Duration::from_millis(700));
+        sys.block_on(wait).expect("Timer failed");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn administers_cache() {
+        let mut sys = System::new("test");
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let api = {
+            let calls = calls.clone();
+            SyncArbiter::start(1, move || CountingWeatherActor {
+                calls: calls.clone(),
+                failures: 0,
+            })
+        };
+
+        let aggregator = Aggregator::new().add_api("test", api.recipient()).start();
+        let query = WeatherQuery::new("UK".to_string(), "London".to_string());
+
+        for _ in 0..2 {
+            sys.block_on(aggregator.send(ForecastQuery(query.clone())))
+                .expect("Aggregator is unavailable")
+                .expect("Aggregator failed");
+        }
+
+        let keys = sys
+            .block_on(aggregator.send(ListCache))
+            .expect("Aggregator is unavailable");
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].query, query);
+        assert_eq!(keys[0].providers, 1);
+        assert_eq!(keys[0].size, 1);
+
+        let info = sys
+            .block_on(aggregator.send(RefreshCache(query.clone())))
+            .expect("Aggregator is unavailable")
+            .expect("Aggregator failed");
+        assert_eq!(info.providers, 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let purged = sys
+            .block_on(aggregator.send(PurgeCache(Some(query))))
+            .expect("Aggregator is unavailable");
+        assert_eq!(purged, 1);
+
+        let stats = sys
+            .block_on(aggregator.send(GetCacheStats))
+            .expect("Aggregator is unavailable");
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.size, 0);
+    }
+
+    #[test]
+    fn does_not_remember_unknown_locations_while_apis_fail() {
+        let mut sys = System::new("test");
+
+        let unknown_calls = Arc::new(AtomicUsize::new(0));
+        let unknown = {
+            let calls = unknown_calls.clone();
+            SyncArbiter::start(1, move || UnknownLocationActor {
+                calls: calls.clone(),
+            })
+        };
+        let failing_calls = Arc::new(AtomicUsize::new(0));
+        let failing = {
+            let calls = failing_calls.clone();
+            SyncArbiter::start(1, move || CountingWeatherActor {
+                calls: calls.clone(),
+                failures: usize::MAX,
+            })
+        };
+
+        let aggregator = Aggregator::new()
+            .add_api("unknown", unknown.recipient())
+            .add_api("failing", failing.recipient())
+            .start();
+        let query = WeatherQuery::new("UK".to_string(), "Londn".to_string());
+
+        for _ in 0..2 {
+            let forecast = sys
+                .block_on(aggregator.send(ForecastQuery(query.clone())))
+                .expect("Aggregator is unavailable")
+                .expect("Location is reported unknown while an API fails");
+            assert!(forecast.data.is_empty());
+        }
+        assert_eq!(unknown_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(failing_calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn remembers_unknown_locations() {
+        let mut sys = System::new("test");
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let api = {
+            let calls = calls.clone();
+            SyncArbiter::start(1, move || UnknownLocationActor {
+                calls: calls.clone(),
+            })
+        };
+
+        let aggregator = Aggregator::new().add_api("test", api.recipient()).start();
+        let query = WeatherQuery::new("UK".to_string(), "Londn".to_string());
+
+        for _ in 0..2 {
+            let err = sys
+                .block_on(aggregator.send(ForecastQuery(query.clone())))
+                .expect("Aggregator is unavailable")
+                .expect_err("Unknown location is not reported");
+            assert!(err.downcast_ref::<UnknownLocation>().is_some());
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let keys = sys
+            .block_on(aggregator.send(ListCache))
+            .expect("Aggregator is unavailable");
+        assert!(keys.is_empty());
+
+        sys.block_on(aggregator.send(PurgeCache(Some(query.clone()))))
+            .expect("Aggregator is unavailable");
+        sys.block_on(aggregator.send(ForecastQuery(query)))
+            .expect("Aggregator is unavailable")
+            .expect_err("Unknown location is not reported");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    /// Тестовый API, отвечающий с задержкой.
+    struct SlowWeatherActor;
+
+    impl Actor for SlowWeatherActor {
+        type Context = SyncContext<Self>;
+    }
+
+    impl Handler<WeatherQuery> for SlowWeatherActor {
+        type Result = Result<WeatherReport, Error>;
+
+        fn handle(&mut self, _msg: WeatherQuery, _ctx: &mut Self::Context) -> Self::Result {
+            thread::sleep(time::Duration::from_millis(100));
+
+            let data: WeatherDataVec = smallvec![WeatherData {
+                date: Utc::now().naive_utc().date(),
+                temperature: 10.0,
+            }];
+
+            Ok(data.into())
+        }
+    }
+
+    #[test]
+    fn replaces_apis_keeping_cache() {
+        let mut sys = System::new("test");
+
+        let counting = |calls: &Arc<AtomicUsize>| {
+            let calls = calls.clone();
+            SyncArbiter::start(1, move || CountingWeatherActor {
+                calls: calls.clone(),
+                failures: 0,
+            }).recipient()
+        };
+        let reconfigure = |apis| Reconfigure {
+            apis,
+            aggregation: Aggregation::Mean,
+            watchlist: Vec::new(),
+            not_found_ttl: Duration::minutes(5),
+            prefetch_spacing: time::Duration::from_secs(0),
+            prefetch_interval: PREFETCH_INTERVAL,
+        };
+
+        let old_calls = Arc::new(AtomicUsize::new(0));
+        let aggregator = Aggregator::new()
+            .add_api("kept", counting(&old_calls))
+            .add_api("removed", counting(&old_calls))
+            .add_api("slow", SyncArbiter::start(1, || SlowWeatherActor).recipient())
+            .start();
+
+        let london = WeatherQuery::new("UK".to_string(), "London".to_string());
+        sys.block_on(aggregator.send(ForecastQuery(london.clone())))
+            .expect("Aggregator is unavailable")
+            .expect("Aggregator failed");
+        assert_eq!(old_calls.load(Ordering::SeqCst), 2);
+
+        // Запрос к медленному API ещё выполняется, когда API заменяют.
+        let paris = WeatherQuery::new("FR".to_string(), "Paris".to_string());
+        let in_flight = aggregator.send(ForecastQuery(paris.clone()));
+
+        let new_calls = Arc::new(AtomicUsize::new(0));
+        let providers = sys
+            .block_on(aggregator.send(reconfigure(vec![
+                ("added".to_string(), counting(&new_calls), 2.0),
+                ("kept".to_string(), counting(&new_calls), 1.0),
+                (
+                    "slow".to_string(),
+                    SyncArbiter::start(1, || SlowWeatherActor).recipient(),
+                    1.0,
+                ),
+            ]))).expect("Aggregator is unavailable");
+        let names: Vec<_> = providers.iter().map(|provider| provider.name.as_str()).collect();
+        assert_eq!(names, ["added", "kept", "slow"]);
+
+        let forecast = sys
+            .block_on(in_flight)
+            .expect("Aggregator is unavailable")
+            .expect("Aggregator failed");
+        assert!(forecast.sources.contains(&"slow".to_string()));
+
+        let forecast = sys
+            .block_on(aggregator.send(ForecastQuery(london)))
+            .expect("Aggregator is unavailable")
+            .expect("Aggregator failed");
+        assert_eq!(forecast.sources, ["added", "kept", "slow"]);
+        // Результат оставшегося API взят из кэша, спрошен только новый.
+        assert_eq!(new_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(old_calls.load(Ordering::SeqCst), 4);
+
+        let stats = sys
+            .block_on(aggregator.send(GetCacheStats))
+            .expect("Aggregator is unavailable");
+        assert_eq!(stats.evictions, 1);
+    }
+
+    #[test]
+    fn manages_apis_at_runtime() {
+        let mut sys = System::new("test");
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counting = |calls: &Arc<AtomicUsize>| {
+            let calls = calls.clone();
+            SyncArbiter::start(1, move || CountingWeatherActor {
+                calls: calls.clone(),
+                failures: 0,
+            }).recipient()
+        };
+
+        let aggregator = Aggregator::new()
+            .add_api("first", counting(&calls))
+            .add_api("second", counting(&calls))
+            .start();
+        let query = WeatherQuery::new("UK".to_string(), "London".to_string());
+
+        sys.block_on(aggregator.send(ForecastQuery(query.clone())))
+            .expect("Aggregator is unavailable")
+            .expect("Aggregator failed");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let disabled = sys
+            .block_on(aggregator.send(SetApiEnabled {
+                name: "second".to_string(),
+                enabled: false,
+                request_id: Some("incident".to_string()),
+            })).expect("Aggregator is unavailable")
+            .expect("Failed to disable API");
+        assert!(!disabled.enabled);
+
+        let forecast = sys
+            .block_on(aggregator.send(ForecastQuery(query.clone())))
+            .expect("Aggregator is unavailable")
+            .expect("Aggregator failed");
+        assert_eq!(forecast.sources, ["first"]);
+
+        let weighted = sys
+            .block_on(aggregator.send(SetApiWeight {
+                name: "first".to_string(),
+                weight: 2.0,
+                request_id: None,
+            })).expect("Aggregator is unavailable")
+            .expect("Failed to change weight");
+        assert_eq!(weighted.weight, 2.0);
+
+        let err = sys
+            .block_on(aggregator.send(RemoveApi {
+                name: "third".to_string(),
+                request_id: None,
+            })).expect("Aggregator is unavailable")
+            .expect_err("Unknown API is removed");
+        assert!(err.downcast_ref::<UnknownProvider>().is_some());
+
+        sys.block_on(aggregator.send(RemoveApi {
+            name: "first".to_string(),
+            request_id: None,
+        })).expect("Aggregator is unavailable")
+        .expect("Failed to remove API");
+        sys.block_on(aggregator.send(AddApi {
+            name: "third".to_string(),
+            api: counting(&calls),
+            weight: 1.0,
+            enabled: true,
+            request_id: None,
+        })).expect("Aggregator is unavailable")
+        .expect("Failed to add API");
+
+        let err = sys
+            .block_on(aggregator.send(AddApi {
+                name: "third".to_string(),
+                api: counting(&calls),
+                weight: 1.0,
+                enabled: true,
+                request_id: None,
+            })).expect("Aggregator is unavailable")
+            .expect_err("API is added twice");
+        assert!(err.downcast_ref::<ProviderExists>().is_some());
+
+        let providers = sys
+            .block_on(aggregator.send(ListProviders))
+            .expect("Aggregator is unavailable");
+        let names: Vec<_> = providers.iter().map(|provider| provider.name.as_str()).collect();
+        assert_eq!(names, ["second", "third"]);
+
+        // Результат отключённого API остался в кэше, спрошен только новый.
+        let forecast = sys
+            .block_on(aggregator.send(ForecastQuery(query)))
+            .expect("Aggregator is unavailable")
+            .expect("Aggregator failed");
+        assert_eq!(forecast.sources, ["third"]);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let audit = sys
+            .block_on(aggregator.send(GetAuditLog))
+            .expect("Aggregator is unavailable");
+        let actions: Vec<_> = audit.iter().map(|entry| entry.action.as_str()).collect();
+        assert_eq!(actions, ["disable", "weight", "remove", "add"]);
+        assert_eq!(audit[0].provider.as_deref(), Some("second"));
+        assert_eq!(audit[0].request_id.as_deref(), Some("incident"));
+        assert_eq!(audit[1].details, "changed weight of first from 1 to 2");
+
+        // Перезагрузка не включает отключённый API и пишет в журнал,
+        // что изменилось.
+        let providers = sys
+            .block_on(aggregator.send(Reconfigure {
+                apis: vec![
+                    ("first".to_string(), counting(&calls), 1.0),
+                    ("second".to_string(), counting(&calls), 3.0),
+                ],
+                aggregation: Aggregation::Mean,
+                watchlist: Vec::new(),
+                not_found_ttl: Duration::minutes(5),
+                prefetch_spacing: time::Duration::from_secs(0),
+                prefetch_interval: PREFETCH_INTERVAL,
+            })).expect("Aggregator is unavailable");
+        assert_eq!(providers[0].name, "first");
+        assert!(providers[0].enabled);
+        assert_eq!(providers[1].name, "second");
+        assert!(!providers[1].enabled);
+
+        let audit = sys
+            .block_on(aggregator.send(GetAuditLog))
+            .expect("Aggregator is unavailable");
+        let details: Vec<_> = audit[4..].iter().map(|entry| entry.details.as_str()).collect();
+        assert_eq!(
+            details,
+            [
+                "configuration reloaded, weather APIs: first, second",
+                "changed weight of second from 1 to 3 on reload",
+                "removed third on reload",
+                "added first with weight 1 on reload",
+            ]
+        );
     }
 }
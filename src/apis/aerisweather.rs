@@ -1,38 +1,36 @@
-use std::env;
+use std::convert::TryFrom;
 
-use chrono::{TimeZone, Utc};
+use chrono::{DateTime, FixedOffset, TimeZone, Utc};
 use failure::Error;
-use reqwest::Url;
+use reqwest::{StatusCode, Url};
 
-use apis::{WeatherAPI, WeatherData, WeatherDataVec, WeatherQuery};
+use apis::{WeatherAPI, WeatherData, WeatherDataVec, WeatherQuery, WeatherReport};
 
 /// https://www.aerisweather.com/support/docs/api/reference/endpoints/forecasts/
 pub struct AerisWeather {
     client_id: String,
     client_secret: String,
+    base_url: String,
 }
 
 impl AerisWeather {
-    pub fn new() -> Result<Self, Error> {
-        let client_id = env::var("AERISWEATHER_CLIENT_ID")?;
-        let client_secret = env::var("AERISWEATHER_CLIENT_SECRET")?;
-
-        Ok(Self {
+    pub fn new(client_id: String, client_secret: String, base_url: String) -> Self {
+        Self {
             client_id,
             client_secret,
-        })
+            base_url,
+        }
     }
 }
 
 impl WeatherAPI for AerisWeather {
+    const NAME: &'static str = "aerisweather";
+    const BASE_URL: &'static str = "https://api.aerisapi.com/forecasts";
     type Response = AerisWeatherResponse;
 
     fn make_url(&self, query: &WeatherQuery) -> Result<Url, Error> {
         Ok(Url::parse_with_params(
-            &format!(
-                "https://api.aerisapi.com/forecasts/{},{}",
-                query.city, query.country
-            ),
+            &format!("{}/{},{}", self.base_url, query.city, query.country),
             &[
                 ("limit", "5"),
                 ("filter", "precise"),
@@ -41,12 +39,23 @@ impl WeatherAPI for AerisWeather {
             ],
         )?)
     }
+
+    /// AerisWeather отвечает на неизвестный город кодом `200` с ошибкой
+    /// в теле, поэтому он разбирается в `AerisWeatherResponse`.
+    fn is_not_found(_status: StatusCode) -> bool {
+        false
+    }
 }
 
+/// Код ошибки, с которым AerisWeather отвечает на неизвестный город.
+const INVALID_LOCATION: &str = "invalid_location";
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct AerisWeatherPeriod {
     timestamp: i64,
+    #[serde(rename = "dateTimeISO")]
+    date_time_iso: Option<String>,
     avg_temp_c: f32,
 }
 
@@ -56,23 +65,80 @@ struct AerisWeatherForecast {
 }
 
 #[derive(Deserialize)]
-pub struct AerisWeatherResponse {
+struct AerisWeatherError {
+    code: String,
+    description: String,
+}
+
+/// Ответ AerisWeather как есть: при ошибке вместо прогноза приходит
+/// её описание.
+#[derive(Deserialize)]
+struct AerisWeatherReply {
     success: bool,
-    response: [AerisWeatherForecast; 1],
+    error: Option<AerisWeatherError>,
+    #[serde(default)]
+    response: Vec<AerisWeatherForecast>,
+}
+
+/// Прогноз AerisWeather. Если такого города AerisWeather не знает или
+/// у него нет данных, прогноза нет; любая другая ошибка в ответе
+/// считается ошибкой разбора.
+#[derive(Deserialize)]
+#[serde(try_from = "AerisWeatherReply")]
+pub struct AerisWeatherResponse {
+    forecast: Option<AerisWeatherForecast>,
+}
+
+impl TryFrom<AerisWeatherReply> for AerisWeatherResponse {
+    type Error = String;
+
+    fn try_from(reply: AerisWeatherReply) -> Result<Self, Self::Error> {
+        if reply.success {
+            return Ok(Self {
+                forecast: reply.response.into_iter().next(),
+            });
+        }
+
+        match reply.error {
+            Some(ref error) if error.code == INVALID_LOCATION => Ok(Self { forecast: None }),
+            Some(error) => Err(format!("{}: {}", error.code, error.description)),
+            None => Err("request failed without an error description".to_string()),
+        }
+    }
+}
+
+impl AerisWeatherResponse {
+    /// Смещение берётся из местного времени начала первого периода.
+    fn utc_offset(&self) -> Option<FixedOffset> {
+        self.forecast.as_ref()?.periods[0]
+            .date_time_iso
+            .as_ref()
+            .and_then(|date_time| DateTime::parse_from_rfc3339(date_time).ok())
+            .map(|date_time| *date_time.offset())
+    }
 }
 
 impl Into<WeatherDataVec> for AerisWeatherResponse {
     fn into(self) -> WeatherDataVec {
-        if self.success {
-            self.response[0]
+        match self.forecast {
+            Some(forecast) => forecast
                 .periods
                 .iter()
                 .map(|forecast| WeatherData {
                     date: Utc.timestamp(forecast.timestamp, 0).naive_utc().date(),
                     temperature: forecast.avg_temp_c,
-                }).collect::<WeatherDataVec>()
-        } else {
-            WeatherDataVec::new()
+                }).collect::<WeatherDataVec>(),
+            None => WeatherDataVec::new(),
+        }
+    }
+}
+
+impl Into<WeatherReport> for AerisWeatherResponse {
+    fn into(self) -> WeatherReport {
+        WeatherReport {
+            utc_offset: self.utc_offset(),
+            hourly: Vec::new(),
+            data: self.into(),
         }
     }
 }
@@ -94,6 +160,7 @@ mod test {
                     "periods": [
                         {
                             "timestamp": now.timestamp(),
+                            "dateTimeISO": "2018-10-02T07:00:00-04:00",
                             "avgTempC": 10.0
                         },
                         {
@@ -120,12 +187,34 @@ mod test {
         let response: AerisWeatherResponse =
             serde_json::from_value(test_json).expect("Failed to parse test JSON");
 
-        assert!(response.success);
-        assert_eq!(response.response[0].periods[0].avg_temp_c, 10.0);
-        assert_eq!(response.response[0].periods[4].avg_temp_c, 10.0);
+        assert_eq!(response.utc_offset(), Some(FixedOffset::west(4 * 3600)));
+        let forecast = response.forecast.expect("Forecast is missing");
+        assert_eq!(forecast.periods[0].avg_temp_c, 10.0);
+        assert_eq!(forecast.periods[4].avg_temp_c, 10.0);
         assert_eq!(
-            response.response[0].periods[2].timestamp,
+            forecast.periods[2].timestamp,
             (now + Duration::days(2)).timestamp()
         );
     }
+
+    #[test]
+    fn parses_errors() {
+        let error = |code: &str| {
+            json!({
+                "success": false,
+                "error": {
+                    "code": code,
+                    "description": "test"
+                },
+                "response": []
+            })
+        };
+
+        let response: AerisWeatherResponse = serde_json::from_value(error(INVALID_LOCATION))
+            .expect("Failed to parse unknown location");
+        assert!(Into::<WeatherDataVec>::into(response).is_empty());
+
+        let response = serde_json::from_value::<AerisWeatherResponse>(error("invalid_client"));
+        assert!(response.is_err());
+    }
 }
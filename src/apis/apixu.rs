@@ -1,35 +1,41 @@
-use std::env;
-
-use chrono::{TimeZone, Utc};
+use chrono::{FixedOffset, NaiveDateTime, TimeZone, Utc};
 use failure::Error;
-use reqwest::Url;
+use reqwest::{StatusCode, Url};
 
-use apis::{WeatherAPI, WeatherData, WeatherDataVec, WeatherQuery};
+use apis::{HourlyData, WeatherAPI, WeatherData, WeatherDataVec, WeatherQuery, WeatherReport};
 
 /// https://www.apixu.com/doc/forecast.aspx
 pub struct Apixu {
     key: String,
+    base_url: String,
 }
 
 const MAX_DAYS: &str = "7";
 
 impl Apixu {
-    pub fn new() -> Result<Self, Error> {
-        let key = env::var("APIXU_API_KEY")?;
-
-        Ok(Self { key })
+    pub fn new(key: String, base_url: String) -> Self {
+        Self { key, base_url }
     }
 }
 
 impl WeatherAPI for Apixu {
+    const NAME: &'static str = "apixu";
+    const BASE_URL: &'static str = "https://api.apixu.com/v1/forecast.json";
     type Response = ApixuResponse;
 
     fn make_url(&self, query: &WeatherQuery) -> Result<Url, Error> {
         Ok(Url::parse_with_params(
-            "https://api.apixu.com/v1/forecast.json",
+            &self.base_url,
             &[("days", MAX_DAYS), ("q", &query.city), ("key", &self.key)],
         )?)
     }
+
+    /// Apixu отвечает на неизвестный город кодом `400` (ошибка `1006`).
+    /// Остальные ошибки с этим кодом касаются параметров запроса, а их
+    /// `make_url` задаёт всегда.
+    fn is_not_found(status: StatusCode) -> bool {
+        status == StatusCode::BAD_REQUEST
+    }
 }
 
 #[derive(Deserialize)]
@@ -37,10 +43,18 @@ struct ApixuDayStats {
     avgtemp_c: f32,
 }
 
+#[derive(Deserialize)]
+struct ApixuHour {
+    time_epoch: i64,
+    temp_c: f32,
+}
+
 #[derive(Deserialize)]
 struct ApixuForecastDay {
     date_epoch: i64,
     day: ApixuDayStats,
+    #[serde(default)]
+    hour: Vec<ApixuHour>,
 }
 
 #[derive(Deserialize)]
@@ -48,11 +62,47 @@ struct ApixuForecast {
     forecastday: [ApixuForecastDay; 7],
 }
 
+/// Текущее местное время в городе.
+#[derive(Deserialize)]
+struct ApixuLocation {
+    localtime_epoch: i64,
+    localtime: String,
+}
+
 #[derive(Deserialize)]
 pub struct ApixuResponse {
+    location: Option<ApixuLocation>,
     forecast: ApixuForecast,
 }
 
+impl ApixuResponse {
+    /// Apixu не отдаёт смещение явно, поэтому оно вычисляется как разница
+    /// между местным временем и UTC, округлённая до четверти часа.
+    fn utc_offset(&self) -> Option<FixedOffset> {
+        let location = self.location.as_ref()?;
+        let localtime =
+            NaiveDateTime::parse_from_str(&location.localtime, "%Y-%m-%d %H:%M").ok()?;
+
+        let offset = localtime.timestamp() - location.localtime_epoch;
+        let quarters = (offset as f64 / 900.0).round() as i32;
+
+        FixedOffset::east_opt(quarters * 900)
+    }
+
+    fn hourly(&self) -> Vec<HourlyData> {
+        self.forecast
+            .forecastday
+            .iter()
+            .flat_map(|forecast| forecast.hour.iter())
+            .filter_map(|hour| {
+                Utc.timestamp_opt(hour.time_epoch, 0).single().map(|time| HourlyData {
+                    time,
+                    temperature: hour.temp_c,
+                })
+            }).collect()
+    }
+}
+
 impl Into<WeatherDataVec> for ApixuResponse {
     fn into(self) -> WeatherDataVec {
         self.forecast
@@ -65,6 +115,16 @@ impl Into<WeatherDataVec> for ApixuResponse {
     }
 }
 
+impl Into<WeatherReport> for ApixuResponse {
+    fn into(self) -> WeatherReport {
+        WeatherReport {
+            utc_offset: self.utc_offset(),
+            hourly: self.hourly(),
+            data: self.into(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use chrono::Duration;
@@ -77,6 +137,10 @@ mod test {
         let now = Utc::now();
 
         let test_json = json!({
+            "location": {
+                "localtime_epoch": 1538487900,
+                "localtime": "2018-10-02 16:45"
+            },
             "forecast": {
                 "forecastday": [
                     {
@@ -130,5 +194,6 @@ mod test {
 
         assert_eq!(response.forecast.forecastday[0].day.avgtemp_c, 10.0);
         assert_eq!(response.forecast.forecastday[6].day.avgtemp_c, 10.0);
+        assert_eq!(response.utc_offset(), Some(FixedOffset::east(3 * 3600)));
     }
 }
@@ -8,10 +8,14 @@ pub use self::apixu::Apixu;
 pub use self::openweathermap::OpenWeatherMap;
 pub use self::weatherbit::WeatherBit;
 
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
 use actix::Message;
-use chrono::NaiveDate;
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
 use failure::Error;
-use reqwest::{Method, Url};
+use reqwest::{Method, StatusCode, Url};
 use smallvec::SmallVec;
 
 /// Прогноз на определенную дату.
@@ -23,28 +27,126 @@ pub struct WeatherData {
 
 pub type WeatherDataVec = SmallVec<[WeatherData; 32]>;
 
+/// Прогноз на определённый час.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HourlyData {
+    pub temperature: f32,
+    pub time: DateTime<Utc>,
+}
+
+/// Ответ погодного API: прогноз по дням, почасовой прогноз, если API
+/// его даёт, и смещение местного времени города относительно UTC,
+/// если API его сообщает.
+#[derive(Debug, Clone)]
+pub struct WeatherReport {
+    pub data: WeatherDataVec,
+    pub hourly: Vec<HourlyData>,
+    pub utc_offset: Option<FixedOffset>,
+}
+
+impl From<WeatherDataVec> for WeatherReport {
+    fn from(data: WeatherDataVec) -> Self {
+        Self {
+            data,
+            hourly: Vec::new(),
+            utc_offset: None,
+        }
+    }
+}
+
 /// Запрос всей имеющейся информации по городу в некой стране.
-#[derive(Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
+/// Идентификатор HTTP-запроса, ради которого он выполняется, нужен
+/// только для логов и трассировки: в сравнении запросов (а значит,
+/// и в ключах кэша) и в сериализации он не участвует.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WeatherQuery {
     country: String,
     city: String,
+    #[serde(skip)]
+    request_id: Option<String>,
 }
 
 impl WeatherQuery {
     pub fn new(country: String, city: String) -> Self {
-        Self { country, city }
+        Self {
+            country,
+            city,
+            request_id: None,
+        }
+    }
+
+    pub fn with_request_id(mut self, request_id: String) -> Self {
+        self.request_id = Some(request_id);
+
+        self
+    }
+
+    /// Идентификатор запроса, если запрос пришёл через `WebAPI`.
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
+    pub fn country(&self) -> &str {
+        &self.country
+    }
+
+    pub fn city(&self) -> &str {
+        &self.city
+    }
+}
+
+/// Запрос в виде `страна/город`, например `UK/London`.
+impl FromStr for WeatherQuery {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().splitn(2, '/').collect::<SmallVec<[&str; 2]>>().as_slice() {
+            [country, city] if !country.is_empty() && !city.is_empty() => {
+                Ok(Self::new(country.to_string(), city.to_string()))
+            }
+            _ => Err(format_err!("expected `country/city`, got `{}`", s)),
+        }
+    }
+}
+
+impl PartialEq for WeatherQuery {
+    fn eq(&self, other: &Self) -> bool {
+        self.country == other.country && self.city == other.city
+    }
+}
+
+impl Eq for WeatherQuery {}
+
+impl Hash for WeatherQuery {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.country.hash(state);
+        self.city.hash(state);
+    }
+}
+
+impl fmt::Display for WeatherQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.country, self.city)
     }
 }
 
 impl Message for WeatherQuery {
-    type Result = Result<WeatherDataVec, Error>;
+    type Result = Result<WeatherReport, Error>;
 }
 
 /// Типаж, позволяющий создавать запросы к некому погодному API.
-/// Запрос должен конвертироваться в `WeatherDataVec`.
+/// Ответ должен конвертироваться в `WeatherReport`.
 pub trait WeatherAPI {
+    const NAME: &'static str;
+    /// Адрес, по которому API отвечает, если в настройках не указан другой.
+    const BASE_URL: &'static str;
     const METHOD: Method = Method::GET;
-    type Response: Into<WeatherDataVec>;
+    type Response: Into<WeatherReport>;
 
     fn make_url(&self, query: &WeatherQuery) -> Result<Url, Error>;
+
+    /// Означает ли код ответа `status`, что API не знает такого города.
+    /// Такой ответ превращается в пустой прогноз, а остальные ответы
+    /// с кодом ошибки считаются ошибкой API.
+    fn is_not_found(status: StatusCode) -> bool;
 }
@@ -1,31 +1,30 @@
-use std::env;
-
-use chrono::{TimeZone, Utc};
+use chrono::{FixedOffset, TimeZone, Utc};
 use failure::Error;
 use itertools::Itertools;
-use reqwest::Url;
+use reqwest::{StatusCode, Url};
 
-use apis::{WeatherAPI, WeatherData, WeatherDataVec, WeatherQuery};
+use apis::{HourlyData, WeatherAPI, WeatherData, WeatherDataVec, WeatherQuery, WeatherReport};
 
 /// https://openweathermap.org/forecast5
 pub struct OpenWeatherMap {
     app_id: String,
+    base_url: String,
 }
 
 impl OpenWeatherMap {
-    pub fn new() -> Result<Self, Error> {
-        let app_id = env::var("OPENWEATHERMAP_API_KEY")?;
-
-        Ok(Self { app_id })
+    pub fn new(app_id: String, base_url: String) -> Self {
+        Self { app_id, base_url }
     }
 }
 
 impl WeatherAPI for OpenWeatherMap {
+    const NAME: &'static str = "openweathermap";
+    const BASE_URL: &'static str = "https://api.openweathermap.org/data/2.5/forecast";
     type Response = OWMResponse;
 
     fn make_url(&self, query: &WeatherQuery) -> Result<Url, Error> {
         Ok(Url::parse_with_params(
-            "https://api.openweathermap.org/data/2.5/forecast",
+            &self.base_url,
             &[
                 ("units", "metric"),
                 ("q", &query.city),
@@ -33,6 +32,11 @@ impl WeatherAPI for OpenWeatherMap {
             ],
         )?)
     }
+
+    /// OpenWeatherMap отвечает на неизвестный город кодом `404`.
+    fn is_not_found(status: StatusCode) -> bool {
+        status == StatusCode::NOT_FOUND
+    }
 }
 
 #[derive(Deserialize)]
@@ -46,18 +50,48 @@ struct OWMDataEntry {
     main: OWMMainSection,
 }
 
+/// Смещение местного времени города относительно UTC в секундах.
+#[derive(Deserialize)]
+struct OWMCity {
+    timezone: i32,
+}
+
 #[derive(Deserialize)]
 pub struct OWMResponse {
     list: Vec<OWMDataEntry>,
+    city: Option<OWMCity>,
+}
+
+impl OWMResponse {
+    fn utc_offset(&self) -> Option<FixedOffset> {
+        self.city
+            .as_ref()
+            .and_then(|city| FixedOffset::east_opt(city.timezone))
+    }
+
+    /// Записи через каждые 3 часа как есть.
+    fn hourly(&self) -> Vec<HourlyData> {
+        self.list
+            .iter()
+            .filter_map(|entry| {
+                Utc.timestamp_opt(entry.dt, 0).single().map(|time| HourlyData {
+                    time,
+                    temperature: entry.main.temp,
+                })
+            }).collect()
+    }
 }
 
 impl Into<WeatherDataVec> for OWMResponse {
     fn into(self) -> WeatherDataVec {
+        let offset = self.utc_offset().unwrap_or_else(|| FixedOffset::east(0));
+
         self.list
             .iter()
             // Здесь нужно нормализовать по дате, потому что OpenWeatherMap
             // возращает по несколько записей на один день - через каждые 3 часа.
-            .group_by(|entry| Utc.timestamp(entry.dt, 0).date())
+            // Дни считаются по местному времени города.
+            .group_by(|entry| offset.timestamp(entry.dt, 0).date())
             .into_iter()
             .map(|(day, data)| {
                 let (temperature_sum, points_count) = data
@@ -68,13 +102,23 @@ impl Into<WeatherDataVec> for OWMResponse {
                 let avg_temperature = temperature_sum / points_count;
 
                 WeatherData {
-                    date: day.naive_utc(),
+                    date: day.naive_local(),
                     temperature: avg_temperature,
                 }
             }).collect::<WeatherDataVec>()
     }
 }
 
+impl Into<WeatherReport> for OWMResponse {
+    fn into(self) -> WeatherReport {
+        WeatherReport {
+            utc_offset: self.utc_offset(),
+            hourly: self.hourly(),
+            data: self.into(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
 
@@ -96,7 +140,10 @@ mod test {
             });
         }
 
-        OWMResponse { list: entries }
+        OWMResponse {
+            list: entries,
+            city: None,
+        }
     }
 
     #[test]
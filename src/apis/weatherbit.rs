@@ -1,29 +1,28 @@
-use std::env;
-
 use chrono::{TimeZone, Utc};
 use failure::Error;
-use reqwest::Url;
+use reqwest::{StatusCode, Url};
 
-use apis::{WeatherAPI, WeatherData, WeatherDataVec, WeatherQuery};
+use apis::{WeatherAPI, WeatherData, WeatherDataVec, WeatherQuery, WeatherReport};
 
 /// https://www.weatherbit.io/api/weather-forecast-16-day
 pub struct WeatherBit {
     key: String,
+    base_url: String,
 }
 
 impl WeatherBit {
-    pub fn new() -> Result<Self, Error> {
-        let key = env::var("WEATHERBIT_API_KEY")?;
-
-        Ok(Self { key })
+    pub fn new(key: String, base_url: String) -> Self {
+        Self { key, base_url }
     }
 }
 
 impl WeatherAPI for WeatherBit {
+    const NAME: &'static str = "weatherbit";
+    const BASE_URL: &'static str = "https://api.weatherbit.io/v2.0/forecast/daily";
     type Response = WeatherBitResponse;
     fn make_url(&self, query: &WeatherQuery) -> Result<Url, Error> {
         Ok(Url::parse_with_params(
-            "https://api.weatherbit.io/v2.0/forecast/daily",
+            &self.base_url,
             &[
                 ("key", &self.key),
                 ("city", &query.city),
@@ -31,6 +30,11 @@ impl WeatherAPI for WeatherBit {
             ],
         )?)
     }
+
+    /// Weatherbit отвечает на неизвестный город кодом `204` без тела.
+    fn is_not_found(status: StatusCode) -> bool {
+        status == StatusCode::NO_CONTENT
+    }
 }
 
 #[derive(Deserialize, Serialize)]
@@ -55,6 +59,13 @@ impl Into<WeatherDataVec> for WeatherBitResponse {
     }
 }
 
+/// WeatherBit сообщает только название часового пояса, без смещения.
+impl Into<WeatherReport> for WeatherBitResponse {
+    fn into(self) -> WeatherReport {
+        WeatherReport::from(Into::<WeatherDataVec>::into(self))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use chrono::{Datelike, Duration};
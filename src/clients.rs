@@ -0,0 +1,222 @@
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+use std::time::{Duration, Instant};
+
+use actix::prelude::*;
+
+/// Окно, в котором считаются запросы клиента для ограничения частоты.
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Ключ клиента API. `rate_limit` - сколько запросов в минуту можно
+/// выполнить с этим ключом; если не задан, запросы не ограничиваются.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClientKey {
+    pub name: String,
+    pub key: String,
+    pub rate_limit: Option<u32>,
+}
+
+/// Причина, по которой запрос клиента отклонён.
+#[derive(Debug, PartialEq)]
+pub enum Rejection {
+    /// Ключ не передан или неизвестен.
+    InvalidKey,
+    /// Лимит запросов исчерпан; следующий запрос уложится в него через
+    /// указанное время.
+    RateLimited(Duration),
+}
+
+/// Проверка ключа, переданного клиентом. Если запрос укладывается в лимит
+/// ключа, он учитывается, и в ответ приходит имя клиента. Пока ни одного
+/// ключа не задано, пропускается любой запрос, а имени нет.
+pub struct CheckClient(pub Option<String>);
+
+impl Message for CheckClient {
+    type Result = Result<Option<String>, Rejection>;
+}
+
+/// Замена набора ключей, например при перезагрузке конфигурации. Запросы,
+/// уже выполненные с оставшимися ключами, продолжают учитываться в лимитах.
+pub struct SetClientKeys(pub Vec<ClientKey>);
+
+impl Message for SetClientKeys {
+    type Result = ();
+}
+
+/// Клиент и время его последних запросов.
+struct Client {
+    name: String,
+    rate_limit: Option<u32>,
+    calls: VecDeque<Instant>,
+}
+
+impl Client {
+    /// Учитывает запрос, если он укладывается в `rate_limit`, или отвечает,
+    /// через сколько это станет возможно.
+    fn acquire(&mut self, now: Instant) -> Result<(), Duration> {
+        let limit = match self.rate_limit {
+            Some(limit) => limit as usize,
+            None => return Ok(()),
+        };
+
+        while self
+            .calls
+            .front()
+            .is_some_and(|call| now.duration_since(*call) >= RATE_LIMIT_WINDOW)
+        {
+            self.calls.pop_front();
+        }
+
+        if self.calls.len() >= limit {
+            let oldest = self.calls[self.calls.len() - limit];
+            return Err(RATE_LIMIT_WINDOW - now.duration_since(oldest));
+        }
+
+        self.calls.push_back(now);
+        Ok(())
+    }
+}
+
+/// Актор, проверяющий ключи клиентов и ограничивающий частоту их запросов.
+/// Один на все рабочие потоки сервера, чтобы лимиты были общими.
+pub struct Clients {
+    clients: HashMap<String, Client>,
+}
+
+impl Clients {
+    pub fn new(keys: Vec<ClientKey>) -> Self {
+        let mut clients = Self {
+            clients: HashMap::new(),
+        };
+        clients.set_keys(keys);
+
+        clients
+    }
+
+    fn set_keys(&mut self, keys: Vec<ClientKey>) {
+        let mut previous = mem::take(&mut self.clients);
+
+        self.clients = keys
+            .into_iter()
+            .map(|key| {
+                let calls = previous
+                    .remove(&key.key)
+                    .map(|client| client.calls)
+                    .unwrap_or_default();
+                let client = Client {
+                    name: key.name,
+                    rate_limit: key.rate_limit,
+                    calls,
+                };
+
+                (key.key, client)
+            })
+            .collect();
+    }
+}
+
+impl Actor for Clients {
+    type Context = Context<Self>;
+}
+
+impl Handler<CheckClient> for Clients {
+    type Result = Result<Option<String>, Rejection>;
+
+    fn handle(&mut self, msg: CheckClient, _ctx: &mut Self::Context) -> Self::Result {
+        if self.clients.is_empty() {
+            return Ok(None);
+        }
+
+        let client = msg
+            .0
+            .and_then(|key| self.clients.get_mut(&key))
+            .ok_or(Rejection::InvalidKey)?;
+
+        match client.acquire(Instant::now()) {
+            Ok(()) => Ok(Some(client.name.clone())),
+            Err(retry_after) => {
+                debug!(
+                    client = client.name.as_str();
+                    "Client {} is over its rate limit",
+                    client.name
+                );
+                Err(Rejection::RateLimited(retry_after))
+            }
+        }
+    }
+}
+
+impl Handler<SetClientKeys> for Clients {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetClientKeys, _ctx: &mut Self::Context) -> Self::Result {
+        info!("Using {} client API keys", msg.0.len());
+
+        self.set_keys(msg.0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(name: &str, key: &str, rate_limit: Option<u32>) -> ClientKey {
+        ClientKey {
+            name: name.to_string(),
+            key: key.to_string(),
+            rate_limit,
+        }
+    }
+
+    #[test]
+    fn limits_requests_per_key() {
+        let mut sys = System::new("test");
+
+        let clients = Clients::new(vec![]).start();
+        let check = |key: Option<&str>| clients.send(CheckClient(key.map(str::to_string)));
+
+        let open = sys.block_on(check(None)).unwrap();
+        assert_eq!(open, Ok(None));
+
+        clients.do_send(SetClientKeys(vec![
+            key("mobile", "mobile-key", Some(2)),
+            key("internal", "internal-key", None),
+        ]));
+
+        assert_eq!(
+            sys.block_on(check(None)).unwrap(),
+            Err(Rejection::InvalidKey)
+        );
+        assert_eq!(
+            sys.block_on(check(Some("wrong"))).unwrap(),
+            Err(Rejection::InvalidKey)
+        );
+
+        for _ in 0..2 {
+            let name = sys.block_on(check(Some("mobile-key"))).unwrap();
+            assert_eq!(name, Ok(Some("mobile".to_string())));
+        }
+        match sys.block_on(check(Some("mobile-key"))).unwrap() {
+            Err(Rejection::RateLimited(retry_after)) => {
+                assert!(retry_after > Duration::from_secs(59));
+                assert!(retry_after <= RATE_LIMIT_WINDOW);
+            }
+            other => panic!("Expected rate limit, got {:?}", other),
+        }
+
+        for _ in 0..5 {
+            let name = sys.block_on(check(Some("internal-key"))).unwrap();
+            assert_eq!(name, Ok(Some("internal".to_string())));
+        }
+
+        // Новый лимит учитывает запросы, сделанные до замены ключей.
+        clients.do_send(SetClientKeys(vec![key("mobile", "mobile-key", Some(3))]));
+        let name = sys.block_on(check(Some("mobile-key"))).unwrap();
+        assert_eq!(name, Ok(Some("mobile".to_string())));
+        assert!(sys.block_on(check(Some("mobile-key"))).unwrap().is_err());
+        assert_eq!(
+            sys.block_on(check(Some("internal-key"))).unwrap(),
+            Err(Rejection::InvalidKey)
+        );
+    }
+}
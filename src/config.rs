@@ -0,0 +1,829 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fs;
+use std::net::ToSocketAddrs;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono;
+use failure::Error;
+use reqwest::Url;
+
+use aggregator::Aggregation;
+use apis::{AerisWeather, Apixu, OpenWeatherMap, WeatherAPI, WeatherBit, WeatherQuery};
+use clients::ClientKey;
+use logging::LogFormat;
+
+/// Файл настроек, который читается, если `CONFIG_FILE` не задан.
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+/// Погодные API, которые умеет опрашивать сервис.
+pub const PROVIDERS: [&str; 4] = [
+    AerisWeather::NAME,
+    Apixu::NAME,
+    OpenWeatherMap::NAME,
+    WeatherBit::NAME,
+];
+
+/// Настройки сервиса. Читаются из файла TOML, после чего переменные
+/// окружения переопределяют отдельные значения (см. `apply_env`).
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub address: String,
+    pub admin_token: Option<String>,
+    pub min_healthy_providers: usize,
+    pub log_format: LogFormat,
+    pub otlp_endpoint: Option<String>,
+    pub shutdown_timeout: u16,
+    pub cache: CacheConfig,
+    pub aggregation: AggregationConfig,
+    pub clients: ClientsConfig,
+    pub providers: BTreeMap<String, ProviderConfig>,
+}
+
+/// Настройки кэша агрегатора. Времена указываются в секундах.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub not_found_ttl: u64,
+    pub prefetch_spacing: u64,
+    pub prefetch_interval: u64,
+    pub prefetch_locations: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AggregationConfig {
+    pub strategy: Aggregation,
+}
+
+/// Ключи клиентов API: таблица `keys` (имя клиента - ключ и лимит)
+/// и файл `keys_file`, в каждой строке которого имя, ключ и, если нужно,
+/// лимит через пробел. `rate_limit` - лимит в запросах в минуту для ключей,
+/// у которых нет своего. Если ключей нет, сервис доступен без ключа.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientsConfig {
+    pub rate_limit: Option<u32>,
+    pub keys_file: Option<String>,
+    pub keys: BTreeMap<String, ClientKeyConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientKeyConfig {
+    pub key: String,
+    #[serde(default)]
+    pub rate_limit: Option<u32>,
+}
+
+/// Настройки одного погодного API. Aeris Weather требует `client_id`
+/// и `client_secret`, остальные - `api_key`. `timeout` указывается
+/// в секундах, `rate_limit` - в запросах в минуту, `horizon` - в днях.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProviderConfig {
+    pub enabled: bool,
+    pub api_key: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub base_url: Option<String>,
+    pub timeout: Option<u64>,
+    pub weight: f32,
+    pub rate_limit: Option<u32>,
+    pub horizon: Option<u32>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1:8088".to_string(),
+            admin_token: None,
+            min_healthy_providers: 1,
+            log_format: LogFormat::Text,
+            otlp_endpoint: None,
+            shutdown_timeout: 30,
+            cache: CacheConfig::default(),
+            aggregation: AggregationConfig::default(),
+            clients: ClientsConfig::default(),
+            providers: PROVIDERS
+                .iter()
+                .map(|name| (name.to_string(), ProviderConfig::default()))
+                .collect(),
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            not_found_ttl: 300,
+            prefetch_spacing: 10,
+            prefetch_interval: 900,
+            prefetch_locations: Vec::new(),
+        }
+    }
+}
+
+impl Default for ProviderConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            api_key: None,
+            client_id: None,
+            client_secret: None,
+            base_url: None,
+            timeout: None,
+            weight: 1.0,
+            rate_limit: None,
+            horizon: None,
+        }
+    }
+}
+
+/// Поля с ключами доступа, которые нужны API.
+fn credentials(provider: &str) -> &'static [&'static str] {
+    if provider == AerisWeather::NAME {
+        &["client_id", "client_secret"]
+    } else {
+        &["api_key"]
+    }
+}
+
+/// Ошибка разбора переменной окружения `name`.
+fn parse_error<E: Display>(name: &str, err: E) -> Error {
+    format_err!("{}: {}", name, err)
+}
+
+/// Значение переменной окружения, если она задана и не пуста.
+fn non_empty(var: Option<String>) -> Option<String> {
+    var.filter(|value| !value.is_empty())
+}
+
+impl Config {
+    /// Файл настроек: `CONFIG_FILE` или `config.toml` в рабочем каталоге,
+    /// если он есть.
+    pub fn file() -> Option<String> {
+        match std::env::var("CONFIG_FILE") {
+            Ok(path) => Some(path),
+            Err(_) if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Some(DEFAULT_CONFIG_FILE.to_string())
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// Настройки из файла `path` (или настройки по умолчанию, если файла
+    /// нет) с переопределениями из переменных окружения.
+    pub fn load(path: Option<&str>) -> Result<Self, Error> {
+        let mut config = match path {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+
+        config.apply_env(|name| std::env::var(name).ok())?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn from_file(path: &str) -> Result<Self, Error> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format_err!("failed to read config file {}: {}", path, err))?;
+
+        Self::parse(&text).map_err(|err| format_err!("{}: {}", path, err))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        Ok(::toml::from_str(text)?)
+    }
+
+    /// Переопределяет настройки переменными окружения: `ADDRESS`,
+    /// `ADMIN_TOKEN`, `MIN_HEALTHY_PROVIDERS`, `LOG_FORMAT`, `OTLP_ENDPOINT`,
+    /// `SHUTDOWN_TIMEOUT`, `NOT_FOUND_TTL`, `PREFETCH_SPACING`,
+    /// `PREFETCH_INTERVAL`, `PREFETCH_LOCATIONS` (через запятую),
+    /// `AGGREGATION_STRATEGY`, `CLIENT_KEYS_FILE`, `CLIENT_RATE_LIMIT`, а для
+    /// каждого API из настроек - `<API>_API_KEY`, `<API>_CLIENT_ID`
+    /// и `<API>_CLIENT_SECRET`.
+    /// Пустые переменные не учитываются.
+    pub fn apply_env<F>(&mut self, var: F) -> Result<(), Error>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let var = |name: &str| non_empty(var(name));
+
+        if let Some(address) = var("ADDRESS") {
+            self.address = address;
+        }
+        if let Some(token) = var("ADMIN_TOKEN") {
+            self.admin_token = Some(token);
+        }
+        if let Some(count) = var("MIN_HEALTHY_PROVIDERS") {
+            self.min_healthy_providers = count
+                .parse()
+                .map_err(|err| parse_error("MIN_HEALTHY_PROVIDERS", err))?;
+        }
+        if let Some(format) = var("LOG_FORMAT") {
+            self.log_format = format
+                .parse()
+                .map_err(|err| parse_error("LOG_FORMAT", err))?;
+        }
+        if let Some(endpoint) = var("OTLP_ENDPOINT") {
+            self.otlp_endpoint = Some(endpoint);
+        }
+        if let Some(secs) = var("SHUTDOWN_TIMEOUT") {
+            self.shutdown_timeout = secs
+                .parse()
+                .map_err(|err| parse_error("SHUTDOWN_TIMEOUT", err))?;
+        }
+        if let Some(secs) = var("NOT_FOUND_TTL") {
+            self.cache.not_found_ttl = secs
+                .parse()
+                .map_err(|err| parse_error("NOT_FOUND_TTL", err))?;
+        }
+        if let Some(secs) = var("PREFETCH_SPACING") {
+            self.cache.prefetch_spacing = secs
+                .parse()
+                .map_err(|err| parse_error("PREFETCH_SPACING", err))?;
+        }
+        if let Some(secs) = var("PREFETCH_INTERVAL") {
+            self.cache.prefetch_interval = secs
+                .parse()
+                .map_err(|err| parse_error("PREFETCH_INTERVAL", err))?;
+        }
+        if let Some(locations) = var("PREFETCH_LOCATIONS") {
+            self.cache.prefetch_locations = locations
+                .split(',')
+                .map(str::trim)
+                .filter(|location| !location.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        if let Some(strategy) = var("AGGREGATION_STRATEGY") {
+            self.aggregation.strategy = strategy
+                .parse()
+                .map_err(|err| parse_error("AGGREGATION_STRATEGY", err))?;
+        }
+        if let Some(path) = var("CLIENT_KEYS_FILE") {
+            self.clients.keys_file = Some(path);
+        }
+        if let Some(limit) = var("CLIENT_RATE_LIMIT") {
+            self.clients.rate_limit = Some(
+                limit
+                    .parse()
+                    .map_err(|err| parse_error("CLIENT_RATE_LIMIT", err))?,
+            );
+        }
+
+        for (name, provider) in &mut self.providers {
+            let prefix = name.to_uppercase();
+
+            if let Some(key) = var(&format!("{}_API_KEY", prefix)) {
+                provider.api_key = Some(key);
+            }
+            if let Some(id) = var(&format!("{}_CLIENT_ID", prefix)) {
+                provider.client_id = Some(id);
+            }
+            if let Some(secret) = var(&format!("{}_CLIENT_SECRET", prefix)) {
+                provider.client_secret = Some(secret);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Проверяет настройки и сообщает обо всех ошибках сразу, указывая
+    /// для каждой путь к неверному значению.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut errors = Vec::new();
+
+        match self.address.to_socket_addrs() {
+            Ok(_) => {}
+            Err(err) => errors.push(format!(
+                "address: invalid address {:?}: {}",
+                self.address, err
+            )),
+        }
+
+        if self.admin_token.as_ref().is_some_and(String::is_empty) {
+            errors.push("admin_token: must not be empty".to_string());
+        }
+
+        if let Some(ref endpoint) = self.otlp_endpoint {
+            if let Err(err) = Url::parse(endpoint) {
+                errors.push(format!(
+                    "otlp_endpoint: invalid URL {:?}: {}",
+                    endpoint, err
+                ));
+            }
+        }
+
+        if self.cache.prefetch_interval == 0 {
+            errors.push("cache.prefetch_interval: must be at least 1 second".to_string());
+        }
+
+        for (i, location) in self.cache.prefetch_locations.iter().enumerate() {
+            if let Err(err) = location.parse::<WeatherQuery>() {
+                errors.push(format!("cache.prefetch_locations[{}]: {}", i, err));
+            }
+        }
+
+        errors.extend(self.read_client_keys().1);
+
+        for (name, provider) in &self.providers {
+            if !PROVIDERS.contains(&name.as_str()) {
+                errors.push(format!(
+                    "providers.{}: unknown provider, expected one of {}",
+                    name,
+                    PROVIDERS.join(", ")
+                ));
+                continue;
+            }
+
+            if provider.enabled {
+                errors.extend(provider.validate(name));
+            }
+        }
+
+        let active = self.active_providers().count();
+        if active == 0 {
+            let skipped: Vec<String> = self
+                .skipped_providers()
+                .map(|(name, missing)| format!("{} is missing {}", name, missing.join(" and ")))
+                .collect();
+
+            if skipped.is_empty() {
+                errors.push("providers: no provider is enabled".to_string());
+            } else {
+                errors.push(format!(
+                    "providers: no provider has credentials ({}), set them in the config file \
+                     or in environment variables such as WEATHERBIT_API_KEY",
+                    skipped.join(", ")
+                ));
+            }
+        } else if self.min_healthy_providers > active {
+            errors.push(format!(
+                "min_healthy_providers: {} is more than the {} providers with credentials",
+                self.min_healthy_providers, active
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format_err!(
+                "invalid configuration:\n  {}",
+                errors.join("\n  ")
+            ))
+        }
+    }
+
+    /// Включённые погодные API в порядке имён.
+    fn enabled_providers(&self) -> impl Iterator<Item = (&String, &ProviderConfig)> {
+        self.providers
+            .iter()
+            .filter(|(name, provider)| provider.enabled && PROVIDERS.contains(&name.as_str()))
+    }
+
+    /// Включённые погодные API, для которых заданы все ключи, - с ними
+    /// сервис и запускается.
+    pub fn active_providers(&self) -> impl Iterator<Item = (&String, &ProviderConfig)> {
+        self.enabled_providers()
+            .filter(|(name, provider)| provider.missing_credentials(name).is_empty())
+    }
+
+    /// Включённые погодные API, которые пропускаются из-за недостающих
+    /// ключей, вместе с этими ключами.
+    pub fn skipped_providers(&self) -> impl Iterator<Item = (&String, Vec<&'static str>)> {
+        self.enabled_providers()
+            .map(|(name, provider)| (name, provider.missing_credentials(name)))
+            .filter(|(_, missing)| !missing.is_empty())
+    }
+
+    /// Города, кэш по которым нужно прогревать заранее.
+    pub fn watchlist(&self) -> Result<Vec<WeatherQuery>, Error> {
+        self.cache
+            .prefetch_locations
+            .iter()
+            .map(|location| location.parse())
+            .collect()
+    }
+
+    /// Ключи клиентов из таблицы `clients.keys` и файла `clients.keys_file`.
+    pub fn client_keys(&self) -> Result<Vec<ClientKey>, Error> {
+        let (keys, errors) = self.read_client_keys();
+
+        if errors.is_empty() {
+            Ok(keys)
+        } else {
+            Err(format_err!("{}", errors.join("; ")))
+        }
+    }
+
+    /// Ключи клиентов и ошибки в них с путями к неверным значениям.
+    fn read_client_keys(&self) -> (Vec<ClientKey>, Vec<String>) {
+        let mut keys = Vec::new();
+        let mut errors = Vec::new();
+
+        if self.clients.rate_limit == Some(0) {
+            errors.push("clients.rate_limit: must allow at least 1 request per minute".to_string());
+        }
+
+        for (name, client) in &self.clients.keys {
+            keys.push((
+                format!("clients.keys.{}", name),
+                ClientKey {
+                    name: name.clone(),
+                    key: client.key.clone(),
+                    rate_limit: client.rate_limit.or(self.clients.rate_limit),
+                },
+            ));
+        }
+
+        if let Some(ref path) = self.clients.keys_file {
+            match fs::read_to_string(path) {
+                Ok(text) => {
+                    let lines = text
+                        .lines()
+                        .enumerate()
+                        .map(|(i, line)| (i + 1, line.trim()))
+                        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+                    for (number, line) in lines {
+                        let at = format!("clients.keys_file: {} line {}", path, number);
+                        let fields: Vec<&str> = line.split_whitespace().collect();
+
+                        if fields.len() < 2 || fields.len() > 3 {
+                            errors.push(format!(
+                                "{}: expected name, key and optional rate limit",
+                                at
+                            ));
+                            continue;
+                        }
+                        let rate_limit = match fields.get(2).map(|limit| limit.parse()) {
+                            Some(Ok(limit)) => Some(limit),
+                            Some(Err(err)) => {
+                                errors.push(format!("{}: invalid rate limit: {}", at, err));
+                                continue;
+                            }
+                            None => self.clients.rate_limit,
+                        };
+
+                        let key = ClientKey {
+                            name: fields[0].to_string(),
+                            key: fields[1].to_string(),
+                            rate_limit,
+                        };
+                        keys.push((at, key));
+                    }
+                }
+                Err(err) => errors.push(format!(
+                    "clients.keys_file: failed to read {}: {}",
+                    path, err
+                )),
+            }
+        }
+
+        for (i, (at, key)) in keys.iter().enumerate() {
+            if key.key.is_empty() {
+                errors.push(format!("{}: key must not be empty", at));
+            }
+            if key.rate_limit == Some(0) {
+                errors.push(format!(
+                    "{}: rate limit must allow at least 1 request per minute",
+                    at
+                ));
+            }
+            if keys[..i].iter().any(|(_, other)| other.key == key.key) {
+                errors.push(format!("{}: key of {} is already in use", at, key.name));
+            }
+        }
+
+        (keys.into_iter().map(|(_, key)| key).collect(), errors)
+    }
+
+    pub fn not_found_ttl(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.cache.not_found_ttl as i64)
+    }
+
+    pub fn prefetch_spacing(&self) -> Duration {
+        Duration::from_secs(self.cache.prefetch_spacing)
+    }
+
+    pub fn prefetch_interval(&self) -> Duration {
+        Duration::from_secs(self.cache.prefetch_interval)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(u64::from(self.shutdown_timeout))
+    }
+
+    /// Настройки, которые отличаются в `other`, но применяются только
+    /// при запуске сервиса.
+    pub fn restart_required(&self, other: &Config) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+
+        if self.address != other.address {
+            changed.push("address");
+        }
+        if self.admin_token != other.admin_token {
+            changed.push("admin_token");
+        }
+        if self.min_healthy_providers != other.min_healthy_providers {
+            changed.push("min_healthy_providers");
+        }
+        if self.log_format != other.log_format {
+            changed.push("log_format");
+        }
+        if self.otlp_endpoint != other.otlp_endpoint {
+            changed.push("otlp_endpoint");
+        }
+        if self.shutdown_timeout != other.shutdown_timeout {
+            changed.push("shutdown_timeout");
+        }
+
+        changed
+    }
+}
+
+impl ProviderConfig {
+    /// Ключи доступа, которые нужны API `name`, но не заданы или пусты.
+    pub fn missing_credentials(&self, name: &str) -> Vec<&'static str> {
+        credentials(name)
+            .iter()
+            .cloned()
+            .filter(|field| {
+                let value = match *field {
+                    "api_key" => &self.api_key,
+                    "client_id" => &self.client_id,
+                    _ => &self.client_secret,
+                };
+
+                value.as_ref().is_none_or(String::is_empty)
+            }).collect()
+    }
+
+    /// Проверяет настройки API `name`, добавляемого на ходу: имя должно быть
+    /// известно, а ключи - заданы.
+    pub fn check(&self, name: &str) -> Result<(), Error> {
+        if !PROVIDERS.contains(&name) {
+            return Err(format_err!(
+                "providers.{}: unknown provider, expected one of {}",
+                name,
+                PROVIDERS.join(", ")
+            ));
+        }
+
+        let mut errors = self.validate(name);
+        for field in self.missing_credentials(name) {
+            errors.push(format!("providers.{}.{}: missing", name, field));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format_err!(
+                "invalid configuration:\n  {}",
+                errors.join("\n  ")
+            ))
+        }
+    }
+
+    fn validate(&self, name: &str) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if let Some(ref url) = self.base_url {
+            if let Err(err) = Url::parse(url) {
+                errors.push(format!(
+                    "providers.{}.base_url: invalid URL {:?}: {}",
+                    name, url, err
+                ));
+            }
+        }
+
+        if self.timeout == Some(0) {
+            errors.push(format!(
+                "providers.{}.timeout: must be at least 1 second",
+                name
+            ));
+        }
+
+        if !self.weight.is_finite() || self.weight < 0.0 {
+            errors.push(format!(
+                "providers.{}.weight: must be a non-negative number, got {}",
+                name, self.weight
+            ));
+        }
+
+        if self.rate_limit == Some(0) {
+            errors.push(format!(
+                "providers.{}.rate_limit: must allow at least 1 request per minute",
+                name
+            ));
+        }
+
+        if self.horizon == Some(0) {
+            errors.push(format!(
+                "providers.{}.horizon: must be at least 1 day",
+                name
+            ));
+        }
+
+        errors
+    }
+
+    /// Ключ доступа `field`; после `validate` он всегда задан.
+    pub fn credential(&self, field: &str) -> String {
+        let value = match field {
+            "api_key" => &self.api_key,
+            "client_id" => &self.client_id,
+            _ => &self.client_secret,
+        };
+
+        value.clone().unwrap_or_default()
+    }
+
+    pub fn base_url(&self, default: &str) -> String {
+        self.base_url.clone().unwrap_or_else(|| default.to_string())
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout.map(Duration::from_secs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::{env, process};
+
+    use super::*;
+
+    const EXAMPLE: &str = r#"
+address = "0.0.0.0:8000"
+min_healthy_providers = 2
+
+[cache]
+not_found_ttl = 60
+prefetch_locations = ["UK/London", "RU/Moscow"]
+
+[aggregation]
+strategy = "weighted_mean"
+
+[providers.weatherbit]
+api_key = "file-key"
+weight = 2.0
+timeout = 5
+
+[providers.apixu]
+rate_limit = 30
+horizon = 5
+"#;
+
+    #[test]
+    fn loads_file_with_env_overrides() {
+        let mut config = Config::parse(EXAMPLE).expect("Failed to parse config");
+
+        let env: HashMap<&str, &str> = [
+            ("APIXU_API_KEY", "env-key"),
+            ("WEATHERBIT_API_KEY", ""),
+            ("NOT_FOUND_TTL", "120"),
+            ("SHUTDOWN_TIMEOUT", "5"),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+        config
+            .apply_env(|name| env.get(name).map(|value| value.to_string()))
+            .expect("Failed to apply env");
+        config.validate().expect("Invalid config");
+
+        assert_eq!(config.address, "0.0.0.0:8000");
+        assert_eq!(config.aggregation.strategy, Aggregation::WeightedMean);
+        assert_eq!(config.cache.not_found_ttl, 120);
+        assert_eq!(config.cache.prefetch_spacing, 10);
+        assert_eq!(config.shutdown_timeout(), Duration::from_secs(5));
+        assert_eq!(config.watchlist().unwrap().len(), 2);
+
+        let providers: Vec<&String> = config.active_providers().map(|(name, _)| name).collect();
+        assert_eq!(providers, vec!["apixu", "weatherbit"]);
+        assert_eq!(config.providers["apixu"].credential("api_key"), "env-key");
+        assert_eq!(config.providers["apixu"].horizon, Some(5));
+        assert_eq!(
+            config.providers["weatherbit"].credential("api_key"),
+            "file-key"
+        );
+        assert_eq!(config.providers["weatherbit"].weight, 2.0);
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let config = Config::parse(
+            r#"
+min_healthy_providers = 3
+
+[cache]
+prefetch_locations = ["London"]
+
+[providers.apixu]
+api_key = "key"
+weight = -1.0
+timeout = 0
+
+[providers.aerisweather]
+client_id = "id"
+
+[providers.darksky]
+api_key = "key"
+"#,
+        )
+        .expect("Failed to parse config");
+
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("cache.prefetch_locations[0]: expected `country/city`, got `London`"));
+        assert!(err.contains("providers.apixu.weight: must be a non-negative number, got -1"));
+        assert!(err.contains("providers.apixu.timeout: must be at least 1 second"));
+        assert!(err.contains("providers.darksky: unknown provider"));
+        assert!(err.contains(
+            "min_healthy_providers: 3 is more than the 1 providers with credentials"
+        ));
+
+        let err = Config::parse("[providers.apixu]\napi_kye = \"key\"\n").unwrap_err();
+        assert!(err.to_string().contains("unknown field `api_kye`"));
+    }
+
+    #[test]
+    fn skips_providers_without_credentials() {
+        let mut config = Config::parse(
+            r#"
+[providers.aerisweather]
+client_id = "id"
+
+[providers.weatherbit]
+api_key = "key"
+"#,
+        )
+        .expect("Failed to parse config");
+
+        let active: Vec<&String> = config.active_providers().map(|(name, _)| name).collect();
+        assert_eq!(active, vec!["weatherbit"]);
+
+        let skipped: Vec<_> = config.skipped_providers().collect();
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].0, "aerisweather");
+        assert_eq!(skipped[0].1, vec!["client_secret"]);
+        assert!(config.validate().is_ok());
+
+        config.providers.remove("weatherbit");
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("no provider has credentials (aerisweather is missing client_secret)"));
+    }
+
+    #[test]
+    fn loads_client_keys() {
+        let path = env::temp_dir().join(format!("congenial-lamp-keys-{}", process::id()));
+        fs::write(
+            &path,
+            "# name key [rate limit]\npartner partner-key 600\n\nintern intern-key\n",
+        )
+        .expect("Failed to write keys file");
+
+        let mut config = Config::parse(
+            r#"
+[clients]
+rate_limit = 60
+
+[clients.keys.mobile]
+key = "mobile-key"
+rate_limit = 120
+
+[providers.apixu]
+api_key = "key"
+"#,
+        )
+        .expect("Failed to parse config");
+        config.clients.keys_file = Some(path.to_string_lossy().into_owned());
+
+        let keys = config.client_keys().expect("Invalid client keys");
+        let keys: Vec<_> = keys
+            .iter()
+            .map(|key| (key.name.as_str(), key.key.as_str(), key.rate_limit))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                ("mobile", "mobile-key", Some(120)),
+                ("partner", "partner-key", Some(600)),
+                ("intern", "intern-key", Some(60)),
+            ]
+        );
+
+        fs::write(&path, "partner mobile-key\nbroken\n").expect("Failed to write keys file");
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("line 1: key of partner is already in use"));
+        assert!(err.contains("line 2: expected name, key and optional rate limit"));
+
+        let _ = fs::remove_file(&path);
+    }
+}
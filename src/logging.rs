@@ -0,0 +1,167 @@
+use std::io::Write;
+use std::str::FromStr;
+
+use chrono::{SecondsFormat, Utc};
+use env_logger::Builder;
+use failure::Error;
+use log::kv::{self, Key, VisitSource, VisitValue};
+use log::Record;
+use serde_json::{Map, Number, Value};
+
+/// Формат строк лога.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Обычные строки `env_logger`.
+    Text,
+    /// Один объект JSON на строку: время, уровень, источник, сообщение
+    /// и поля записи (`request_id`, `provider`, `city`, `duration_ms`,
+    /// `error_kind` и другие).
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format_err!(
+                "unknown log format {:?}, expected \"text\" or \"json\"",
+                s
+            )),
+        }
+    }
+}
+
+/// Значение поля записи в JSON. Числа и логические значения остаются
+/// собой, остальное выводится строкой.
+struct FieldValue(Value);
+
+impl<'v> VisitValue<'v> for FieldValue {
+    fn visit_any(&mut self, value: kv::Value) -> Result<(), kv::Error> {
+        self.0 = Value::String(value.to_string());
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> Result<(), kv::Error> {
+        self.0 = Value::Null;
+        Ok(())
+    }
+
+    fn visit_u64(&mut self, value: u64) -> Result<(), kv::Error> {
+        self.0 = Value::from(value);
+        Ok(())
+    }
+
+    fn visit_i64(&mut self, value: i64) -> Result<(), kv::Error> {
+        self.0 = Value::from(value);
+        Ok(())
+    }
+
+    fn visit_f64(&mut self, value: f64) -> Result<(), kv::Error> {
+        self.0 = Number::from_f64(value).map_or(Value::Null, Value::Number);
+        Ok(())
+    }
+
+    fn visit_bool(&mut self, value: bool) -> Result<(), kv::Error> {
+        self.0 = Value::Bool(value);
+        Ok(())
+    }
+
+    fn visit_str(&mut self, value: &str) -> Result<(), kv::Error> {
+        self.0 = Value::String(value.to_string());
+        Ok(())
+    }
+}
+
+/// Поля записи. Пустые (`None`) поля пропускаются.
+struct Fields(Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let mut field = FieldValue(Value::Null);
+        value.visit(&mut field)?;
+
+        if !field.0.is_null() {
+            self.0.insert(key.to_string(), field.0);
+        }
+        Ok(())
+    }
+}
+
+/// Запись лога в виде объекта JSON.
+fn json_record(record: &Record) -> Value {
+    let mut fields = Fields(Map::new());
+    let _ = record.key_values().visit(&mut fields);
+
+    let mut line = fields.0;
+    line.insert(
+        "timestamp".to_string(),
+        Value::String(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+    );
+    line.insert(
+        "level".to_string(),
+        Value::String(record.level().to_string()),
+    );
+    line.insert(
+        "target".to_string(),
+        Value::String(record.target().to_string()),
+    );
+    line.insert(
+        "message".to_string(),
+        Value::String(record.args().to_string()),
+    );
+
+    Value::Object(line)
+}
+
+/// Настраивает логгер. Уровни, как и раньше, задаются через `RUST_LOG`.
+pub fn init(format: LogFormat) {
+    let mut builder = Builder::from_default_env();
+
+    if format == LogFormat::Json {
+        builder.format(|buf, record| writeln!(buf, "{}", json_record(record)));
+    }
+
+    builder.init();
+}
+
+#[cfg(test)]
+mod test {
+    use log::Level;
+
+    use super::*;
+
+    #[test]
+    fn formats_json_records() {
+        let fields: &[(&str, kv::Value)] = &[
+            ("request_id", kv::Value::from("abc")),
+            ("provider", kv::Value::from("weatherbit")),
+            ("duration_ms", kv::Value::from(42u64)),
+            ("error_kind", kv::Value::null()),
+        ];
+
+        let line = json_record(
+            &Record::builder()
+                .args(format_args!("weatherbit answered in {} ms", 42))
+                .level(Level::Debug)
+                .target("congenial_lamp::weather_api")
+                .key_values(&fields)
+                .build(),
+        );
+
+        assert_eq!(line["level"], "DEBUG");
+        assert_eq!(line["target"], "congenial_lamp::weather_api");
+        assert_eq!(line["message"], "weatherbit answered in 42 ms");
+        assert_eq!(line["request_id"], "abc");
+        assert_eq!(line["provider"], "weatherbit");
+        assert_eq!(line["duration_ms"], 42);
+        assert!(line.get("error_kind").is_none());
+        assert!(line["timestamp"].as_str().unwrap().ends_with('Z'));
+
+        assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+}
@@ -1,5 +1,6 @@
 extern crate actix;
 extern crate actix_web;
+extern crate bytes;
 extern crate futures;
 extern crate reqwest;
 extern crate tokio;
@@ -20,72 +21,145 @@ extern crate serde_derive;
 extern crate serde;
 #[macro_use]
 extern crate serde_json;
-
-use actix::{Actor, Addr};
+extern crate csv;
+extern crate juniper;
+extern crate quick_xml;
+extern crate rmp_serde;
+extern crate toml;
+extern crate uuid;
+
+use actix::{Actor, Addr, SyncArbiter};
 use actix_web::server;
 use failure::Error;
 
 mod aggregator;
 mod apis;
+mod clients;
+mod config;
+mod logging;
+mod metrics;
+mod otlp;
+mod providers;
+mod shutdown;
 mod weather_api;
 mod web_api;
 
 use aggregator::Aggregator;
-use weather_api::WeatherAPIActor;
-
-fn init_aggregator() -> Result<Addr<Aggregator>, Error> {
-    let client = std::sync::Arc::new(reqwest::async::Client::new());
-
-    let aerisweather = {
-        let api = apis::AerisWeather::new()?;
-        let client = client.clone();
-        WeatherAPIActor::new(client, api).start()
-    };
-
-    let apixu = {
-        let api = apis::Apixu::new()?;
-        let client = client.clone();
-        WeatherAPIActor::new(client, api).start()
-    };
-
-    let openweathermap = {
-        let api = apis::OpenWeatherMap::new()?;
-        let client = client.clone();
-        WeatherAPIActor::new(client, api).start()
-    };
-
-    let weatherbit = {
-        let api = apis::WeatherBit::new()?;
-        let client = client.clone();
-        WeatherAPIActor::new(client, api).start()
-    };
-
-    let aggregator = aggregator::Aggregator::new()
-        .add_api(aerisweather.recipient())
-        .add_api(apixu.recipient())
-        .add_api(openweathermap.recipient())
-        .add_api(weatherbit.recipient());
+use clients::Clients;
+use config::Config;
+use metrics::Metrics;
+use otlp::OtlpExporter;
+use providers::ConfigReloader;
+use shutdown::Shutdown;
+
+/// Число потоков, в которых выполняются запросы GraphQL.
+const GRAPHQL_THREADS: usize = 4;
+
+fn init_aggregator(
+    config: &Config,
+    metrics: &Addr<Metrics>,
+    tracer: Option<&Addr<OtlpExporter>>,
+) -> Result<Addr<Aggregator>, Error> {
+    let mut aggregator = aggregator::Aggregator::new()
+        .aggregation(config.aggregation.strategy)
+        .prefetch_spacing(config.prefetch_spacing())
+        .prefetch_interval(config.prefetch_interval())
+        .not_found_ttl(config.not_found_ttl());
+
+    for (name, api, weight) in providers::start_providers(config, metrics, tracer)? {
+        aggregator = aggregator.add_api(&name, api).weight(&name, weight);
+    }
+
+    for query in config.watchlist()? {
+        aggregator = aggregator.watch(query);
+    }
 
     Ok(aggregator.start())
 }
 
-fn main() -> Result<(), Error> {
-    let bind_to = std::env::var("ADDRESS").unwrap_or_else(|_| "127.0.0.1:8088".to_string());
+/// Запускает сервис и возвращает код выхода, с которым остановилась система.
+fn run() -> Result<i32, Error> {
+    let config_file = Config::file();
+    let config = Config::load(config_file.as_deref())?;
 
-    env_logger::init();
+    logging::init(config.log_format);
 
     let sys = actix::System::new("forecast");
 
-    let aggregator = init_aggregator()?;
+    let metrics = Metrics::new().start();
+    let tracer = config
+        .otlp_endpoint
+        .as_ref()
+        .map(|endpoint| OtlpExporter::new(endpoint).start());
+    let aggregator = init_aggregator(&config, &metrics, tracer.as_ref())?;
+    let clients = Clients::new(config.client_keys()?).start();
+    let reloader = ConfigReloader::new(
+        config_file,
+        config.clone(),
+        aggregator.clone(),
+        clients.clone(),
+        metrics.clone(),
+        tracer.clone(),
+    ).start();
+
+    let pending = aggregator.clone().recipient();
+    let shutdown_tracer = tracer.clone();
+
+    let admin_token = config.admin_token.clone();
+    let min_healthy = config.min_healthy_providers;
+    let log_format = config.log_format;
+
+    let graphql = {
+        let aggregator = aggregator.clone();
+        SyncArbiter::start(GRAPHQL_THREADS, move || {
+            web_api::GraphQLExecutor::new(&aggregator)
+        })
+    };
 
-    server::new(move || {
+    let server = server::new(move || {
         let addr = aggregator.clone().recipient();
-        web_api::WebAPI::new(addr)
-    }).bind(&bind_to)?
+        let admin = admin_token.clone().map(|token| {
+            web_api::CacheAdmin::new(token, &aggregator).reloader(&reloader)
+        });
+        let stream = aggregator.clone().recipient();
+        let subscriptions = web_api::ForecastSubscriptions::new(&aggregator);
+        let health = web_api::HealthCheck::new(min_healthy, &aggregator);
+        let metrics = web_api::PrometheusMetrics::new(&metrics, &aggregator);
+        let providers = aggregator.clone().recipient();
+        let mut api = web_api::WebAPI::new(addr)
+            .graphql(graphql.clone())
+            .stream(stream)
+            .subscriptions(subscriptions)
+            .health(health)
+            .metrics(metrics)
+            .providers(providers)
+            .clients(clients.clone().recipient())
+            .log_format(log_format);
+        if let Some(admin) = admin {
+            api = api.admin(admin);
+        }
+        if let Some(ref tracer) = tracer {
+            api = api.tracer(tracer.clone().recipient());
+        }
+
+        api.app()
+    }).disable_signals()
+    .shutdown_timeout(config.shutdown_timeout)
+    .bind(&config.address)?
     .start();
 
-    info!("Running server on {}", bind_to);
-    sys.run();
+    Shutdown::new(server, pending, shutdown_tracer, config.shutdown_timeout()).start();
+
+    info!(address = config.address.as_str(); "Running server on {}", config.address);
+    Ok(sys.run())
+}
 
-    Ok(())
+fn main() {
+    match run() {
+        Ok(code) => std::process::exit(code),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
 }
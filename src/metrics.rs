@@ -0,0 +1,318 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Write};
+use std::time::Duration;
+
+use actix::prelude::*;
+
+/// Границы корзин гистограмм времени ответа в секундах (как в клиентах
+/// Prometheus по умолчанию).
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Гистограмма времени ответа.
+#[derive(Clone, Debug, Default)]
+pub struct Histogram {
+    counts: [u64; 11],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, elapsed: Duration) {
+        let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+
+        for (bound, count) in BUCKETS.iter().zip(self.counts.iter_mut()) {
+            if seconds <= *bound {
+                *count += 1;
+            }
+        }
+
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+/// Текст в формате Prometheus (text exposition format 0.0.4).
+#[derive(Default)]
+pub struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Заголовок семейства метрик: `counter`, `gauge` или `histogram`.
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+    }
+
+    pub fn sample<V: Display>(&mut self, name: &str, labels: &[(&str, &str)], value: V) {
+        let _ = writeln!(self.text, "{}{} {}", name, Labels(labels, None), value);
+    }
+
+    pub fn histogram(&mut self, name: &str, labels: &[(&str, &str)], histogram: &Histogram) {
+        for (bound, count) in BUCKETS.iter().zip(histogram.counts.iter()) {
+            let le = bound.to_string();
+            let _ = writeln!(
+                self.text,
+                "{}_bucket{} {}",
+                name,
+                Labels(labels, Some(&le)),
+                count
+            );
+        }
+
+        let _ = writeln!(
+            self.text,
+            "{}_bucket{} {}",
+            name,
+            Labels(labels, Some("+Inf")),
+            histogram.count
+        );
+        let _ = writeln!(
+            self.text,
+            "{}_sum{} {}",
+            name,
+            Labels(labels, None),
+            histogram.sum
+        );
+        let _ = writeln!(
+            self.text,
+            "{}_count{} {}",
+            name,
+            Labels(labels, None),
+            histogram.count
+        );
+    }
+
+    pub fn into_string(self) -> String {
+        self.text
+    }
+}
+
+/// Метки образца и, для корзин гистограммы, граница `le`.
+struct Labels<'a>(&'a [(&'a str, &'a str)], Option<&'a str>);
+
+impl<'a> Display for Labels<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let le = self.1.map(|le| ("le", le));
+        let mut labels = self.0.iter().cloned().chain(le).peekable();
+
+        if labels.peek().is_none() {
+            return Ok(());
+        }
+
+        f.write_str("{")?;
+        for (i, (name, value)) in labels.enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            write!(f, "{}=\"{}\"", name, value)?;
+        }
+        f.write_str("}")
+    }
+}
+
+/// Обработанный запрос к `WebAPI`. `route` - шаблон маршрута, а не путь,
+/// чтобы число рядов не зависело от запрошенных городов.
+pub struct RecordRequest {
+    pub route: String,
+    pub method: String,
+    pub status: u16,
+    pub elapsed: Duration,
+}
+
+impl Message for RecordRequest {
+    type Result = ();
+}
+
+/// Завершённое обращение к погодному API. `error` - вид ошибки, если
+/// API не ответил.
+pub struct RecordProviderCall {
+    pub provider: &'static str,
+    pub error: Option<&'static str>,
+    pub elapsed: Duration,
+}
+
+impl Message for RecordProviderCall {
+    type Result = ();
+}
+
+/// Запрос собранных метрик в формате Prometheus.
+pub struct RenderMetrics;
+
+impl Message for RenderMetrics {
+    type Result = String;
+}
+
+#[derive(Default)]
+struct ProviderMetrics {
+    calls: u64,
+    errors: BTreeMap<&'static str, u64>,
+    latency: Histogram,
+}
+
+/// Актор, собирающий метрики запросов к сервису и обращений к погодным
+/// API. Метрики кэша агрегатор хранит сам.
+#[derive(Default)]
+pub struct Metrics {
+    requests: BTreeMap<(String, String, u16), u64>,
+    latency: BTreeMap<(String, String, u16), Histogram>,
+    providers: BTreeMap<&'static str, ProviderMetrics>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Actor for Metrics {
+    type Context = Context<Self>;
+}
+
+impl Handler<RecordRequest> for Metrics {
+    type Result = ();
+
+    fn handle(&mut self, msg: RecordRequest, _ctx: &mut Self::Context) -> Self::Result {
+        *self
+            .requests
+            .entry((msg.route.clone(), msg.method.clone(), msg.status))
+            .or_default() += 1;
+
+        self.latency
+            .entry((msg.route, msg.method, msg.status))
+            .or_default()
+            .observe(msg.elapsed);
+    }
+}
+
+impl Handler<RecordProviderCall> for Metrics {
+    type Result = ();
+
+    fn handle(&mut self, msg: RecordProviderCall, _ctx: &mut Self::Context) -> Self::Result {
+        let provider = self.providers.entry(msg.provider).or_default();
+
+        provider.calls += 1;
+        provider.latency.observe(msg.elapsed);
+        if let Some(kind) = msg.error {
+            *provider.errors.entry(kind).or_default() += 1;
+        }
+    }
+}
+
+impl Handler<RenderMetrics> for Metrics {
+    type Result = MessageResult<RenderMetrics>;
+
+    fn handle(&mut self, _msg: RenderMetrics, _ctx: &mut Self::Context) -> Self::Result {
+        let mut out = Exposition::new();
+
+        out.family(
+            "http_requests_total",
+            "counter",
+            "Requests handled by route, method and status code.",
+        );
+        for ((route, method, status), count) in &self.requests {
+            let status = status.to_string();
+            let labels = [
+                ("route", route.as_str()),
+                ("method", method.as_str()),
+                ("status", &status),
+            ];
+            out.sample("http_requests_total", &labels, count);
+        }
+
+        out.family(
+            "http_request_duration_seconds",
+            "histogram",
+            "Time until the response headers are ready by route, method and status code.",
+        );
+        for ((route, method, status), histogram) in &self.latency {
+            let status = status.to_string();
+            let labels = [
+                ("route", route.as_str()),
+                ("method", method.as_str()),
+                ("status", &status),
+            ];
+            out.histogram("http_request_duration_seconds", &labels, histogram);
+        }
+
+        out.family(
+            "weather_api_calls_total",
+            "counter",
+            "Requests sent to each weather API.",
+        );
+        for (name, provider) in &self.providers {
+            out.sample(
+                "weather_api_calls_total",
+                &[("provider", name)],
+                provider.calls,
+            );
+        }
+
+        out.family(
+            "weather_api_errors_total",
+            "counter",
+            "Failed weather API requests by kind of error.",
+        );
+        for (name, provider) in &self.providers {
+            for (kind, count) in &provider.errors {
+                out.sample(
+                    "weather_api_errors_total",
+                    &[("provider", name), ("kind", kind)],
+                    count,
+                );
+            }
+        }
+
+        out.family(
+            "weather_api_request_duration_seconds",
+            "histogram",
+            "Response time of each weather API.",
+        );
+        for (name, provider) in &self.providers {
+            out.histogram(
+                "weather_api_request_duration_seconds",
+                &[("provider", name)],
+                &provider.latency,
+            );
+        }
+
+        MessageResult(out.into_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn renders_histogram() {
+        let mut histogram = Histogram::default();
+        histogram.observe(Duration::from_millis(20));
+        histogram.observe(Duration::from_secs(20));
+
+        let mut out = Exposition::new();
+        out.family("latency_seconds", "histogram", "Test latency.");
+        out.histogram("latency_seconds", &[("route", "/a\"b")], &histogram);
+        let text = out.into_string();
+
+        assert!(text.starts_with(
+            "# HELP latency_seconds Test latency.\n# TYPE latency_seconds histogram\n"
+        ));
+        assert!(text.contains("latency_seconds_bucket{route=\"/a\\\"b\",le=\"0.01\"} 0\n"));
+        assert!(text.contains("latency_seconds_bucket{route=\"/a\\\"b\",le=\"0.025\"} 1\n"));
+        assert!(text.contains("latency_seconds_bucket{route=\"/a\\\"b\",le=\"10\"} 1\n"));
+        assert!(text.contains("latency_seconds_bucket{route=\"/a\\\"b\",le=\"+Inf\"} 2\n"));
+        assert!(text.contains("latency_seconds_count{route=\"/a\\\"b\"} 2\n"));
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix::fut::wrap_future;
+use actix::prelude::*;
+use futures::{future, Future};
+use reqwest::async::Client;
+use serde_json::Value;
+
+/// Как часто накопленные участки отправляются коллектору.
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Имя сервиса в трассировках.
+const SERVICE_NAME: &str = "congenial-lamp";
+
+/// Вид участка: обработка входящего запроса или запрос к погодному API.
+#[derive(Clone, Copy, Debug)]
+pub enum SpanKind {
+    Server,
+    Client,
+}
+
+/// Завершённый участок трассировки. Все участки одного HTTP-запроса
+/// относятся к трассировке, которая выводится из его идентификатора.
+#[derive(Clone, Debug)]
+pub struct Span {
+    pub request_id: String,
+    pub name: String,
+    pub kind: SpanKind,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub attributes: Vec<(&'static str, String)>,
+    pub failed: bool,
+}
+
+impl Message for Span {
+    type Result = ();
+}
+
+/// Запрос на немедленную отправку накопленных участков, например перед
+/// остановкой сервиса. Отвечает, когда коллектор принял их или отказал.
+pub struct Flush;
+
+impl Message for Flush {
+    type Result = Result<(), ()>;
+}
+
+/// Идентификатор трассировки по идентификатору запроса. UUID, которые
+/// сервис генерирует сам, используются как есть, остальные хэшируются.
+pub fn trace_id(request_id: &str) -> String {
+    let hex: String = request_id.chars().filter(|c| *c != '-').collect();
+    if hex.len() == 32 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return hex.to_ascii_lowercase();
+    }
+
+    format!(
+        "{}{}",
+        span_id(request_id, "trace"),
+        span_id(request_id, "trace-low")
+    )
+}
+
+/// Идентификатор участка, однозначно определяемый запросом и `salt`.
+pub fn span_id(request_id: &str, salt: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    (request_id, salt).hash(&mut hasher);
+
+    format!("{:016x}", hasher.finish())
+}
+
+/// Идентификатор участка обработки входящего запроса, родительского
+/// для запросов к погодным API.
+pub fn server_span_id(request_id: &str) -> String {
+    span_id(request_id, "server")
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    (since_epoch.as_secs() * 1_000_000_000 + u64::from(since_epoch.subsec_nanos())).to_string()
+}
+
+impl Span {
+    /// Участок в формате OTLP/JSON.
+    fn to_otlp(&self) -> Value {
+        let (kind, span_id, parent_span_id) = match self.kind {
+            SpanKind::Server => (2, server_span_id(&self.request_id), None),
+            SpanKind::Client => (
+                3,
+                span_id(
+                    &self.request_id,
+                    &format!("{}@{}", self.name, unix_nanos(self.start)),
+                ),
+                Some(server_span_id(&self.request_id)),
+            ),
+        };
+
+        let attributes: Vec<Value> = self
+            .attributes
+            .iter()
+            .map(|(key, value)| json!({ "key": key, "value": { "stringValue": value } }))
+            .collect();
+
+        json!({
+            "traceId": trace_id(&self.request_id),
+            "spanId": span_id,
+            "parentSpanId": parent_span_id.unwrap_or_default(),
+            "name": self.name,
+            "kind": kind,
+            "startTimeUnixNano": unix_nanos(self.start),
+            "endTimeUnixNano": unix_nanos(self.end),
+            "attributes": attributes,
+            "status": { "code": if self.failed { 2 } else { 0 } },
+        })
+    }
+}
+
+/// Актор, отправляющий участки трассировки коллектору OpenTelemetry
+/// по OTLP/HTTP в формате JSON. Участки копятся и отправляются пачкой
+/// раз в `EXPORT_INTERVAL`; если коллектор недоступен, они теряются.
+pub struct OtlpExporter {
+    client: Client,
+    url: String,
+    spans: Vec<Span>,
+}
+
+impl OtlpExporter {
+    /// `endpoint` - адрес коллектора, например `http://localhost:4318`.
+    pub fn new(endpoint: &str) -> Self {
+        Self {
+            client: Client::new(),
+            url: format!("{}/v1/traces", endpoint.trim_end_matches('/')),
+            spans: Vec::new(),
+        }
+    }
+
+    fn export(&mut self) -> ResponseFuture<(), ()> {
+        if self.spans.is_empty() {
+            return Box::new(future::ok(()));
+        }
+
+        let spans: Vec<Value> = mem::take(&mut self.spans)
+            .iter()
+            .map(Span::to_otlp)
+            .collect();
+        let body = json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [
+                        { "key": "service.name", "value": { "stringValue": SERVICE_NAME } }
+                    ]
+                },
+                "scopeSpans": [{ "scope": { "name": SERVICE_NAME }, "spans": spans }]
+            }]
+        });
+
+        let request = self
+            .client
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .and_then(|res| res.error_for_status())
+            .map(|_| ())
+            .map_err(|err| warn!("Failed to export spans: {}", err));
+
+        Box::new(request)
+    }
+}
+
+impl Actor for OtlpExporter {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(EXPORT_INTERVAL, |exporter, ctx| {
+            ctx.spawn(wrap_future(exporter.export()));
+        });
+    }
+}
+
+impl Handler<Span> for OtlpExporter {
+    type Result = ();
+
+    fn handle(&mut self, msg: Span, _ctx: &mut Self::Context) -> Self::Result {
+        self.spans.push(msg);
+    }
+}
+
+impl Handler<Flush> for OtlpExporter {
+    type Result = ResponseFuture<(), ()>;
+
+    fn handle(&mut self, _msg: Flush, _ctx: &mut Self::Context) -> Self::Result {
+        self.export()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn links_spans_by_request() {
+        let request_id = "5f0c6f4e-8a8b-4d5c-9a3e-2b1f0e9d8c7b";
+        assert_eq!(trace_id(request_id), "5f0c6f4e8a8b4d5c9a3e2b1f0e9d8c7b");
+        assert_eq!(trace_id("custom").len(), 32);
+        assert_eq!(trace_id("custom"), trace_id("custom"));
+
+        let now = SystemTime::now();
+        let server = Span {
+            request_id: "custom".to_string(),
+            name: "GET /forecast/weekly/{country}/{city}".to_string(),
+            kind: SpanKind::Server,
+            start: now,
+            end: now,
+            attributes: vec![],
+            failed: false,
+        };
+        let client = Span {
+            name: "weatherbit".to_string(),
+            kind: SpanKind::Client,
+            failed: true,
+            ..server.clone()
+        };
+
+        let (server, client) = (server.to_otlp(), client.to_otlp());
+        assert_eq!(server["traceId"], client["traceId"]);
+        assert_eq!(server["spanId"], client["parentSpanId"]);
+        assert_eq!(server["kind"], 2);
+        assert_eq!(client["status"]["code"], 2);
+    }
+}
@@ -0,0 +1,361 @@
+use std::sync::Arc;
+
+use actix::actors::signal::{ProcessSignals, Signal, SignalType, Subscribe};
+use actix::prelude::*;
+use failure::Error;
+use futures::{future, Future};
+use reqwest;
+use serde;
+
+use aggregator::{AddApi, Aggregator, ProviderInfo, Reconfigure};
+use apis::{self, WeatherAPI, WeatherQuery, WeatherReport};
+use clients::{Clients, SetClientKeys};
+use config::{Config, ProviderConfig};
+use metrics::Metrics;
+use otlp::OtlpExporter;
+use weather_api::WeatherAPIActor;
+
+/// Актор погодного API с настройками из `config`, отправляющий метрики
+/// и, если задан `tracer`, участки трассировки.
+fn start_api<A, R>(
+    api: A,
+    config: &ProviderConfig,
+    metrics: &Addr<Metrics>,
+    tracer: Option<&Addr<OtlpExporter>>,
+) -> Result<Recipient<WeatherQuery>, Error>
+where
+    A: WeatherAPI<Response = R> + 'static,
+    R: Into<WeatherReport> + for<'de> serde::Deserialize<'de> + 'static,
+{
+    let mut client = reqwest::async::Client::builder();
+    if let Some(timeout) = config.timeout() {
+        client = client.timeout(timeout);
+    }
+
+    let mut actor =
+        WeatherAPIActor::new(Arc::new(client.build()?), api).metrics(metrics.clone().recipient());
+    if let Some(tracer) = tracer {
+        actor = actor.tracer(tracer.clone().recipient());
+    }
+    if let Some(limit) = config.rate_limit {
+        actor = actor.rate_limit(limit);
+    }
+    if let Some(days) = config.horizon {
+        actor = actor.horizon(days);
+    }
+
+    Ok(actor.start().recipient())
+}
+
+/// Актор погодного API `name`.
+fn start_provider(
+    name: &str,
+    config: &ProviderConfig,
+    metrics: &Addr<Metrics>,
+    tracer: Option<&Addr<OtlpExporter>>,
+) -> Result<Recipient<WeatherQuery>, Error> {
+    match name {
+        apis::AerisWeather::NAME => {
+            let api = apis::AerisWeather::new(
+                config.credential("client_id"),
+                config.credential("client_secret"),
+                config.base_url(apis::AerisWeather::BASE_URL),
+            );
+            start_api(api, config, metrics, tracer)
+        }
+        apis::Apixu::NAME => {
+            let api = apis::Apixu::new(
+                config.credential("api_key"),
+                config.base_url(apis::Apixu::BASE_URL),
+            );
+            start_api(api, config, metrics, tracer)
+        }
+        apis::OpenWeatherMap::NAME => {
+            let api = apis::OpenWeatherMap::new(
+                config.credential("api_key"),
+                config.base_url(apis::OpenWeatherMap::BASE_URL),
+            );
+            start_api(api, config, metrics, tracer)
+        }
+        apis::WeatherBit::NAME => {
+            let api = apis::WeatherBit::new(
+                config.credential("api_key"),
+                config.base_url(apis::WeatherBit::BASE_URL),
+            );
+            start_api(api, config, metrics, tracer)
+        }
+        _ => Err(format_err!("unknown provider {}", name)),
+    }
+}
+
+/// Акторы включённых погодных API, для которых заданы ключи, с их весами.
+/// О пропущенных из-за отсутствия ключей API пишется в лог.
+pub fn start_providers(
+    config: &Config,
+    metrics: &Addr<Metrics>,
+    tracer: Option<&Addr<OtlpExporter>>,
+) -> Result<Vec<(String, Recipient<WeatherQuery>, f32)>, Error> {
+    for (name, missing) in config.skipped_providers() {
+        warn!(
+            provider = name.as_str();
+            "Skipping {}: missing {}",
+            name,
+            missing.join(" and ")
+        );
+    }
+
+    let mut apis = Vec::new();
+    for (name, provider) in config.active_providers() {
+        let api = start_provider(name, provider, metrics, tracer)?;
+        apis.push((name.clone(), api, provider.weight));
+    }
+
+    let names: Vec<&str> = apis.iter().map(|(name, _, _)| name.as_str()).collect();
+    info!("Using weather APIs: {}", names.join(", "));
+
+    Ok(apis)
+}
+
+/// Запрос на перезагрузку конфигурации. Отвечает списком API, которые
+/// агрегатор опрашивает после перезагрузки.
+pub struct ReloadConfig;
+
+impl Message for ReloadConfig {
+    type Result = Result<Vec<ProviderInfo>, Error>;
+}
+
+/// Запрос на запуск погодного API `name` с настройками `config` и его
+/// добавление в агрегатор. Отвечает добавленным API.
+pub struct AddProvider {
+    pub name: String,
+    pub config: ProviderConfig,
+    pub request_id: Option<String>,
+}
+
+impl Message for AddProvider {
+    type Result = Result<ProviderInfo, Error>;
+}
+
+/// Актор, перечитывающий настройки из файла `config_file` (того же, что
+/// был прочитан при запуске) по сигналу SIGHUP или по запросу
+/// `ReloadConfig`. Акторы погодных API создаются заново и подменяются
+/// в работающем агрегаторе вместе с весами, способом агрегации
+/// и настройками кэша, а ключи клиентов - в `clients`. Если новые
+/// настройки неверны, остаются старые.
+///
+/// Адрес сервера, токен администратора, `min_healthy_providers`, формат
+/// лога и адрес коллектора трассировок применяются только при запуске.
+///
+/// Через него же добавляются погодные API на ходу (`AddProvider`): актору
+/// API нужны те же метрики и трассировка. Такие API, как и веса, изменённые
+/// прямо в агрегаторе, действуют до следующей перезагрузки; отключённые API
+/// остаются отключёнными и после неё.
+pub struct ConfigReloader {
+    config_file: Option<String>,
+    config: Config,
+    aggregator: Addr<Aggregator>,
+    clients: Addr<Clients>,
+    metrics: Addr<Metrics>,
+    tracer: Option<Addr<OtlpExporter>>,
+}
+
+impl ConfigReloader {
+    pub fn new(
+        config_file: Option<String>,
+        config: Config,
+        aggregator: Addr<Aggregator>,
+        clients: Addr<Clients>,
+        metrics: Addr<Metrics>,
+        tracer: Option<Addr<OtlpExporter>>,
+    ) -> Self {
+        Self {
+            config_file,
+            config,
+            aggregator,
+            clients,
+            metrics,
+            tracer,
+        }
+    }
+
+    fn reload(&mut self) -> Result<Reconfigure, Error> {
+        let config = Config::load(self.config_file.as_deref())?;
+
+        for setting in self.config.restart_required(&config) {
+            warn!("Setting {} has changed, restart to apply it", setting);
+        }
+
+        let client_keys = config.client_keys()?;
+        let reconfigure = Reconfigure {
+            apis: start_providers(&config, &self.metrics, self.tracer.as_ref())?,
+            aggregation: config.aggregation.strategy,
+            watchlist: config.watchlist()?,
+            not_found_ttl: config.not_found_ttl(),
+            prefetch_spacing: config.prefetch_spacing(),
+            prefetch_interval: config.prefetch_interval(),
+        };
+        self.clients.do_send(SetClientKeys(client_keys));
+        self.config = config;
+
+        Ok(reconfigure)
+    }
+}
+
+impl Actor for ConfigReloader {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let signals = System::current().registry().get::<ProcessSignals>();
+        signals.do_send(Subscribe(ctx.address().recipient()));
+    }
+}
+
+impl Handler<ReloadConfig> for ConfigReloader {
+    type Result = ResponseFuture<Vec<ProviderInfo>, Error>;
+
+    fn handle(&mut self, _msg: ReloadConfig, _ctx: &mut Self::Context) -> Self::Result {
+        let reconfigure = match self.reload() {
+            Ok(reconfigure) => reconfigure,
+            Err(err) => {
+                error!("Failed to reload configuration: {}", err);
+                return Box::new(future::err(err));
+            }
+        };
+
+        let reloaded = self
+            .aggregator
+            .send(reconfigure)
+            .map(|providers| {
+                info!("Configuration reloaded");
+                providers
+            })
+            .from_err();
+
+        Box::new(reloaded)
+    }
+}
+
+impl Handler<Signal> for ConfigReloader {
+    type Result = ();
+
+    fn handle(&mut self, msg: Signal, ctx: &mut Self::Context) -> Self::Result {
+        if let SignalType::Hup = msg.0 {
+            info!("Reloading configuration on SIGHUP");
+
+            ctx.notify(ReloadConfig);
+        }
+    }
+}
+
+impl Handler<AddProvider> for ConfigReloader {
+    type Result = ResponseFuture<ProviderInfo, Error>;
+
+    fn handle(&mut self, msg: AddProvider, _ctx: &mut Self::Context) -> Self::Result {
+        let AddProvider {
+            name,
+            config,
+            request_id,
+        } = msg;
+
+        let api = config
+            .check(&name)
+            .and_then(|_| start_provider(&name, &config, &self.metrics, self.tracer.as_ref()));
+        let api = match api {
+            Ok(api) => api,
+            Err(err) => return Box::new(future::err(err)),
+        };
+
+        let added = self
+            .aggregator
+            .send(AddApi {
+                name,
+                api,
+                weight: config.weight,
+                enabled: config.enabled,
+                request_id,
+            }).from_err()
+            .and_then(|res| res);
+
+        Box::new(added)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, process};
+
+    use super::*;
+    use aggregator::ListProviders;
+    use clients::CheckClient;
+
+    #[test]
+    fn reloads_providers() {
+        let path = env::temp_dir().join(format!("congenial-lamp-{}.toml", process::id()));
+
+        let mut sys = System::new("test");
+        let metrics = Metrics::new().start();
+
+        let config = Config::parse(
+            r#"
+[providers.apixu]
+api_key = "key"
+
+[providers.weatherbit]
+api_key = "key"
+"#,
+        )
+        .expect("Failed to parse config");
+
+        let mut aggregator = Aggregator::new();
+        for (name, api, weight) in start_providers(&config, &metrics, None).unwrap() {
+            aggregator = aggregator.add_api(&name, api).weight(&name, weight);
+        }
+        let aggregator = aggregator.start();
+        let clients = Clients::new(vec![]).start();
+        let reloader = ConfigReloader::new(
+            Some(path.to_string_lossy().into_owned()),
+            config,
+            aggregator.clone(),
+            clients.clone(),
+            metrics,
+            None,
+        ).start();
+
+        fs::write(
+            &path,
+            "[clients.keys.mobile]\nkey = \"secret\"\n\n\
+             [providers.weatherbit]\napi_key = \"new\"\nweight = 2.0\n",
+        )
+        .expect("Failed to write config");
+        let providers = sys
+            .block_on(reloader.send(ReloadConfig))
+            .expect("Reloader is unavailable")
+            .expect("Failed to reload");
+        assert_eq!(providers.len(), 1);
+        assert_eq!(providers[0].name, "weatherbit");
+        assert_eq!(providers[0].weight, 2.0);
+
+        let client = sys
+            .block_on(clients.send(CheckClient(Some("secret".to_string()))))
+            .expect("Clients actor is unavailable");
+        assert_eq!(client, Ok(Some("mobile".to_string())));
+
+        fs::write(
+            &path,
+            "[providers.weatherbit]\napi_key = \"new\"\nweight = -1.0\n",
+        )
+        .expect("Failed to write config");
+        let err = sys
+            .block_on(reloader.send(ReloadConfig))
+            .expect("Reloader is unavailable")
+            .expect_err("Invalid config is applied");
+        assert!(err.to_string().contains("providers.weatherbit.weight"));
+
+        let providers = sys
+            .block_on(aggregator.send(ListProviders))
+            .expect("Aggregator is unavailable");
+        assert_eq!(providers[0].weight, 2.0);
+
+        let _ = fs::remove_file(&path);
+    }
+}
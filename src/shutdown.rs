@@ -0,0 +1,233 @@
+use std::time::{Duration, Instant};
+
+use actix::actors::signal::{ProcessSignals, Signal, SignalType, Subscribe};
+use actix::fut::wrap_future;
+use actix::prelude::*;
+use actix_web::server::{Server, StopServer};
+use futures::future::{self, Either, Loop};
+use futures::Future;
+use tokio::timer::{Delay, Timeout};
+
+use aggregator::GetPendingRequests;
+use otlp::{Flush, OtlpExporter};
+
+/// Код выхода, если к сроку остались запросы к погодным API без ответа.
+pub const EXIT_DRAIN_TIMEOUT: i32 = 2;
+
+/// Код выхода при повторном сигнале во время остановки или по SIGQUIT.
+pub const EXIT_FORCED: i32 = 3;
+
+/// Как часто проверяется, остались ли запросы к погодным API без ответа.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Ждёт, пока у агрегатора не останется запросов к погодным API без ответа,
+/// но не дольше `deadline`. Отвечает, удалось ли дождаться.
+fn drain(
+    pending: Recipient<GetPendingRequests>,
+    deadline: Instant,
+) -> impl Future<Item = bool, Error = ()> {
+    future::loop_fn((), move |_| {
+        pending
+            .send(GetPendingRequests)
+            .map_err(|_| ())
+            .and_then(move |requests| {
+                let left: usize = requests.iter().map(|(_, pending)| pending).sum();
+                if left == 0 {
+                    return Either::A(future::ok(Loop::Break(true)));
+                }
+
+                let now = Instant::now();
+                if now >= deadline {
+                    warn!("{} weather API requests are still pending, giving up", left);
+                    return Either::A(future::ok(Loop::Break(false)));
+                }
+
+                debug!("Waiting for {} weather API requests", left);
+                let wake = deadline.min(now + DRAIN_POLL_INTERVAL);
+                Either::B(Delay::new(wake).map(|_| Loop::Continue(())).map_err(|_| ()))
+            })
+    })
+}
+
+/// Актор, останавливающий сервис по SIGTERM или SIGINT: сервер перестаёт
+/// принимать соединения и дорабатывает начатые запросы, затем агрегатор
+/// дожидается ответов погодных API, а накопленные участки трассировки
+/// отправляются коллектору. На всё это отводится `timeout`.
+///
+/// Система останавливается с кодом `0`, если все запросы дождались ответа,
+/// и `EXIT_DRAIN_TIMEOUT`, если срок вышел раньше. Повторный сигнал или
+/// SIGQUIT останавливают её сразу с кодом `EXIT_FORCED`.
+pub struct Shutdown {
+    server: Addr<Server>,
+    pending: Recipient<GetPendingRequests>,
+    tracer: Option<Addr<OtlpExporter>>,
+    timeout: Duration,
+    stopping: bool,
+}
+
+impl Shutdown {
+    pub fn new(
+        server: Addr<Server>,
+        pending: Recipient<GetPendingRequests>,
+        tracer: Option<Addr<OtlpExporter>>,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            server,
+            pending,
+            tracer,
+            timeout,
+            stopping: false,
+        }
+    }
+
+    fn shut_down(&mut self, ctx: &mut Context<Self>) {
+        self.stopping = true;
+
+        let deadline = Instant::now() + self.timeout;
+        let pending = self.pending.clone();
+        let tracer = self.tracer.clone();
+
+        let shutdown = self
+            .server
+            .send(StopServer { graceful: true })
+            .then(move |_| {
+                info!("Server stopped, waiting for weather APIs");
+                drain(pending, deadline)
+            })
+            .then(move |drained| {
+                let drained = drained.unwrap_or(false);
+
+                let flush = match tracer {
+                    Some(tracer) => {
+                        // Трассировки отправляются только в пределах срока:
+                        // если он уже вышел, они теряются.
+                        let left = deadline.saturating_duration_since(Instant::now());
+                        let flush = tracer.send(Flush).then(|_| Ok::<_, ()>(()));
+                        Either::A(Timeout::new(flush, left).then(|_| Ok(())))
+                    }
+                    None => Either::B(future::ok(())),
+                };
+
+                flush.map(move |_| drained)
+            })
+            .map(|drained| {
+                let code = if drained { 0 } else { EXIT_DRAIN_TIMEOUT };
+                info!("Shutdown complete");
+                System::current().stop_with_code(code);
+            });
+
+        ctx.spawn(wrap_future(shutdown));
+    }
+}
+
+impl Actor for Shutdown {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let signals = System::current().registry().get::<ProcessSignals>();
+        signals.do_send(Subscribe(ctx.address().recipient()));
+    }
+}
+
+impl Handler<Signal> for Shutdown {
+    type Result = ();
+
+    fn handle(&mut self, msg: Signal, ctx: &mut Self::Context) -> Self::Result {
+        match msg.0 {
+            SignalType::Int | SignalType::Term if !self.stopping => {
+                info!(
+                    "{:?} received, stopping within {} s",
+                    msg.0,
+                    self.timeout.as_secs()
+                );
+                self.shut_down(ctx);
+            }
+            SignalType::Int | SignalType::Term | SignalType::Quit => {
+                warn!("{:?} received, exiting immediately", msg.0);
+                System::current().stop_with_code(EXIT_FORCED);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use chrono::Utc;
+    use failure::Error;
+
+    use super::*;
+    use aggregator::{Aggregator, ForecastQuery, GetPendingRequests};
+    use apis::{WeatherData, WeatherDataVec, WeatherQuery, WeatherReport};
+
+    /// Тестовый API, отвечающий через `delay`.
+    struct SlowWeatherActor {
+        delay: Duration,
+    }
+
+    impl Actor for SlowWeatherActor {
+        type Context = SyncContext<Self>;
+    }
+
+    impl Handler<WeatherQuery> for SlowWeatherActor {
+        type Result = Result<WeatherReport, Error>;
+
+        fn handle(&mut self, _msg: WeatherQuery, _ctx: &mut Self::Context) -> Self::Result {
+            thread::sleep(self.delay);
+
+            let data: WeatherDataVec = smallvec![WeatherData {
+                date: Utc::now().naive_utc().date(),
+                temperature: 10.0,
+            }];
+
+            Ok(data.into())
+        }
+    }
+
+    #[test]
+    fn drains_pending_requests() {
+        let mut sys = System::new("test");
+
+        let slow = |millis| {
+            SyncArbiter::start(1, move || SlowWeatherActor {
+                delay: Duration::from_millis(millis),
+            })
+        };
+        let aggregator = Aggregator::new()
+            .add_api("slow", slow(200).recipient())
+            .start();
+        let query = WeatherQuery::new("UK".to_string(), "London".to_string());
+
+        // Запрос уже отправлен API, но ответа на него ещё нет.
+        let forecast = aggregator.send(ForecastQuery(query.clone()));
+        let pending = sys
+            .block_on(aggregator.send(GetPendingRequests))
+            .expect("Aggregator is unavailable");
+        assert_eq!(pending, [("slow".to_string(), 1)]);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let drained = sys
+            .block_on(drain(aggregator.clone().recipient(), deadline))
+            .expect("Failed to drain");
+        assert!(drained);
+        sys.block_on(forecast)
+            .expect("Aggregator is unavailable")
+            .expect("Aggregator failed");
+
+        let aggregator = Aggregator::new()
+            .add_api("stuck", slow(2000).recipient())
+            .start();
+
+        // Ответа на этот запрос никто не ждёт, но агрегатор всё равно
+        // отправляет его API.
+        let _forecast = aggregator.send(ForecastQuery(query));
+        let deadline = Instant::now() + Duration::from_millis(300);
+        let drained = sys
+            .block_on(drain(aggregator.recipient(), deadline))
+            .expect("Failed to drain");
+        assert!(!drained);
+    }
+}
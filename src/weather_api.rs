@@ -1,19 +1,53 @@
+use std::collections::VecDeque;
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
-use actix::{Actor, Context, Handler};
+use actix::{Actor, Context, Handler, Recipient};
+use chrono::{self, Utc};
 use failure::Error;
-use futures::Future;
+use futures::future::{self, Either};
+use futures::{Future, Stream};
 use reqwest::async::Client;
+use reqwest::StatusCode;
 
-use apis::{WeatherAPI, WeatherDataVec, WeatherQuery};
+use apis::{WeatherAPI, WeatherDataVec, WeatherQuery, WeatherReport};
+use metrics::RecordProviderCall;
+use otlp::{Span, SpanKind};
+
+/// Окно, в котором считаются запросы для ограничения частоты.
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Запрос к API не отправлен: исчерпан его `rate_limit` (запросов в минуту).
+/// С самим API при этом всё в порядке.
+#[derive(Fail, Debug)]
+#[fail(display = "{} rate limit of {} requests per minute exceeded", _0, _1)]
+pub struct RateLimited(pub &'static str, pub u32);
 
 /// Актор, отправляющий запросы погодным API с помощью типажа `WeatherAPI`.
+/// Ответы API о том, что город не найден (см. `WeatherAPI::is_not_found`),
+/// превращаются в пустой результат, а ответы с другими кодами ошибок
+/// завершаются ошибкой. О каждом запросе в лог пишется адрес (без
+/// параметров, в которых лежит ключ), код ответа и время ответа, а тела
+/// ответов с ошибками - только на уровне `debug`.
+/// Если задан `metrics`, то о каждом запросе отправляется его время
+/// и вид ошибки, а если `tracer` - участок трассировки запроса, ради
+/// которого API опрашивался.
+///
+/// `rate_limit` - сколько запросов в минуту можно отправить API, сверх
+/// этого запросы сразу завершаются ошибкой `RateLimited`. `horizon` - на
+/// сколько дней вперёд прогнозу API можно доверять, более далёкие дни
+/// отбрасываются.
 pub struct WeatherAPIActor<A>
 where
     A: WeatherAPI + 'static,
 {
     client: Arc<Client>,
     api: A,
+    metrics: Option<Recipient<RecordProviderCall>>,
+    tracer: Option<Recipient<Span>>,
+    rate_limit: Option<u32>,
+    horizon: Option<u32>,
+    calls: VecDeque<Instant>,
 }
 
 impl<A> WeatherAPIActor<A>
@@ -21,7 +55,91 @@ where
     A: WeatherAPI,
 {
     pub fn new(client: Arc<Client>, api: A) -> Self {
-        Self { client, api }
+        Self {
+            client,
+            api,
+            metrics: None,
+            tracer: None,
+            rate_limit: None,
+            horizon: None,
+            calls: VecDeque::new(),
+        }
+    }
+
+    pub fn metrics(mut self, metrics: Recipient<RecordProviderCall>) -> Self {
+        self.metrics = Some(metrics);
+
+        self
+    }
+
+    pub fn tracer(mut self, tracer: Recipient<Span>) -> Self {
+        self.tracer = Some(tracer);
+
+        self
+    }
+
+    pub fn rate_limit(mut self, per_minute: u32) -> Self {
+        self.rate_limit = Some(per_minute);
+
+        self
+    }
+
+    pub fn horizon(mut self, days: u32) -> Self {
+        self.horizon = Some(days);
+
+        self
+    }
+
+    /// Учитывает запрос, если он укладывается в `rate_limit`.
+    fn acquire(&mut self) -> bool {
+        let limit = match self.rate_limit {
+            Some(limit) => limit as usize,
+            None => return true,
+        };
+
+        let now = Instant::now();
+        while self
+            .calls
+            .front()
+            .is_some_and(|call| now.duration_since(*call) >= RATE_LIMIT_WINDOW)
+        {
+            self.calls.pop_front();
+        }
+
+        if self.calls.len() >= limit {
+            return false;
+        }
+
+        self.calls.push_back(now);
+        true
+    }
+}
+
+/// Вид ошибки запроса для метрик.
+fn error_kind(err: &reqwest::Error) -> &'static str {
+    if err.is_timeout() {
+        "timeout"
+    } else if err.status().is_some() {
+        "status"
+    } else if err.is_serialization() {
+        "decode"
+    } else if err.is_http() {
+        "connection"
+    } else {
+        "other"
+    }
+}
+
+/// Ошибка запроса без параметров URL: в них лежит ключ API, а сообщение
+/// об ошибке попадает в состояние API.
+fn redact(err: reqwest::Error) -> Error {
+    match err.url() {
+        Some(url) => {
+            let mut redacted = url.clone();
+            redacted.set_query(None);
+            format_err!("{}", err.to_string().replace(url.as_str(), redacted.as_str()))
+        }
+        None => Error::from(err),
     }
 }
 
@@ -35,21 +153,121 @@ where
 impl<A, R> Handler<WeatherQuery> for WeatherAPIActor<A>
 where
     A: WeatherAPI<Response = R>,
-    R: Into<WeatherDataVec> + 'static,
+    R: Into<WeatherReport> + 'static,
     R: for<'de> ::serde::Deserialize<'de>,
 {
-    type Result = Box<Future<Item = WeatherDataVec, Error = Error>>;
+    type Result = Box<Future<Item = WeatherReport, Error = Error>>;
 
     fn handle(&mut self, msg: WeatherQuery, _ctx: &mut Self::Context) -> Self::Result {
+        if !self.acquire() {
+            let reason = RateLimited(A::NAME, self.rate_limit.unwrap_or_default());
+            return Box::new(future::err(Error::from(reason)));
+        }
+
         let url = self.api.make_url(&msg).expect("Failed to prepare URL");
+        let logged_url = {
+            let mut logged_url = url.clone();
+            logged_url.set_query(None);
+            logged_url.to_string()
+        };
+        let horizon = self
+            .horizon
+            .map(|days| Utc::now().naive_utc().date() + chrono::Duration::days(i64::from(days)));
+        let metrics = self.metrics.clone();
+        let tracer = self.tracer.clone();
+        let request_id = msg.request_id().map(str::to_string);
+        let city = msg.city().to_string();
+        let body_request_id = request_id.clone();
+        let started = Instant::now();
+        let started_at = SystemTime::now();
 
         let req = self
             .client
             .get(url)
             .send()
-            .and_then(|mut res| res.json::<A::Response>())
-            .map(|res| res.into())
-            .map_err(|err| Error::from(err));
+            .and_then(move |mut res| {
+                let status = res.status();
+                if A::is_not_found(status) {
+                    let report = WeatherReport::from(WeatherDataVec::new());
+                    return Either::A(future::ok((status, report)));
+                }
+
+                if let Err(err) = res.error_for_status_ref() {
+                    let body = res.into_body().concat2().then(move |body| {
+                        if let Ok(body) = body {
+                            debug!(
+                                request_id = body_request_id.as_deref(),
+                                provider = A::NAME;
+                                "[{}] {} error response: {}",
+                                body_request_id.as_ref().map_or("-", String::as_str),
+                                A::NAME,
+                                String::from_utf8_lossy(&body)
+                            );
+                        }
+
+                        Err(err)
+                    });
+                    return Either::B(Either::A(body));
+                }
+
+                let report = res.json::<A::Response>().map(move |res| (status, res.into()));
+                Either::B(Either::B(report))
+            }).map(move |(status, mut report): (StatusCode, WeatherReport)| {
+                if let Some(horizon) = horizon {
+                    report.data.retain(|data| data.date < horizon);
+                }
+
+                (status, report)
+            }).then(move |res| {
+                let elapsed = started.elapsed();
+                let error = res.as_ref().err().map(error_kind);
+                let status = match res {
+                    Ok((status, _)) => Some(status),
+                    Err(ref err) => err.status(),
+                };
+                let duration_ms = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+
+                info!(
+                    request_id = request_id.as_deref(),
+                    provider = A::NAME,
+                    city = city.as_str(),
+                    duration_ms = duration_ms,
+                    error_kind = error;
+                    "[{}] GET {} answered {} in {} ms{}",
+                    request_id.as_ref().map_or("-", String::as_str),
+                    logged_url,
+                    status.map_or("-".to_string(), |status| status.as_u16().to_string()),
+                    duration_ms,
+                    error.map_or(String::new(), |kind| format!(" with {} error", kind)),
+                );
+
+                if let Some(metrics) = metrics {
+                    let _ = metrics.do_send(RecordProviderCall {
+                        provider: A::NAME,
+                        error,
+                        elapsed,
+                    });
+                }
+
+                if let (Some(tracer), Some(request_id)) = (tracer, request_id) {
+                    let mut attributes = vec![("weather_api.provider", A::NAME.to_string())];
+                    if let Some(kind) = error {
+                        attributes.push(("error.type", kind.to_string()));
+                    }
+
+                    let _ = tracer.do_send(Span {
+                        request_id,
+                        name: A::NAME.to_string(),
+                        kind: SpanKind::Client,
+                        start: started_at,
+                        end: SystemTime::now(),
+                        attributes,
+                        failed: error.is_some(),
+                    });
+                }
+
+                res.map(|(_, report)| report).map_err(redact)
+            });
 
         Box::new(req)
     }
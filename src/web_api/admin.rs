@@ -0,0 +1,630 @@
+use actix::{Addr, Recipient};
+use actix_web::{http, App, FromRequest, HttpRequest, Json, Path};
+use failure::Error;
+use futures::{future, Future};
+
+use aggregator::{
+    Aggregator, AuditEntry, CacheKeyInfo, CacheStats, GetAuditLog, GetCacheStats, ListCache,
+    ListProviders, ProviderInfo, PurgeCache, RefreshCache, RemoveApi, SetApiEnabled, SetApiWeight,
+};
+use apis::WeatherQuery;
+use config::ProviderConfig;
+use providers::{AddProvider, ConfigReloader, ReloadConfig};
+
+use super::providers::ProviderList;
+use super::tracing::{request_id, traced};
+use super::{APIError, APIFuture, WebAPI};
+
+/// Доступ к администрированию кэша и погодных API агрегатора. Все запросы
+/// к маршрутам `/admin` должны содержать заголовок
+/// `Authorization: Bearer <token>`. Если задан `reloader`, через
+/// `/admin/reload` можно перезагрузить конфигурацию, а через
+/// `/admin/providers/{name}` - добавить погодный API.
+pub struct CacheAdmin {
+    token: String,
+    list: Recipient<ListCache>,
+    purge: Recipient<PurgeCache>,
+    refresh: Recipient<RefreshCache>,
+    stats: Recipient<GetCacheStats>,
+    providers: Recipient<ListProviders>,
+    remove: Recipient<RemoveApi>,
+    enable: Recipient<SetApiEnabled>,
+    weight: Recipient<SetApiWeight>,
+    audit: Recipient<GetAuditLog>,
+    reload: Option<Recipient<ReloadConfig>>,
+    add: Option<Recipient<AddProvider>>,
+}
+
+impl CacheAdmin {
+    pub fn new(token: String, aggregator: &Addr<Aggregator>) -> Self {
+        Self {
+            token,
+            list: aggregator.clone().recipient(),
+            purge: aggregator.clone().recipient(),
+            refresh: aggregator.clone().recipient(),
+            stats: aggregator.clone().recipient(),
+            providers: aggregator.clone().recipient(),
+            remove: aggregator.clone().recipient(),
+            enable: aggregator.clone().recipient(),
+            weight: aggregator.clone().recipient(),
+            audit: aggregator.clone().recipient(),
+            reload: None,
+            add: None,
+        }
+    }
+
+    pub fn reloader(mut self, reloader: &Addr<ConfigReloader>) -> Self {
+        self.reload = Some(reloader.clone().recipient());
+        self.add = Some(reloader.clone().recipient());
+
+        self
+    }
+}
+
+/// Новый вес погодного API.
+#[derive(Serialize, Deserialize)]
+struct WeightRequest {
+    weight: f32,
+}
+
+/// Число результатов API, удалённых из кэша.
+#[derive(Serialize, Deserialize)]
+struct PurgeResponse {
+    purged: usize,
+}
+
+type AdminResponder<D> = APIFuture<Json<D>>;
+
+/// Сравнивает строки за время, зависящее только от их длины, чтобы по
+/// времени ответа нельзя было подобрать токен администратора.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+impl WebAPI {
+    pub(super) fn admin_routes(app: App<Self>) -> App<Self> {
+        app.resource("/admin/cache", |r| {
+            r.method(http::Method::GET).f(Self::list_cache);
+            r.method(http::Method::DELETE).f(Self::purge_cache);
+        }).resource("/admin/cache/stats", |r| {
+            r.method(http::Method::GET).f(Self::cache_stats)
+        }).resource("/admin/cache/{country}/{city}", |r| {
+            r.method(http::Method::DELETE).f(Self::purge_cache_key)
+        }).resource("/admin/cache/{country}/{city}/refresh", |r| {
+            r.method(http::Method::POST).f(Self::refresh_cache_key)
+        }).resource("/admin/reload", |r| {
+            r.method(http::Method::POST).f(Self::reload_config)
+        }).resource("/admin/providers", |r| {
+            r.method(http::Method::GET).f(Self::list_all_providers)
+        }).resource("/admin/providers/{name}", |r| {
+            r.method(http::Method::POST).f(Self::add_provider);
+            r.method(http::Method::DELETE).f(Self::remove_provider);
+        }).resource("/admin/providers/{name}/enable", |r| {
+            r.method(http::Method::POST).f(|req| Self::enable_provider(req, true))
+        }).resource("/admin/providers/{name}/disable", |r| {
+            r.method(http::Method::POST).f(|req| Self::enable_provider(req, false))
+        }).resource("/admin/providers/{name}/weight", |r| {
+            r.method(http::Method::PUT).f(Self::set_provider_weight)
+        }).resource("/admin/audit", |r| {
+            r.method(http::Method::GET).f(Self::audit_log)
+        })
+    }
+
+    fn authorize(req: &HttpRequest<Self>) -> Result<&CacheAdmin, APIError> {
+        let admin = req.state().admin.as_ref().ok_or(APIError::Unauthorized)?;
+
+        let expected = format!("Bearer {}", admin.token);
+        let provided = req
+            .headers()
+            .get(http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim);
+
+        let authorized = provided.is_some_and(|provided| {
+            constant_time_eq(provided.as_bytes(), expected.as_bytes())
+        });
+        if authorized {
+            Ok(admin)
+        } else {
+            Err(APIError::Unauthorized)
+        }
+    }
+
+    fn list_cache(req: &HttpRequest<Self>) -> AdminResponder<Vec<CacheKeyInfo>> {
+        let admin = match Self::authorize(req) {
+            Ok(admin) => admin,
+            Err(reason) => return reason.into_responder(),
+        };
+
+        let keys = admin
+            .list
+            .send(ListCache)
+            .map(|keys| Ok(Json(keys)))
+            .map_err(|err| APIError::UnexpectedError(Error::from(err)));
+
+        Box::new(keys)
+    }
+
+    fn purge_cache(req: &HttpRequest<Self>) -> AdminResponder<PurgeResponse> {
+        Self::purge(req, None)
+    }
+
+    fn purge_cache_key(req: &HttpRequest<Self>) -> AdminResponder<PurgeResponse> {
+        match Path::<WeatherQuery>::extract(req) {
+            Ok(query) => Self::purge(req, Some(query.into_inner())),
+            Err(reason) => APIError::BadRequest(reason).into_responder(),
+        }
+    }
+
+    fn purge(
+        req: &HttpRequest<Self>,
+        query: Option<WeatherQuery>,
+    ) -> AdminResponder<PurgeResponse> {
+        let admin = match Self::authorize(req) {
+            Ok(admin) => admin,
+            Err(reason) => return reason.into_responder(),
+        };
+
+        let purged = admin
+            .purge
+            .send(PurgeCache(query))
+            .map(|purged| Ok(Json(PurgeResponse { purged })))
+            .map_err(|err| APIError::UnexpectedError(Error::from(err)));
+
+        Box::new(purged)
+    }
+
+    fn refresh_cache_key(req: &HttpRequest<Self>) -> AdminResponder<CacheKeyInfo> {
+        let admin = match Self::authorize(req) {
+            Ok(admin) => admin,
+            Err(reason) => return reason.into_responder(),
+        };
+
+        let query = match Path::<WeatherQuery>::extract(req) {
+            Ok(query) => traced(req, query.into_inner()),
+            Err(reason) => return APIError::BadRequest(reason).into_responder(),
+        };
+
+        let info = admin
+            .refresh
+            .send(RefreshCache(query))
+            .map(|res| match res {
+                Ok(info) => Ok(Json(info)),
+                Err(reason) => Err(APIError::from_aggregator(reason)),
+            }).map_err(|err| APIError::UnexpectedError(Error::from(err)));
+
+        Box::new(info)
+    }
+
+    fn cache_stats(req: &HttpRequest<Self>) -> AdminResponder<CacheStats> {
+        let admin = match Self::authorize(req) {
+            Ok(admin) => admin,
+            Err(reason) => return reason.into_responder(),
+        };
+
+        let stats = admin
+            .stats
+            .send(GetCacheStats)
+            .map(|stats| Ok(Json(stats)))
+            .map_err(|err| APIError::UnexpectedError(Error::from(err)));
+
+        Box::new(stats)
+    }
+
+    /// Перезагружает конфигурацию. Неверные настройки не применяются,
+    /// и ошибки в них возвращаются с кодом `422`.
+    fn reload_config(req: &HttpRequest<Self>) -> AdminResponder<ProviderList> {
+        let admin = match Self::authorize(req) {
+            Ok(admin) => admin,
+            Err(reason) => return reason.into_responder(),
+        };
+
+        let reload = match admin.reload {
+            Some(ref reload) => reload,
+            None => {
+                let reason = format_err!("configuration reload is not configured");
+                return APIError::UnexpectedError(reason).into_responder();
+            }
+        };
+
+        let reloaded = reload
+            .send(ReloadConfig)
+            .map(|res| match res {
+                Ok(providers) => Ok(Json(ProviderList { providers })),
+                Err(reason) => Err(APIError::from_config_change(reason)),
+            }).map_err(|err| APIError::UnexpectedError(Error::from(err)));
+
+        Box::new(reloaded)
+    }
+
+    /// Все погодные API агрегатора, включая отключённые.
+    fn list_all_providers(req: &HttpRequest<Self>) -> AdminResponder<ProviderList> {
+        let admin = match Self::authorize(req) {
+            Ok(admin) => admin,
+            Err(reason) => return reason.into_responder(),
+        };
+
+        let providers = admin
+            .providers
+            .send(ListProviders)
+            .map(|providers| Ok(Json(ProviderList { providers })))
+            .map_err(|err| APIError::UnexpectedError(Error::from(err)));
+
+        Box::new(providers)
+    }
+
+    /// Запускает погодный API с настройками из тела запроса (поля как
+    /// в таблице `[providers.<имя>]` файла настроек) и добавляет его
+    /// в агрегатор.
+    fn add_provider(req: &HttpRequest<Self>) -> AdminResponder<ProviderInfo> {
+        let admin = match Self::authorize(req) {
+            Ok(admin) => admin,
+            Err(reason) => return reason.into_responder(),
+        };
+
+        let add = match admin.add {
+            Some(ref add) => add.clone(),
+            None => {
+                let reason = format_err!("adding weather APIs is not configured");
+                return APIError::UnexpectedError(reason).into_responder();
+            }
+        };
+
+        let name = match Path::<String>::extract(req) {
+            Ok(name) => name.into_inner(),
+            Err(reason) => return APIError::BadRequest(reason).into_responder(),
+        };
+        let request_id = request_id(req);
+
+        let added = Json::<ProviderConfig>::extract(req)
+            .map_err(APIError::BadRequest)
+            .and_then(move |config| {
+                add.send(AddProvider {
+                    name,
+                    config: config.into_inner(),
+                    request_id,
+                }).map_err(|err| APIError::UnexpectedError(Error::from(err)))
+            }).map(|res| match res {
+                Ok(provider) => Ok(Json(provider)),
+                Err(reason) => Err(APIError::from_config_change(reason)),
+            });
+
+        Box::new(added)
+    }
+
+    fn remove_provider(req: &HttpRequest<Self>) -> AdminResponder<ProviderInfo> {
+        let admin = match Self::authorize(req) {
+            Ok(admin) => admin,
+            Err(reason) => return reason.into_responder(),
+        };
+
+        let name = match Path::<String>::extract(req) {
+            Ok(name) => name.into_inner(),
+            Err(reason) => return APIError::BadRequest(reason).into_responder(),
+        };
+
+        let removed = admin
+            .remove
+            .send(RemoveApi {
+                name,
+                request_id: request_id(req),
+            }).map(|res| match res {
+                Ok(provider) => Ok(Json(provider)),
+                Err(reason) => Err(APIError::from_config_change(reason)),
+            }).map_err(|err| APIError::UnexpectedError(Error::from(err)));
+
+        Box::new(removed)
+    }
+
+    fn enable_provider(req: &HttpRequest<Self>, enabled: bool) -> AdminResponder<ProviderInfo> {
+        let admin = match Self::authorize(req) {
+            Ok(admin) => admin,
+            Err(reason) => return reason.into_responder(),
+        };
+
+        let name = match Path::<String>::extract(req) {
+            Ok(name) => name.into_inner(),
+            Err(reason) => return APIError::BadRequest(reason).into_responder(),
+        };
+
+        let changed = admin
+            .enable
+            .send(SetApiEnabled {
+                name,
+                enabled,
+                request_id: request_id(req),
+            }).map(|res| match res {
+                Ok(provider) => Ok(Json(provider)),
+                Err(reason) => Err(APIError::from_config_change(reason)),
+            }).map_err(|err| APIError::UnexpectedError(Error::from(err)));
+
+        Box::new(changed)
+    }
+
+    fn set_provider_weight(req: &HttpRequest<Self>) -> AdminResponder<ProviderInfo> {
+        let admin = match Self::authorize(req) {
+            Ok(admin) => admin,
+            Err(reason) => return reason.into_responder(),
+        };
+
+        let weight = admin.weight.clone();
+        let name = match Path::<String>::extract(req) {
+            Ok(name) => name.into_inner(),
+            Err(reason) => return APIError::BadRequest(reason).into_responder(),
+        };
+        let request_id = request_id(req);
+
+        let changed = Json::<WeightRequest>::extract(req)
+            .map_err(APIError::BadRequest)
+            .and_then(move |body| {
+                let WeightRequest { weight: value } = body.into_inner();
+                if !value.is_finite() || value < 0.0 {
+                    let reason =
+                        format_err!("weight: must be a non-negative number, got {}", value);
+                    return future::Either::A(future::err(APIError::InvalidConfig(reason)));
+                }
+
+                let changed = weight
+                    .send(SetApiWeight {
+                        name,
+                        weight: value,
+                        request_id,
+                    }).map_err(|err| APIError::UnexpectedError(Error::from(err)));
+                future::Either::B(changed)
+            }).map(|res| match res {
+                Ok(provider) => Ok(Json(provider)),
+                Err(reason) => Err(APIError::from_config_change(reason)),
+            });
+
+        Box::new(changed)
+    }
+
+    /// Журнал изменений набора погодных API.
+    fn audit_log(req: &HttpRequest<Self>) -> AdminResponder<Vec<AuditEntry>> {
+        let admin = match Self::authorize(req) {
+            Ok(admin) => admin,
+            Err(reason) => return reason.into_responder(),
+        };
+
+        let entries = admin
+            .audit
+            .send(GetAuditLog)
+            .map(|entries| Ok(Json(entries)))
+            .map_err(|err| APIError::UnexpectedError(Error::from(err)));
+
+        Box::new(entries)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix::prelude::*;
+    use actix_web::{test, HttpMessage};
+    use chrono::Utc;
+
+    use super::*;
+    use aggregator::ForecastQuery;
+    use apis::{WeatherData, WeatherDataVec, WeatherReport};
+
+    struct TestWeatherActor;
+
+    impl Actor for TestWeatherActor {
+        type Context = SyncContext<Self>;
+    }
+
+    impl Handler<WeatherQuery> for TestWeatherActor {
+        type Result = Result<WeatherReport, Error>;
+
+        fn handle(&mut self, _msg: WeatherQuery, _ctx: &mut Self::Context) -> Self::Result {
+            let data: WeatherDataVec = smallvec![WeatherData {
+                temperature: 10.0,
+                date: Utc::now().naive_utc().date(),
+            }];
+
+            Ok(data.into())
+        }
+    }
+
+    #[test]
+    fn compares_tokens() {
+        assert!(constant_time_eq(b"Bearer secret", b"Bearer secret"));
+        assert!(!constant_time_eq(b"Bearer secreT", b"Bearer secret"));
+        assert!(!constant_time_eq(b"Bearer secret2", b"Bearer secret"));
+        assert!(!constant_time_eq(b"", b"Bearer secret"));
+    }
+
+    fn init_test_server() -> test::TestServer {
+        test::TestServer::build_with_state(|| {
+            let weather_actor = SyncArbiter::start(1, || TestWeatherActor {});
+            let aggregator = Aggregator::new()
+                .add_api("test", weather_actor.recipient())
+                .start();
+
+            let admin = CacheAdmin::new("secret".to_string(), &aggregator);
+
+            WebAPI::new(aggregator.recipient::<ForecastQuery>()).admin(admin)
+        }).start(|app: &mut test::TestApp<WebAPI>| {
+            app.resource("/admin/cache", |r| {
+                r.method(http::Method::GET).f(WebAPI::list_cache);
+                r.method(http::Method::DELETE).f(WebAPI::purge_cache);
+            }).resource("/admin/cache/stats", |r| {
+                r.method(http::Method::GET).f(WebAPI::cache_stats)
+            }).resource("/admin/cache/{country}/{city}/refresh", |r| {
+                r.method(http::Method::POST).f(WebAPI::refresh_cache_key)
+            }).resource("/admin/providers", |r| {
+                r.method(http::Method::GET).f(WebAPI::list_all_providers)
+            }).resource("/admin/providers/{name}/disable", |r| {
+                r.method(http::Method::POST).f(|req| WebAPI::enable_provider(req, false))
+            }).resource("/admin/providers/{name}/weight", |r| {
+                r.method(http::Method::PUT).f(WebAPI::set_provider_weight)
+            }).resource("/admin/audit", |r| {
+                r.method(http::Method::GET).f(WebAPI::audit_log)
+            });
+        })
+    }
+
+    #[test]
+    fn requires_token() {
+        let mut srv = init_test_server();
+
+        let request = srv
+            .client(http::Method::GET, "/admin/cache")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+
+        let request = srv
+            .client(http::Method::GET, "/admin/cache")
+            .header(http::header::AUTHORIZATION, "Bearer wrong")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn manages_cache() {
+        let mut srv = init_test_server();
+
+        let request = srv
+            .client(http::Method::POST, "/admin/cache/UK/London/refresh")
+            .header(http::header::AUTHORIZATION, "Bearer secret")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert!(response.status().is_success());
+
+        let info: CacheKeyInfo = srv
+            .execute(response.json())
+            .expect("Failed to parse response as JSON");
+        assert_eq!(info.providers, 1);
+
+        let request = srv
+            .client(http::Method::GET, "/admin/cache")
+            .header(http::header::AUTHORIZATION, "Bearer secret")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        let keys: Vec<CacheKeyInfo> = srv
+            .execute(response.json())
+            .expect("Failed to parse response as JSON");
+        assert_eq!(keys.len(), 1);
+
+        let request = srv
+            .client(http::Method::DELETE, "/admin/cache")
+            .header(http::header::AUTHORIZATION, "Bearer secret")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        let purged: PurgeResponse = srv
+            .execute(response.json())
+            .expect("Failed to parse response as JSON");
+        assert_eq!(purged.purged, 1);
+
+        let request = srv
+            .client(http::Method::GET, "/admin/cache/stats")
+            .header(http::header::AUTHORIZATION, "Bearer secret")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        let stats: CacheStats = srv
+            .execute(response.json())
+            .expect("Failed to parse response as JSON");
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.size, 0);
+    }
+
+    #[test]
+    fn manages_providers() {
+        let mut srv = init_test_server();
+
+        let request = srv
+            .client(http::Method::POST, "/admin/providers/test/disable")
+            .header(http::header::AUTHORIZATION, "Bearer secret")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        let provider: ProviderInfo = srv
+            .execute(response.json())
+            .expect("Failed to parse response as JSON");
+        assert!(!provider.enabled);
+
+        let request = srv
+            .client(http::Method::POST, "/admin/providers/unknown/disable")
+            .header(http::header::AUTHORIZATION, "Bearer secret")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+
+        let request = srv
+            .client(http::Method::PUT, "/admin/providers/test/weight")
+            .header(http::header::AUTHORIZATION, "Bearer secret")
+            .json(WeightRequest { weight: -1.0 })
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+        assert_eq!(response.status(), http::StatusCode::UNPROCESSABLE_ENTITY);
+
+        let request = srv
+            .client(http::Method::GET, "/admin/providers")
+            .header(http::header::AUTHORIZATION, "Bearer secret")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        let list: ProviderList = srv
+            .execute(response.json())
+            .expect("Failed to parse response as JSON");
+        assert_eq!(list.providers.len(), 1);
+        assert!(!list.providers[0].enabled);
+        assert_eq!(list.providers[0].weight, 1.0);
+
+        let request = srv
+            .client(http::Method::GET, "/admin/audit")
+            .header(http::header::AUTHORIZATION, "Bearer secret")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        let audit: Vec<AuditEntry> = srv
+            .execute(response.json())
+            .expect("Failed to parse response as JSON");
+        assert_eq!(audit.len(), 1);
+        assert_eq!(audit[0].action, "disable");
+        assert_eq!(audit[0].details, "disabled test");
+    }
+}
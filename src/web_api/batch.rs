@@ -0,0 +1,314 @@
+use actix::Recipient;
+use actix_web::{error, HttpMessage, HttpRequest};
+use chrono::NaiveDate;
+use futures::future::{self, Either};
+use futures::{stream, Future, Stream};
+
+use aggregator::{Forecast, ForecastQuery};
+use apis::{WeatherData, WeatherQuery};
+
+use super::format::{Days, Document, Format, Formatted, Negotiated, Table};
+use super::tracing::traced;
+use super::v2::EnvelopeError;
+use super::{APIError, APIFuture, WebAPI, WEEKLY_FORECAST_DAYS};
+
+/// Максимальное число городов в одном пакетном запросе.
+const MAX_BATCH_LOCATIONS: usize = 50;
+
+/// Сколько запросов из одного пакета одновременно ждут ответа агрегатора.
+const BATCH_CONCURRENCY: usize = 8;
+
+/// Прогноз, запрашиваемый для каждого города пакета. Параметры те же,
+/// что и у соответствующих маршрутов.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum BatchMode {
+    Daily { day: NaiveDate },
+    Weekly { days: Option<i64> },
+    Range { from: NaiveDate, to: NaiveDate },
+}
+
+#[derive(Serialize, Deserialize)]
+struct BatchRequest {
+    locations: Vec<WeatherQuery>,
+    mode: BatchMode,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum BatchData {
+    Day(WeatherData),
+    Days(Days),
+}
+
+/// Результат по одному городу пакета: либо данные, либо ошибка.
+#[derive(Serialize)]
+struct BatchResult {
+    location: WeatherQuery,
+    data: Option<BatchData>,
+    error: Option<EnvelopeError>,
+}
+
+/// Результаты в том же порядке, в котором города перечислены в запросе.
+#[derive(Serialize)]
+pub(super) struct BatchResponse {
+    results: Vec<BatchResult>,
+}
+
+/// В CSV по строке на каждый день каждого города, у дней без прогноза
+/// температура пустая. Для города с ошибкой строка одна, с пустыми датой
+/// и температурой.
+impl Document for BatchResponse {
+    fn table(&self) -> Table {
+        let mut rows = Vec::new();
+
+        for result in &self.results {
+            let location = result.location.to_string();
+
+            match (&result.data, &result.error) {
+                (Some(BatchData::Day(data)), _) => {
+                    rows.push(row(&location, data.date, Some(data)))
+                }
+                (Some(BatchData::Days(days)), _) => {
+                    rows.extend(days.dated().map(|(date, data)| row(&location, date, data)))
+                }
+                (None, error) => rows.push(vec![
+                    location,
+                    String::new(),
+                    String::new(),
+                    error.as_ref().map_or(String::new(), |err| err.message.clone()),
+                ]),
+            }
+        }
+
+        Table {
+            columns: &["location", "date", "temperature", "error"],
+            rows,
+        }
+    }
+}
+
+fn row(location: &str, date: NaiveDate, data: Option<&WeatherData>) -> Vec<String> {
+    let mut row = vec![location.to_string()];
+    row.extend(Days::row(date, data));
+    row.push(String::new());
+
+    row
+}
+
+type BatchFuture = Box<Future<Item = BatchResult, Error = APIError>>;
+
+type BatchResponder =
+    Box<Future<Item = Formatted<BatchResponse>, Error = Negotiated<APIError>>>;
+
+impl WebAPI {
+    /// Прогноз сразу для нескольких городов. Ошибка по одному городу
+    /// не мешает остальным и возвращается в его результате.
+    pub(super) fn batch_forecast(req: &HttpRequest<Self>) -> BatchResponder {
+        let format = match Format::negotiate(req) {
+            Ok(format) => format,
+            Err(reason) => return Box::new(future::err(Negotiated(reason, Format::Json))),
+        };
+
+        let response = Self::fetch_batch(req)
+            .map(move |response| Formatted(response, format))
+            .map_err(move |reason| Negotiated(reason, format));
+
+        Box::new(response)
+    }
+
+    /// Разбирает тело пакетного запроса и собирает результаты по всем его
+    /// городам.
+    pub(super) fn fetch_batch(
+        req: &HttpRequest<Self>,
+    ) -> Box<Future<Item = BatchResponse, Error = APIError>> {
+        let aggregator = req.state().aggregator.clone();
+        let request = req.clone();
+
+        let results = req
+            .json()
+            .map_err(|err| APIError::BadRequest(err.into()))
+            .and_then(move |batch: BatchRequest| {
+                if batch.locations.len() > MAX_BATCH_LOCATIONS {
+                    let reason = error::ErrorBadRequest(format!(
+                        "batch may contain at most {} locations",
+                        MAX_BATCH_LOCATIONS
+                    ));
+                    return Either::A(future::err(APIError::BadRequest(reason)));
+                }
+
+                let mode = batch.mode;
+                let results = stream::iter_ok(batch.locations)
+                    .map(move |location| {
+                        Self::batch_item(&aggregator, traced(&request, location), mode)
+                    })
+                    .buffered(BATCH_CONCURRENCY)
+                    .collect()
+                    .map(|results| BatchResponse { results });
+
+                Either::B(results)
+            });
+
+        Box::new(results)
+    }
+
+    fn batch_item(
+        aggregator: &Recipient<ForecastQuery>,
+        location: WeatherQuery,
+        mode: BatchMode,
+    ) -> BatchFuture {
+        let query = location.clone();
+
+        let data: APIFuture<(BatchData, Forecast)> = match mode {
+            BatchMode::Daily { day } => Box::new(
+                Self::daily(aggregator, query, day)
+                    .map(|res| res.map(|(data, forecast)| (BatchData::Day(data), forecast))),
+            ),
+            BatchMode::Weekly { days } => Box::new(
+                Self::weekly(aggregator, query, days.unwrap_or(WEEKLY_FORECAST_DAYS))
+                    .map(|res| res.map(|(data, forecast)| (BatchData::Days(data), forecast))),
+            ),
+            BatchMode::Range { from, to } => Box::new(
+                Self::range(aggregator, query, from, to)
+                    .map(|res| res.map(|(data, forecast)| (BatchData::Days(data), forecast))),
+            ),
+        };
+
+        let result = data.then(move |res| {
+            let result = match res {
+                Ok(Ok((data, _))) => BatchResult {
+                    location,
+                    data: Some(data),
+                    error: None,
+                },
+                Ok(Err(reason)) | Err(reason) => {
+                    if let APIError::UnexpectedError(ref err) = reason {
+                        error!("Batch forecast for {} failed: {}", location, err);
+                    }
+
+                    BatchResult {
+                        location,
+                        data: None,
+                        error: Some(EnvelopeError::from(&reason)),
+                    }
+                }
+            };
+
+            Ok(result)
+        });
+
+        Box::new(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix::prelude::*;
+    use actix_web::{http, test};
+    use chrono::Utc;
+    use failure::Error;
+    use serde_json::Value;
+
+    use super::*;
+    use aggregator::UnknownLocation;
+
+    struct TestWeatherActor;
+
+    impl Actor for TestWeatherActor {
+        type Context = SyncContext<Self>;
+    }
+
+    impl Handler<ForecastQuery> for TestWeatherActor {
+        type Result = Result<Forecast, Error>;
+
+        fn handle(&mut self, msg: ForecastQuery, _ctx: &mut Self::Context) -> Self::Result {
+            let ForecastQuery(query) = msg;
+
+            if query != WeatherQuery::new("UK".to_string(), "London".to_string()) {
+                return Err(Error::from(UnknownLocation(query)));
+            }
+
+            Ok(Forecast {
+                query,
+                data: smallvec![WeatherData {
+                    temperature: 10.0,
+                    date: Utc::now().naive_utc().date(),
+                }],
+                sources: vec!["test".to_string()],
+                utc_offset: None,
+                fetched_at: Utc::now(),
+                expires_at: Utc::now(),
+            })
+        }
+    }
+
+    fn init_test_server() -> test::TestServer {
+        test::TestServer::build_with_state(|| {
+            let weather_actor = SyncArbiter::start(1, || TestWeatherActor {});
+            WebAPI::new(weather_actor.recipient())
+        }).start(|app: &mut test::TestApp<WebAPI>| {
+            app.resource("/forecast/batch", |r| {
+                r.method(http::Method::POST).f(WebAPI::batch_forecast)
+            });
+        })
+    }
+
+    #[test]
+    fn returns_results_per_location() {
+        let mut srv = init_test_server();
+
+        let request = srv
+            .client(http::Method::POST, "/forecast/batch")
+            .json(json!({
+                "locations": [
+                    { "country": "UK", "city": "London" },
+                    { "country": "UK", "city": "Londn" }
+                ],
+                "mode": { "type": "weekly", "days": 3 }
+            })).expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert!(response.status().is_success());
+
+        let batch: Value = srv
+            .execute(response.json())
+            .expect("Failed to parse response as JSON");
+        let results = batch["results"].as_array().expect("Results are missing");
+
+        assert_eq!(results.len(), 2);
+
+        let days = results[0]["data"]
+            .as_array()
+            .expect("Expected weekly forecast for London");
+        assert_eq!(days.len(), 3);
+        assert!(days[0].is_object());
+        assert!(days[1].is_null());
+        assert!(results[0]["error"].is_null());
+
+        assert!(results[1]["data"].is_null());
+        assert_eq!(results[1]["error"]["code"], "unknown_location");
+    }
+
+    #[test]
+    fn limits_batch_size() {
+        let mut srv = init_test_server();
+
+        let locations: Vec<_> = (0..MAX_BATCH_LOCATIONS + 1)
+            .map(|i| WeatherQuery::new("UK".to_string(), format!("City{}", i)))
+            .collect();
+
+        let request = srv
+            .client(http::Method::POST, "/forecast/batch")
+            .json(json!({
+                "locations": locations,
+                "mode": { "type": "weekly" }
+            })).expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+    }
+}
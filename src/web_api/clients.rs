@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+use actix::Recipient;
+use actix_web::middleware::{Middleware, Started};
+use actix_web::{HttpRequest, Result};
+use failure::Error;
+use futures::Future;
+
+use clients::{CheckClient, Rejection};
+
+use super::format::{Format, Render};
+use super::v2::V2Error;
+use super::APIError;
+
+/// Заголовок с ключом клиента.
+pub(super) const API_KEY_HEADER: &str = "x-api-key";
+
+/// Параметр запроса с ключом клиента, для тех, кто не может передать
+/// заголовок (например, `EventSource` в браузере).
+const API_KEY_PARAM: &str = "api_key";
+
+/// Маршруты, доступные без ключа: метрики и описание API.
+const PUBLIC_PATHS: [&str; 2] = ["/metrics", "/openapi.json"];
+
+/// Группы маршрутов, доступные без ключа: проверки состояния и
+/// администрирование, у которого свой токен.
+const PUBLIC_PREFIXES: [&str; 2] = ["/health/", "/admin/"];
+
+/// Ключ из заголовка `X-API-Key` или, если его нет, из параметра `api_key`.
+fn provided_key<S>(req: &HttpRequest<S>) -> Option<String> {
+    let header = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .map(str::to_string);
+
+    header.or_else(|| req.query().get(API_KEY_PARAM).cloned())
+}
+
+/// Через сколько целых секунд можно повторить запрос.
+fn retry_after_secs(retry_after: Duration) -> u64 {
+    let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    secs.max(1)
+}
+
+/// Промежуточный обработчик, пропускающий запросы только с известным
+/// ключом клиента и в пределах его лимита. Без ключа запрос отклоняется
+/// с кодом `401`, сверх лимита - с кодом `429` и заголовком `Retry-After`.
+/// Ошибки API v2 отдаются в конверте.
+pub(super) struct ClientAuth(pub(super) Recipient<CheckClient>);
+
+impl<S> Middleware<S> for ClientAuth {
+    fn start(&self, req: &HttpRequest<S>) -> Result<Started> {
+        let path = req.path();
+        if PUBLIC_PATHS.contains(&path)
+            || PUBLIC_PREFIXES.iter().any(|prefix| path.starts_with(prefix))
+        {
+            return Ok(Started::Done);
+        }
+
+        let format = Format::negotiate(req).unwrap_or(Format::Json);
+        let v2 = req.path().starts_with("/v2/");
+        let checked = self
+            .0
+            .send(CheckClient(provided_key(req)))
+            .then(move |res| {
+                let reason = match res {
+                    Ok(Ok(_)) => return Ok(None),
+                    Ok(Err(Rejection::InvalidKey)) => APIError::InvalidApiKey,
+                    Ok(Err(Rejection::RateLimited(retry_after))) => {
+                        APIError::RateLimited(retry_after_secs(retry_after))
+                    }
+                    Err(err) => APIError::UnexpectedError(Error::from(err)),
+                };
+
+                if v2 {
+                    Ok(Some(V2Error(reason).render(format)))
+                } else {
+                    Ok(Some(reason.render(format)))
+                }
+            });
+
+        Ok(Started::Future(Box::new(checked)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix::prelude::*;
+    use actix_web::{http, test, HttpMessage};
+    use chrono::Utc;
+    use serde_json::Value;
+
+    use super::*;
+    use aggregator::Aggregator;
+    use apis::{WeatherData, WeatherQuery, WeatherReport};
+    use clients::{ClientKey, Clients};
+    use web_api::{HealthCheck, WebAPI};
+
+    struct TestWeatherActor;
+
+    impl Actor for TestWeatherActor {
+        type Context = SyncContext<Self>;
+    }
+
+    impl Handler<WeatherQuery> for TestWeatherActor {
+        type Result = Result<WeatherReport, Error>;
+
+        fn handle(&mut self, _msg: WeatherQuery, _ctx: &mut Self::Context) -> Self::Result {
+            Ok(WeatherReport::from(smallvec![WeatherData {
+                temperature: 10.0,
+                date: Utc::now().naive_utc().date(),
+            }]))
+        }
+    }
+
+    #[test]
+    fn requires_api_key() {
+        let mut srv = test::TestServer::with_factory(|| {
+            let weather_actor = SyncArbiter::start(1, || TestWeatherActor);
+            let aggregator = Aggregator::new()
+                .add_api("test", weather_actor.recipient())
+                .start();
+            let clients = Clients::new(vec![ClientKey {
+                name: "mobile".to_string(),
+                key: "secret".to_string(),
+                rate_limit: Some(2),
+            }])
+            .start();
+
+            WebAPI::new(aggregator.clone().recipient())
+                .health(HealthCheck::new(1, &aggregator))
+                .clients(clients.recipient())
+                .app()
+        });
+
+        let mut get = |uri: &str, key: Option<&str>| {
+            let mut request = srv.client(http::Method::GET, uri);
+            if let Some(key) = key {
+                request.header(API_KEY_HEADER, key);
+            }
+            let request = request.finish().expect("Failed to construct test request");
+
+            srv.execute(request.send())
+                .expect("Failed to send test request")
+        };
+
+        let response = get("/forecast/weekly/UK/London", None);
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+        let response = get("/forecast/weekly/UK/London", Some("wrong"));
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+
+        let response = get("/health/live", None);
+        assert_eq!(response.status(), http::StatusCode::OK);
+        let response = get("/openapi.json", None);
+        assert_eq!(response.status(), http::StatusCode::OK);
+        let response = get("/openapi.json/extra", None);
+        assert_eq!(response.status(), http::StatusCode::UNAUTHORIZED);
+
+        let response = get("/forecast/weekly/UK/London", Some("secret"));
+        assert_eq!(response.status(), http::StatusCode::OK);
+        let response = get("/forecast/weekly/UK/London?api_key=secret", None);
+        assert_eq!(response.status(), http::StatusCode::OK);
+
+        let response = get("/v2/forecast/weekly/UK/London", Some("secret"));
+        assert_eq!(response.status(), http::StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = response.headers()[http::header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(retry_after > 0 && retry_after <= 60);
+        let body: Value = srv
+            .execute(response.json())
+            .expect("Failed to parse response");
+        assert_eq!(body["errors"][0]["code"], "rate_limited");
+    }
+}
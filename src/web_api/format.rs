@@ -0,0 +1,331 @@
+use std::cmp::Reverse;
+use std::fmt;
+
+use actix_web::http::header;
+use actix_web::{error, http, HttpMessage, HttpRequest, HttpResponse, Responder};
+use chrono::{Duration, NaiveDate};
+use csv;
+use failure::{Error, Fail};
+use quick_xml;
+use rmp_serde;
+use serde::{Serialize, Serializer};
+use serde_json;
+
+use apis::WeatherData;
+
+use super::APIError;
+
+/// Формат ответа. Выбирается параметром `?format=`, а если его нет -
+/// по заголовку `Accept`. Если клиент не принимает ни один из
+/// поддерживаемых форматов, то ответ отдаётся в JSON.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum Format {
+    Json,
+    Csv,
+    Xml,
+    MessagePack,
+}
+
+impl Format {
+    pub(super) fn negotiate<S>(req: &HttpRequest<S>) -> Result<Self, APIError> {
+        if let Some(name) = req.query().get("format") {
+            return Self::from_name(name).ok_or_else(|| {
+                let reason = format!("unsupported format `{}`", name);
+                APIError::BadRequest(error::ErrorBadRequest(reason))
+            });
+        }
+
+        let mut accepted = match req.get_header::<header::Accept>() {
+            Some(header::Accept(accepted)) => accepted,
+            None => return Ok(Format::Json),
+        };
+        accepted.sort_by_key(|item| Reverse(item.quality));
+
+        let format = accepted
+            .iter()
+            .filter_map(|item| {
+                Self::from_mime(item.item.type_().as_str(), item.item.subtype().as_str())
+            }).next()
+            .unwrap_or(Format::Json);
+
+        Ok(format)
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(Format::Json),
+            "csv" => Some(Format::Csv),
+            "xml" => Some(Format::Xml),
+            "msgpack" => Some(Format::MessagePack),
+            _ => None,
+        }
+    }
+
+    fn from_mime(type_: &str, subtype: &str) -> Option<Self> {
+        match (type_, subtype) {
+            ("application", "json") | ("*", "*") | ("application", "*") => Some(Format::Json),
+            ("text", "csv") => Some(Format::Csv),
+            ("application", "xml") | ("text", "xml") => Some(Format::Xml),
+            ("application", "msgpack") | ("application", "x-msgpack") => Some(Format::MessagePack),
+            _ => None,
+        }
+    }
+
+    pub(super) fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Xml => "application/xml",
+            Format::MessagePack => "application/msgpack",
+        }
+    }
+
+    pub(super) fn serialize<D: Document>(self, data: &D) -> Result<Vec<u8>, Error> {
+        match self {
+            Format::Json => Ok(serde_json::to_vec(data)?),
+            Format::Csv => {
+                let table = data.table();
+                let mut writer = csv::Writer::from_writer(Vec::new());
+
+                writer.write_record(table.columns)?;
+                for row in table.rows {
+                    writer.write_record(&row)?;
+                }
+
+                Ok(writer.into_inner().map_err(|err| err.into_error())?)
+            }
+            Format::Xml => Ok(data.xml()?.into_bytes()),
+            Format::MessagePack => Ok(rmp_serde::to_vec_named(data)?),
+        }
+    }
+
+    /// Ответ с данными в этом формате.
+    pub(super) fn respond<D: Document>(self, status: http::StatusCode, data: &D) -> HttpResponse {
+        match self.serialize(data) {
+            Ok(body) => HttpResponse::build(status)
+                .content_type(self.content_type())
+                .header(header::VARY, "Accept")
+                .body(body),
+            Err(err) => {
+                error!("Failed to serialize response as {:?}: {}", self, err);
+                HttpResponse::InternalServerError().finish()
+            }
+        }
+    }
+}
+
+/// Таблица для CSV: заголовок и строки.
+pub(super) struct Table {
+    pub(super) columns: &'static [&'static str],
+    pub(super) rows: Vec<Vec<String>>,
+}
+
+/// Ответ, который можно отдать в любом из форматов. JSON и MessagePack
+/// строятся по `Serialize` как есть, для CSV ответ разворачивается
+/// в таблицу, а в XML у документа должен быть один корневой элемент.
+pub(super) trait Document: Serialize {
+    fn table(&self) -> Table;
+
+    fn xml(&self) -> Result<String, quick_xml::DeError> {
+        quick_xml::se::to_string_with_root("response", self)
+    }
+}
+
+const FORECAST_COLUMNS: &[&str] = &["date", "temperature"];
+
+impl WeatherData {
+    fn row(&self) -> Vec<String> {
+        vec![self.date.to_string(), self.temperature.to_string()]
+    }
+}
+
+impl Document for WeatherData {
+    fn table(&self) -> Table {
+        Table {
+            columns: FORECAST_COLUMNS,
+            rows: vec![self.row()],
+        }
+    }
+
+    fn xml(&self) -> Result<String, quick_xml::DeError> {
+        quick_xml::se::to_string_with_root("forecast", self)
+    }
+}
+
+/// Прогноз на каждый день подряд начиная с `from`. В JSON и MessagePack
+/// отдаётся списком, в котором дни без прогноза - `null`.
+#[derive(Debug)]
+pub(super) struct Days {
+    pub(super) from: NaiveDate,
+    pub(super) data: Vec<Option<WeatherData>>,
+}
+
+impl Days {
+    /// Дата каждого дня и прогноз на него, если он есть.
+    pub(super) fn dated(&self) -> impl Iterator<Item = (NaiveDate, Option<&WeatherData>)> {
+        let from = self.from;
+
+        self.data
+            .iter()
+            .enumerate()
+            .map(move |(offset, data)| (from + Duration::days(offset as i64), data.as_ref()))
+    }
+
+    /// Строка CSV на день: если прогноза нет, температура пустая.
+    pub(super) fn row(date: NaiveDate, data: Option<&WeatherData>) -> Vec<String> {
+        let temperature = data.map_or(String::new(), |data| data.temperature.to_string());
+
+        vec![date.to_string(), temperature]
+    }
+}
+
+impl Serialize for Days {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.data.serialize(serializer)
+    }
+}
+
+/// Прогноз по дням: в CSV и XML по строке на каждый день, у дней без
+/// прогноза температура пустая.
+impl Document for Days {
+    fn table(&self) -> Table {
+        Table {
+            columns: FORECAST_COLUMNS,
+            rows: self.dated().map(|(date, data)| Self::row(date, data)).collect(),
+        }
+    }
+
+    fn xml(&self) -> Result<String, quick_xml::DeError> {
+        #[derive(Serialize)]
+        struct Day {
+            temperature: Option<f32>,
+            date: NaiveDate,
+        }
+
+        #[derive(Serialize)]
+        struct Forecast {
+            day: Vec<Day>,
+        }
+
+        let day = self
+            .dated()
+            .map(|(date, data)| Day {
+                temperature: data.map(|data| data.temperature),
+                date,
+            }).collect();
+
+        quick_xml::se::to_string_with_root("forecast", &Forecast { day })
+    }
+}
+
+/// Ответ без кэширования в согласованном формате.
+pub(super) struct Formatted<D>(pub(super) D, pub(super) Format);
+
+impl<D: Document> Responder for Formatted<D> {
+    type Item = HttpResponse;
+    type Error = error::Error;
+
+    fn respond_to<S>(self, _req: &HttpRequest<S>) -> Result<HttpResponse, error::Error> {
+        Ok(self.1.respond(http::StatusCode::OK, &self.0))
+    }
+}
+
+/// Ошибка вместе с форматом, в котором её нужно отдать клиенту.
+#[derive(Debug)]
+pub(super) struct Negotiated<E>(pub(super) E, pub(super) Format);
+
+/// Ошибка, которая умеет отдавать себя в любом из форматов.
+pub(super) trait Render {
+    fn render(&self, format: Format) -> HttpResponse;
+}
+
+impl<E: fmt::Display> fmt::Display for Negotiated<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<E: Fail> Fail for Negotiated<E> {
+    fn cause(&self) -> Option<&Fail> {
+        self.0.cause()
+    }
+}
+
+impl<E: Fail + Render> error::ResponseError for Negotiated<E> {
+    fn error_response(&self) -> HttpResponse {
+        self.0.render(self.1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::test::TestRequest;
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn days() -> Days {
+        Days {
+            from: NaiveDate::from_ymd_opt(2018, 10, 1).expect("Invalid date"),
+            data: vec![
+                None,
+                Some(WeatherData {
+                    temperature: 10.5,
+                    date: NaiveDate::from_ymd_opt(2018, 10, 2).expect("Invalid date"),
+                }),
+                None,
+            ],
+        }
+    }
+
+    #[test]
+    fn negotiates_format() {
+        let req = TestRequest::with_uri("/?format=csv")
+            .header(header::ACCEPT, "application/xml")
+            .finish();
+        assert_eq!(Format::negotiate(&req).ok(), Some(Format::Csv));
+
+        let req = TestRequest::with_uri("/?format=yaml").finish();
+        assert!(Format::negotiate(&req).is_err());
+
+        let req = TestRequest::default()
+            .header(header::ACCEPT, "text/html, application/json;q=0.5, text/csv;q=0.9")
+            .finish();
+        assert_eq!(Format::negotiate(&req).ok(), Some(Format::Csv));
+
+        let req = TestRequest::default()
+            .header(header::ACCEPT, "text/html")
+            .finish();
+        assert_eq!(Format::negotiate(&req).ok(), Some(Format::Json));
+    }
+
+    #[test]
+    fn serializes_forecast() {
+        let csv = Format::Csv.serialize(&days()).expect("Failed to write CSV");
+        assert_eq!(
+            String::from_utf8(csv).expect("CSV is not UTF-8"),
+            "date,temperature\n2018-10-01,\n2018-10-02,10.5\n2018-10-03,\n"
+        );
+
+        let xml = Format::Xml.serialize(&days()).expect("Failed to write XML");
+        assert_eq!(
+            String::from_utf8(xml).expect("XML is not UTF-8"),
+            concat!(
+                "<forecast>",
+                "<day><temperature/><date>2018-10-01</date></day>",
+                "<day><temperature>10.5</temperature><date>2018-10-02</date></day>",
+                "<day><temperature/><date>2018-10-03</date></day>",
+                "</forecast>"
+            )
+        );
+
+        let msgpack = Format::MessagePack
+            .serialize(&days())
+            .expect("Failed to write MessagePack");
+        let decoded: Vec<Option<WeatherData>> =
+            rmp_serde::from_slice(&msgpack).expect("Failed to read MessagePack");
+        assert_eq!(decoded.len(), 3);
+        assert!(decoded[0].is_none());
+        assert!(decoded[1].is_some());
+    }
+}
@@ -0,0 +1,522 @@
+use actix::{Actor, Addr, Handler, Message, SyncContext};
+use actix_web::{http, App, HttpMessage, HttpRequest, HttpResponse};
+use chrono::{DateTime, NaiveDate, Utc};
+use failure::Error;
+use futures::Future;
+use juniper::http::GraphQLRequest;
+use juniper::{
+    graphql_object, graphql_value, EmptyMutation, EmptySubscription, FieldError, FieldResult,
+    GraphQLObject, RootNode,
+};
+use serde_json;
+
+use aggregator::{
+    Aggregator, Forecast, ForecastQuery, GetHourlyForecast, GetProviderForecasts,
+    GetProviderHealth, ProviderForecast, ProviderHealth,
+};
+use apis::{HourlyData, WeatherData, WeatherQuery};
+
+use super::tracing::request_id;
+use super::{APIError, APIFuture, WebAPI, MAX_FORECAST_DAYS, WEEKLY_FORECAST_DAYS};
+
+/// Длина почасового прогноза по умолчанию в часах.
+const HOURLY_FORECAST_HOURS: i64 = 24;
+
+/// Доступ резолверов к агрегатору. Резолверы выполняются в потоках
+/// `SyncArbiter`, поэтому ждут ответа агрегатора синхронно. Juniper
+/// требует от контекста `Sync`, поэтому здесь адрес, а не `Recipient`.
+pub struct GraphQLContext {
+    aggregator: Addr<Aggregator>,
+    request_id: Option<String>,
+}
+
+impl juniper::Context for GraphQLContext {}
+
+impl GraphQLContext {
+    fn forecast(&self, query: WeatherQuery) -> Result<Forecast, APIError> {
+        let query = match self.request_id {
+            Some(ref id) => query.with_request_id(id.clone()),
+            None => query,
+        };
+
+        match self.aggregator.send(ForecastQuery(query)).wait() {
+            Ok(Ok(forecast)) => Ok(forecast),
+            Ok(Err(reason)) => Err(APIError::from_aggregator(reason)),
+            Err(err) => Err(APIError::UnexpectedError(Error::from(err))),
+        }
+    }
+}
+
+/// Ошибка поля с тем же кодом и описанием, что и в REST API.
+fn field_error(err: APIError) -> FieldError {
+    if let APIError::UnexpectedError(ref reason) = err {
+        error!("GraphQL query failed: {}", reason);
+    }
+
+    let code = err.code();
+    FieldError::new(err.message(), graphql_value!({ "code": code }))
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "Average temperature for a day, null if no API has data for it")]
+struct Day {
+    date: NaiveDate,
+    temperature: Option<f64>,
+}
+
+impl From<&WeatherData> for Day {
+    fn from(data: &WeatherData) -> Self {
+        Self {
+            date: data.date,
+            temperature: Some(f64::from(data.temperature)),
+        }
+    }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "Average temperature for an hour")]
+struct Hour {
+    time: DateTime<Utc>,
+    temperature: f64,
+}
+
+impl From<HourlyData> for Hour {
+    fn from(data: HourlyData) -> Self {
+        Self {
+            time: data.time,
+            temperature: f64::from(data.temperature),
+        }
+    }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(name = "ProviderForecast", description = "Forecast reported by a single weather API")]
+struct ProviderValues {
+    name: String,
+    fetched_at: DateTime<Utc>,
+    daily: Vec<Day>,
+}
+
+impl From<ProviderForecast> for ProviderValues {
+    fn from(forecast: ProviderForecast) -> Self {
+        Self {
+            daily: forecast.data.iter().map(Day::from).collect(),
+            name: forecast.name,
+            fetched_at: forecast.fetched_at,
+        }
+    }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(
+    name = "ProviderHealth",
+    description = "Health of a weather API according to the latest requests to it"
+)]
+struct ProviderStatus {
+    name: String,
+    healthy: bool,
+    last_success: Option<DateTime<Utc>>,
+    last_failure: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+    consecutive_failures: i32,
+    #[graphql(description = "Share of failed requests among the latest ones")]
+    error_rate: f64,
+    #[graphql(description = "Average response time of the latest requests in milliseconds")]
+    latency_ms: Option<i32>,
+}
+
+impl From<ProviderHealth> for ProviderStatus {
+    fn from(health: ProviderHealth) -> Self {
+        Self {
+            name: health.name,
+            healthy: health.healthy,
+            last_success: health.last_success,
+            last_failure: health.last_failure,
+            last_error: health.last_error,
+            consecutive_failures: health.consecutive_failures as i32,
+            error_rate: f64::from(health.error_rate),
+            latency_ms: health.latency_ms.map(|latency| latency as i32),
+        }
+    }
+}
+
+/// Агрегированный прогноз по городу.
+struct Location(Forecast);
+
+#[graphql_object(
+    context = GraphQLContext,
+    description = "Aggregated forecast for a location"
+)]
+impl Location {
+    fn country(&self) -> &str {
+        self.0.query.country()
+    }
+
+    fn city(&self) -> &str {
+        self.0.query.city()
+    }
+
+    #[graphql(description = "Weather APIs the forecast is aggregated from")]
+    fn sources(&self) -> Vec<String> {
+        self.0.sources.clone()
+    }
+
+    #[graphql(description = "Offset of the local time from UTC in seconds, if any API reported it")]
+    fn utc_offset(&self) -> Option<i32> {
+        self.0.utc_offset.map(|offset| offset.local_minus_utc())
+    }
+
+    #[graphql(description = "Today's date in the location")]
+    fn today(&self) -> NaiveDate {
+        self.0.today()
+    }
+
+    fn fetched_at(&self) -> DateTime<Utc> {
+        self.0.fetched_at
+    }
+
+    fn expires_at(&self) -> DateTime<Utc> {
+        self.0.expires_at
+    }
+
+    #[graphql(description = "Forecast for every day starting from `from` (today by default)")]
+    fn daily(&self, from: Option<NaiveDate>, days: Option<i32>) -> FieldResult<Vec<Day>> {
+        let days = days.map_or(WEEKLY_FORECAST_DAYS, i64::from);
+        if !(1..=MAX_FORECAST_DAYS).contains(&days) {
+            let reason = format!("length must be between 1 and {} days", MAX_FORECAST_DAYS);
+            return Err(field_error(APIError::InvalidRange(reason)));
+        }
+
+        let from = from.unwrap_or_else(|| self.0.today());
+        let data = (0..days)
+            .map(|offset| from + chrono::Duration::days(offset))
+            .map(|date| match self.0.data.iter().find(|e| e.date == date) {
+                Some(data) => Day::from(data),
+                None => Day {
+                    date,
+                    temperature: None,
+                },
+            }).collect();
+
+        Ok(data)
+    }
+
+    #[graphql(description = "Forecast for the hours starting from `from` (now by default), \
+                             only from the weather APIs that report hourly values")]
+    fn hourly(
+        &self,
+        context: &GraphQLContext,
+        from: Option<DateTime<Utc>>,
+        hours: Option<i32>,
+    ) -> FieldResult<Vec<Hour>> {
+        let hours = hours.map_or(HOURLY_FORECAST_HOURS, i64::from);
+        let max_hours = MAX_FORECAST_DAYS * 24;
+        if !(1..=max_hours).contains(&hours) {
+            let reason = format!("length must be between 1 and {} hours", max_hours);
+            return Err(field_error(APIError::InvalidRange(reason)));
+        }
+
+        let from = from.unwrap_or_else(Utc::now);
+        let to = from + chrono::Duration::hours(hours);
+        let hourly = context
+            .aggregator
+            .send(GetHourlyForecast(self.0.query.clone()))
+            .wait()
+            .map_err(|err| field_error(APIError::UnexpectedError(Error::from(err))))?;
+
+        Ok(hourly
+            .into_iter()
+            .filter(|data| from <= data.time && data.time < to)
+            .map(Hour::from)
+            .collect())
+    }
+
+    #[graphql(description = "Forecasts of the individual weather APIs")]
+    fn providers(&self, context: &GraphQLContext) -> FieldResult<Vec<ProviderValues>> {
+        let forecasts = context
+            .aggregator
+            .send(GetProviderForecasts(self.0.query.clone()))
+            .wait()
+            .map_err(|err| field_error(APIError::UnexpectedError(Error::from(err))))?;
+
+        Ok(forecasts.into_iter().map(ProviderValues::from).collect())
+    }
+}
+
+pub struct Query;
+
+#[graphql_object(context = GraphQLContext)]
+impl Query {
+    #[graphql(description = "Aggregated forecast for a location")]
+    fn location(context: &GraphQLContext, country: String, city: String) -> FieldResult<Location> {
+        context
+            .forecast(WeatherQuery::new(country, city))
+            .map(Location)
+            .map_err(field_error)
+    }
+
+    #[graphql(description = "Health of every weather API")]
+    fn providers(context: &GraphQLContext) -> FieldResult<Vec<ProviderStatus>> {
+        let health = context
+            .aggregator
+            .send(GetProviderHealth)
+            .wait()
+            .map_err(|err| field_error(APIError::UnexpectedError(Error::from(err))))?;
+
+        Ok(health.into_iter().map(ProviderStatus::from).collect())
+    }
+}
+
+type Schema = RootNode<
+    'static,
+    Query,
+    EmptyMutation<GraphQLContext>,
+    EmptySubscription<GraphQLContext>,
+>;
+
+/// Выполняет запросы GraphQL. Резолверы блокируются, пока ждут агрегатор,
+/// поэтому актор нужно запускать в `SyncArbiter`.
+pub struct GraphQLExecutor {
+    schema: Schema,
+    aggregator: Addr<Aggregator>,
+}
+
+impl GraphQLExecutor {
+    pub fn new(aggregator: &Addr<Aggregator>) -> Self {
+        Self {
+            schema: Schema::new(Query, EmptyMutation::new(), EmptySubscription::new()),
+            aggregator: aggregator.clone(),
+        }
+    }
+}
+
+impl Actor for GraphQLExecutor {
+    type Context = SyncContext<Self>;
+}
+
+/// Запрос GraphQL и идентификатор HTTP-запроса, в котором он пришёл.
+pub struct GraphQLQuery(GraphQLRequest, Option<String>);
+
+/// Ответ на запрос GraphQL в JSON. `ok` ложно, если запрос
+/// не прошёл разбор или проверку по схеме.
+pub struct GraphQLResult {
+    ok: bool,
+    body: String,
+}
+
+impl Message for GraphQLQuery {
+    type Result = Result<GraphQLResult, Error>;
+}
+
+impl Handler<GraphQLQuery> for GraphQLExecutor {
+    type Result = Result<GraphQLResult, Error>;
+
+    fn handle(&mut self, msg: GraphQLQuery, _ctx: &mut Self::Context) -> Self::Result {
+        let GraphQLQuery(request, request_id) = msg;
+        let context = GraphQLContext {
+            aggregator: self.aggregator.clone(),
+            request_id,
+        };
+        let response = request.execute_sync(&self.schema, &context);
+
+        Ok(GraphQLResult {
+            ok: response.is_ok(),
+            body: serde_json::to_string(&response)?,
+        })
+    }
+}
+
+impl WebAPI {
+    pub(super) fn graphql_routes(app: App<Self>) -> App<Self> {
+        app.resource("/graphql", |r| {
+            r.method(http::Method::POST).f(Self::graphql_query)
+        })
+    }
+
+    fn graphql_query(req: &HttpRequest<Self>) -> APIFuture<HttpResponse> {
+        let executor = match req.state().graphql {
+            Some(ref executor) => executor.clone(),
+            None => {
+                let reason = format_err!("GraphQL executor is not configured");
+                return APIError::UnexpectedError(reason).into_responder();
+            }
+        };
+
+        let request_id = request_id(req);
+
+        let response = req
+            .json()
+            .map_err(|err| APIError::BadRequest(err.into()))
+            .and_then(move |request: GraphQLRequest| {
+                executor
+                    .send(GraphQLQuery(request, request_id))
+                    .map_err(|err| APIError::UnexpectedError(Error::from(err)))
+            }).map(|res| match res {
+                Ok(result) => {
+                    let mut response = if result.ok {
+                        HttpResponse::Ok()
+                    } else {
+                        HttpResponse::BadRequest()
+                    };
+
+                    Ok(response.content_type("application/json").body(result.body))
+                }
+                Err(err) => Err(APIError::UnexpectedError(err)),
+            });
+
+        Box::new(response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix::SyncArbiter;
+    use actix_web::test;
+    use chrono::Duration;
+    use serde_json::Value;
+
+    use super::*;
+    use apis::WeatherReport;
+
+    struct TestWeatherActor;
+
+    impl Actor for TestWeatherActor {
+        type Context = SyncContext<Self>;
+    }
+
+    impl Handler<WeatherQuery> for TestWeatherActor {
+        type Result = Result<WeatherReport, Error>;
+
+        fn handle(&mut self, msg: WeatherQuery, _ctx: &mut Self::Context) -> Self::Result {
+            if msg.city() != "London" {
+                return Ok(WeatherReport::from(smallvec![]));
+            }
+
+            let today = Utc::now().naive_utc().date();
+            let now = Utc::now();
+
+            Ok(WeatherReport {
+                data: smallvec![
+                    WeatherData {
+                        temperature: 10.0,
+                        date: today,
+                    },
+                    WeatherData {
+                        temperature: 12.0,
+                        date: today + Duration::days(2),
+                    },
+                ],
+                hourly: (1..=3)
+                    .map(|hour| HourlyData {
+                        temperature: hour as f32,
+                        time: now + Duration::hours(hour * 12),
+                    }).collect(),
+                utc_offset: None,
+            })
+        }
+    }
+
+    fn init_test_server() -> test::TestServer {
+        test::TestServer::with_factory(|| {
+            let weather_actor = SyncArbiter::start(1, || TestWeatherActor {});
+            let aggregator = Aggregator::new()
+                .add_api("test", weather_actor.recipient())
+                .start();
+            let executor = {
+                let aggregator = aggregator.clone();
+                SyncArbiter::start(1, move || GraphQLExecutor::new(&aggregator))
+            };
+
+            WebAPI::new(aggregator.recipient())
+                .graphql(executor)
+                .app()
+        })
+    }
+
+    fn execute(srv: &mut test::TestServer, query: &str) -> (http::StatusCode, Value) {
+        let request = srv
+            .client(http::Method::POST, "/graphql")
+            .json(json!({ "query": query }))
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        let status = response.status();
+        let body = srv
+            .execute(response.json())
+            .expect("Failed to parse response as JSON");
+
+        (status, body)
+    }
+
+    #[test]
+    fn queries_location() {
+        let mut srv = init_test_server();
+
+        let (status, body) = execute(
+            &mut srv,
+            r#"{
+                location(country: "UK", city: "London") {
+                    city
+                    sources
+                    daily(days: 3) { temperature }
+                    hourly(hours: 30) { temperature }
+                    providers { name daily { temperature } }
+                }
+                providers { name healthy consecutiveFailures errorRate }
+            }"#,
+        );
+
+        assert_eq!(status, http::StatusCode::OK);
+        assert!(body["errors"].is_null(), "Unexpected errors: {}", body);
+
+        let location = &body["data"]["location"];
+        assert_eq!(location["city"], "London");
+        assert_eq!(location["sources"], json!(["test"]));
+        assert_eq!(
+            location["daily"],
+            json!([{ "temperature": 10.0 }, { "temperature": null }, { "temperature": 12.0 }])
+        );
+        assert_eq!(
+            location["hourly"],
+            json!([{ "temperature": 1.0 }, { "temperature": 2.0 }])
+        );
+        assert_eq!(location["providers"][0]["name"], "test");
+        assert_eq!(location["providers"][0]["daily"].as_array().map(Vec::len), Some(2));
+
+        assert_eq!(
+            body["data"]["providers"],
+            json!([{ "name": "test", "healthy": true, "consecutiveFailures": 0, "errorRate": 0.0 }])
+        );
+    }
+
+    #[test]
+    fn reports_errors() {
+        let mut srv = init_test_server();
+
+        let (status, body) = execute(
+            &mut srv,
+            r#"{ location(country: "UK", city: "Londn") { city } }"#,
+        );
+        assert_eq!(status, http::StatusCode::OK);
+        assert_eq!(body["errors"][0]["extensions"]["code"], "unknown_location");
+
+        let (status, body) = execute(
+            &mut srv,
+            r#"{ location(country: "UK", city: "London") { daily(days: 100) { date } } }"#,
+        );
+        assert_eq!(status, http::StatusCode::OK);
+        assert_eq!(body["errors"][0]["extensions"]["code"], "invalid_range");
+
+        let (status, body) = execute(
+            &mut srv,
+            r#"{ location(country: "UK", city: "London") { hourly(hours: 0) { time } } }"#,
+        );
+        assert_eq!(status, http::StatusCode::OK);
+        assert_eq!(body["errors"][0]["extensions"]["code"], "invalid_range");
+
+        let (status, _) = execute(&mut srv, "{ location { humidity } }");
+        assert_eq!(status, http::StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,201 @@
+use actix::{Addr, Recipient};
+use actix_web::{http, App, HttpRequest, HttpResponse, Json};
+use failure::Error;
+use futures::Future;
+
+use aggregator::{Aggregator, GetCacheStats, GetProviderHealth, ProviderHealth};
+
+use super::{APIError, APIFuture, WebAPI};
+
+/// Проверки состояния сервиса для оркестратора. Сервис готов принимать
+/// запросы, пока здоровых API не меньше `min_healthy`.
+pub struct HealthCheck {
+    min_healthy: usize,
+    health: Recipient<GetProviderHealth>,
+    stats: Recipient<GetCacheStats>,
+}
+
+impl HealthCheck {
+    pub fn new(min_healthy: usize, aggregator: &Addr<Aggregator>) -> Self {
+        Self {
+            min_healthy,
+            health: aggregator.clone().recipient(),
+            stats: aggregator.clone().recipient(),
+        }
+    }
+}
+
+/// Ответ на проверку живости.
+#[derive(Serialize, Deserialize)]
+struct Liveness {
+    status: String,
+}
+
+/// Ответ на проверку готовности: состояние каждого API и размер кэша.
+#[derive(Serialize, Deserialize)]
+struct Readiness {
+    ready: bool,
+    healthy_providers: usize,
+    min_healthy_providers: usize,
+    cache_size: usize,
+    providers: Vec<ProviderHealth>,
+}
+
+impl WebAPI {
+    pub(super) fn health_routes(app: App<Self>) -> App<Self> {
+        app.resource("/health/live", |r| {
+            r.method(http::Method::GET).f(Self::liveness)
+        }).resource("/health/ready", |r| {
+            r.method(http::Method::GET).f(Self::readiness)
+        })
+    }
+
+    /// Сервис жив, пока отвечает на запросы.
+    fn liveness(_req: &HttpRequest<Self>) -> Json<Liveness> {
+        Json(Liveness {
+            status: "ok".to_string(),
+        })
+    }
+
+    /// Сервис готов, если здоровых API достаточно. Иначе отвечает
+    /// `503 Service Unavailable` с тем же телом.
+    fn readiness(req: &HttpRequest<Self>) -> APIFuture<HttpResponse> {
+        let check = match req.state().health {
+            Some(ref check) => check,
+            None => {
+                let reason = format_err!("health checks are not configured");
+                return APIError::UnexpectedError(reason).into_responder();
+            }
+        };
+
+        let min_healthy = check.min_healthy;
+
+        let readiness = check
+            .health
+            .send(GetProviderHealth)
+            .join(check.stats.send(GetCacheStats))
+            .map(move |(providers, stats)| {
+                let healthy_providers = providers.iter().filter(|api| api.healthy).count();
+                let readiness = Readiness {
+                    ready: healthy_providers >= min_healthy,
+                    healthy_providers,
+                    min_healthy_providers: min_healthy,
+                    cache_size: stats.size,
+                    providers,
+                };
+
+                let mut builder = if readiness.ready {
+                    HttpResponse::Ok()
+                } else {
+                    HttpResponse::ServiceUnavailable()
+                };
+
+                Ok(builder.json(readiness))
+            }).map_err(|err| APIError::UnexpectedError(Error::from(err)));
+
+        Box::new(readiness)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix::prelude::*;
+    use actix_web::{test, HttpMessage};
+    use chrono::Utc;
+    use failure::err_msg;
+    use serde_json::Value;
+
+    use super::*;
+    use apis::{WeatherData, WeatherQuery, WeatherReport};
+
+    struct TestWeatherActor;
+
+    impl Actor for TestWeatherActor {
+        type Context = SyncContext<Self>;
+    }
+
+    impl Handler<WeatherQuery> for TestWeatherActor {
+        type Result = Result<WeatherReport, Error>;
+
+        fn handle(&mut self, _msg: WeatherQuery, _ctx: &mut Self::Context) -> Self::Result {
+            Ok(WeatherReport::from(smallvec![WeatherData {
+                temperature: 10.0,
+                date: Utc::now().naive_utc().date(),
+            }]))
+        }
+    }
+
+    struct BrokenWeatherActor;
+
+    impl Actor for BrokenWeatherActor {
+        type Context = SyncContext<Self>;
+    }
+
+    impl Handler<WeatherQuery> for BrokenWeatherActor {
+        type Result = Result<WeatherReport, Error>;
+
+        fn handle(&mut self, _msg: WeatherQuery, _ctx: &mut Self::Context) -> Self::Result {
+            Err(err_msg("invalid API key"))
+        }
+    }
+
+    #[test]
+    fn reports_readiness() {
+        let mut srv = test::TestServer::with_factory(|| {
+            let working = SyncArbiter::start(1, || TestWeatherActor {});
+            let broken = SyncArbiter::start(1, || BrokenWeatherActor {});
+            let aggregator = Aggregator::new()
+                .add_api("working", working.recipient())
+                .add_api("broken", broken.recipient())
+                .start();
+            let health = HealthCheck::new(2, &aggregator);
+
+            WebAPI::new(aggregator.recipient())
+                .health(health)
+                .app()
+        });
+
+        let request = srv
+            .client(http::Method::GET, "/health/live")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+        assert!(response.status().is_success());
+
+        // Пока к API не обращались, все они считаются здоровыми.
+        let request = srv
+            .client(http::Method::GET, "/health/ready")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+        assert!(response.status().is_success());
+
+        let request = srv
+            .client(http::Method::GET, "/forecast/weekly/UK/London")
+            .finish()
+            .expect("Failed to construct test request");
+        srv.execute(request.send())
+            .expect("Failed to send test request");
+
+        let request = srv
+            .client(http::Method::GET, "/health/ready")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+        assert_eq!(response.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+
+        let body: Value = srv.execute(response.json()).expect("Invalid readiness");
+        assert_eq!(body["ready"], false);
+        assert_eq!(body["healthy_providers"], 1);
+        assert_eq!(body["cache_size"], 1);
+        assert_eq!(body["providers"][1]["name"], "broken");
+        assert_eq!(body["providers"][1]["last_error"], "invalid API key");
+        assert_eq!(body["providers"][1]["error_rate"], 1.0);
+    }
+}
@@ -0,0 +1,202 @@
+use actix_web::http::header;
+use actix_web::{FromRequest, HttpRequest, HttpResponse, Path};
+use chrono::Utc;
+use failure::Error;
+use futures::Future;
+
+use aggregator::{Forecast, ForecastQuery};
+use apis::WeatherQuery;
+
+use super::tracing::traced;
+use super::{APIError, APIFuture, WebAPI};
+
+/// Идентификатор программы в календаре, он же домен в UID событий.
+const PRODUCT: &str = "congenial-lamp";
+
+/// Максимальная длина строки календаря в октетах без CRLF (RFC 5545, 3.1).
+const MAX_LINE_LENGTH: usize = 75;
+
+/// Экранирует текстовое значение свойства (RFC 5545, 3.3.11).
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '\\' | ';' | ',' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Дописывает строку календаря, перенося её по 75 октетов. Переносы
+/// не разрывают многобайтовые символы.
+fn push_line(calendar: &mut String, line: &str) {
+    let mut length = 0;
+
+    for c in line.chars() {
+        if length + c.len_utf8() > MAX_LINE_LENGTH {
+            calendar.push_str("\r\n ");
+            length = 1;
+        }
+        calendar.push(c);
+        length += c.len_utf8();
+    }
+
+    calendar.push_str("\r\n");
+}
+
+/// Календарь с событием на весь день для каждого дня прогноза. UID события
+/// зависит только от города и даты, поэтому при обновлении подписки
+/// календари заменяют события, а не дублируют их.
+fn calendar(forecast: &Forecast) -> String {
+    let mut calendar = String::new();
+    let stamp = forecast.fetched_at.format("%Y%m%dT%H%M%SZ").to_string();
+    let sources = forecast.sources.join(", ");
+
+    push_line(&mut calendar, "BEGIN:VCALENDAR");
+    push_line(&mut calendar, "VERSION:2.0");
+    push_line(&mut calendar, &format!("PRODID:-//{}//Forecast//EN", PRODUCT));
+    push_line(&mut calendar, "CALSCALE:GREGORIAN");
+    push_line(&mut calendar, "METHOD:PUBLISH");
+    push_line(
+        &mut calendar,
+        &format!("X-WR-CALNAME:{}", escape(&format!("Forecast for {}", forecast.query))),
+    );
+
+    for entry in &forecast.data {
+        // У последней представимой даты нет следующего дня, а без него
+        // не задать конец события.
+        let end = match entry.date.succ_opt() {
+            Some(end) => end,
+            None => continue,
+        };
+        let uid = format!("{}/{}@{}", entry.date.format("%Y%m%d"), forecast.query, PRODUCT);
+        let summary = format!("{:.1} °C", entry.temperature);
+        let description = format!(
+            "Average temperature in {}: {:.1} °C.\nSources: {}",
+            forecast.query, entry.temperature, sources
+        );
+
+        push_line(&mut calendar, "BEGIN:VEVENT");
+        push_line(&mut calendar, &format!("UID:{}", escape(&uid)));
+        push_line(&mut calendar, &format!("DTSTAMP:{}", stamp));
+        push_line(
+            &mut calendar,
+            &format!("DTSTART;VALUE=DATE:{}", entry.date.format("%Y%m%d")),
+        );
+        push_line(
+            &mut calendar,
+            &format!("DTEND;VALUE=DATE:{}", end.format("%Y%m%d")),
+        );
+        push_line(&mut calendar, &format!("SUMMARY:{}", escape(&summary)));
+        push_line(&mut calendar, &format!("DESCRIPTION:{}", escape(&description)));
+        push_line(&mut calendar, "TRANSP:TRANSPARENT");
+        push_line(&mut calendar, "END:VEVENT");
+    }
+
+    push_line(&mut calendar, "END:VCALENDAR");
+
+    calendar
+}
+
+impl WebAPI {
+    pub(super) fn ical_forecast(req: &HttpRequest<Self>) -> APIFuture<HttpResponse> {
+        let query = match Path::<WeatherQuery>::extract(req) {
+            Ok(query) => traced(req, query.into_inner()),
+            Err(reason) => return APIError::BadRequest(reason).into_responder(),
+        };
+
+        let response = req
+            .state()
+            .aggregator
+            .send(ForecastQuery(query))
+            .map(|res| match res {
+                Ok(forecast) => {
+                    let max_age = forecast
+                        .expires_at
+                        .signed_duration_since(Utc::now())
+                        .num_seconds()
+                        .max(0) as u32;
+
+                    Ok(HttpResponse::Ok()
+                        .content_type("text/calendar; charset=utf-8")
+                        .set(header::CacheControl(vec![
+                            header::CacheDirective::Public,
+                            header::CacheDirective::MaxAge(max_age),
+                        ])).body(calendar(&forecast)))
+                }
+                Err(reason) => Err(APIError::from_aggregator(reason)),
+            }).map_err(|err| APIError::UnexpectedError(Error::from(err)));
+
+        Box::new(response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{NaiveDate, TimeZone};
+
+    use super::*;
+    use apis::WeatherData;
+
+    fn forecast(city: &str) -> Forecast {
+        Forecast {
+            query: WeatherQuery::new("UK".to_string(), city.to_string()),
+            data: smallvec![
+                WeatherData {
+                    temperature: 10.5,
+                    date: NaiveDate::from_ymd(2018, 10, 2),
+                },
+                WeatherData {
+                    temperature: 11.0,
+                    date: NaiveDate::from_ymd(2018, 10, 3),
+                },
+            ],
+            sources: vec!["apixu".to_string(), "weatherbit".to_string()],
+            utc_offset: None,
+            fetched_at: Utc.ymd(2018, 10, 2).and_hms(12, 0, 0),
+            expires_at: Utc.ymd(2018, 10, 3).and_hms(0, 0, 0),
+        }
+    }
+
+    #[test]
+    fn builds_calendar() {
+        let ics = calendar(&forecast("London"));
+        let lines: Vec<_> = ics.split("\r\n").collect();
+
+        assert_eq!(lines[0], "BEGIN:VCALENDAR");
+        assert_eq!(lines[lines.len() - 2], "END:VCALENDAR");
+        assert_eq!(ics.matches("BEGIN:VEVENT").count(), 2);
+        assert!(lines.contains(&"UID:20181002/UK/London@congenial-lamp"));
+        assert!(lines.contains(&"DTSTART;VALUE=DATE:20181002"));
+        assert!(lines.contains(&"DTEND;VALUE=DATE:20181003"));
+        assert!(lines.contains(&"SUMMARY:10.5 °C"));
+        assert!(lines.contains(&"DTSTAMP:20181002T120000Z"));
+
+        // UID не зависит от данных прогноза.
+        let mut updated = forecast("London");
+        updated.data[0].temperature = 20.0;
+        assert!(calendar(&updated).contains("UID:20181002/UK/London@congenial-lamp"));
+    }
+
+    #[test]
+    fn folds_long_lines() {
+        let ics = calendar(&forecast(&"Лондон".repeat(20)));
+
+        for line in ics.split("\r\n") {
+            assert!(line.len() <= MAX_LINE_LENGTH, "Line is too long: {}", line);
+        }
+
+        assert!(ics.contains("\r\n "));
+
+        let unfolded = ics.replace("\r\n ", "");
+        assert!(unfolded.contains("Sources: apixu\\, weatherbit"));
+    }
+}
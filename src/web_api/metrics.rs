@@ -0,0 +1,220 @@
+use std::time::Instant;
+
+use actix::{Addr, Recipient};
+use actix_web::middleware::{Middleware, Response, Started};
+use actix_web::{http, App, HttpRequest, HttpResponse, Result};
+use failure::Error;
+use futures::Future;
+
+use aggregator::{Aggregator, CacheStats, GetCacheStats, GetPendingRequests};
+use metrics::{Exposition, Metrics, RecordRequest, RenderMetrics};
+
+use super::{APIError, APIFuture, WebAPI};
+
+/// Метрики сервиса в формате Prometheus на `/metrics`.
+pub struct PrometheusMetrics {
+    metrics: Recipient<RenderMetrics>,
+    requests: Recipient<RecordRequest>,
+    stats: Recipient<GetCacheStats>,
+    pending: Recipient<GetPendingRequests>,
+}
+
+impl PrometheusMetrics {
+    pub fn new(metrics: &Addr<Metrics>, aggregator: &Addr<Aggregator>) -> Self {
+        Self {
+            metrics: metrics.clone().recipient(),
+            requests: metrics.clone().recipient(),
+            stats: aggregator.clone().recipient(),
+            pending: aggregator.clone().recipient(),
+        }
+    }
+
+    pub(super) fn recorder(&self) -> RequestMetrics {
+        RequestMetrics(self.requests.clone())
+    }
+}
+
+/// Время начала обработки запроса.
+struct RequestStart(Instant);
+
+/// Промежуточный обработчик, отправляющий время и код ответа на каждый
+/// запрос. Запросы к несуществующим маршрутам учитываются вместе.
+pub(super) struct RequestMetrics(Recipient<RecordRequest>);
+
+impl<S> Middleware<S> for RequestMetrics {
+    fn start(&self, req: &HttpRequest<S>) -> Result<Started> {
+        req.extensions_mut().insert(RequestStart(Instant::now()));
+
+        Ok(Started::Done)
+    }
+
+    fn response(&self, req: &HttpRequest<S>, resp: HttpResponse) -> Result<Response> {
+        if let Some(&RequestStart(started)) = req.extensions().get::<RequestStart>() {
+            let route = req
+                .resource()
+                .rdef()
+                .map_or("unmatched", |rdef| rdef.pattern());
+
+            let _ = self.0.do_send(RecordRequest {
+                route: route.to_string(),
+                method: req.method().to_string(),
+                status: resp.status().as_u16(),
+                elapsed: started.elapsed(),
+            });
+        }
+
+        Ok(Response::Done(resp))
+    }
+}
+
+/// Метрики кэша агрегатора и число запросов к каждому погодному API,
+/// на которые он ещё не ответил.
+fn render_aggregator(stats: &CacheStats, pending: &[(String, usize)]) -> String {
+    let mut out = Exposition::new();
+
+    let counters = [
+        (
+            "forecast_cache_hits_total",
+            "Forecast requests served from the cache.",
+            stats.hits,
+        ),
+        (
+            "forecast_cache_misses_total",
+            "Forecast requests that queried weather APIs.",
+            stats.misses,
+        ),
+        (
+            "forecast_cache_evictions_total",
+            "Weather API results removed from the cache.",
+            stats.evictions,
+        ),
+    ];
+    for (name, help, value) in &counters {
+        out.family(name, "counter", help);
+        out.sample(name, &[], value);
+    }
+
+    out.family("forecast_cache_size", "gauge", "Locations in the cache.");
+    out.sample("forecast_cache_size", &[], stats.size);
+
+    out.family(
+        "provider_pending_requests",
+        "gauge",
+        "Requests sent to a weather API that it has not answered yet.",
+    );
+    for (name, pending) in pending {
+        out.sample("provider_pending_requests", &[("provider", name)], pending);
+    }
+
+    out.into_string()
+}
+
+impl WebAPI {
+    pub(super) fn metrics_routes(app: App<Self>) -> App<Self> {
+        app.resource("/metrics", |r| r.method(http::Method::GET).f(Self::export_metrics))
+    }
+
+    fn export_metrics(req: &HttpRequest<Self>) -> APIFuture<HttpResponse> {
+        let metrics = match req.state().metrics {
+            Some(ref metrics) => metrics,
+            None => {
+                let reason = format_err!("metrics are not configured");
+                return APIError::UnexpectedError(reason).into_responder();
+            }
+        };
+
+        let response = metrics
+            .metrics
+            .send(RenderMetrics)
+            .join3(
+                metrics.stats.send(GetCacheStats),
+                metrics.pending.send(GetPendingRequests),
+            )
+            .map(|(mut text, stats, pending)| {
+                text.push_str(&render_aggregator(&stats, &pending));
+
+                Ok(HttpResponse::Ok()
+                    .content_type("text/plain; version=0.0.4")
+                    .body(text))
+            }).map_err(|err| APIError::UnexpectedError(Error::from(err)));
+
+        Box::new(response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix::prelude::*;
+    use actix_web::{test, HttpMessage};
+    use chrono::Utc;
+
+    use super::*;
+    use apis::{WeatherData, WeatherQuery, WeatherReport};
+
+    struct TestWeatherActor;
+
+    impl Actor for TestWeatherActor {
+        type Context = SyncContext<Self>;
+    }
+
+    impl Handler<WeatherQuery> for TestWeatherActor {
+        type Result = Result<WeatherReport, Error>;
+
+        fn handle(&mut self, _msg: WeatherQuery, _ctx: &mut Self::Context) -> Self::Result {
+            Ok(WeatherReport::from(smallvec![WeatherData {
+                temperature: 10.0,
+                date: Utc::now().naive_utc().date(),
+            }]))
+        }
+    }
+
+    fn get(srv: &mut test::TestServer, uri: &str) -> String {
+        let request = srv
+            .client(http::Method::GET, uri)
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+        let body = srv.execute(response.body()).expect("Failed to read body");
+
+        String::from_utf8(body.to_vec()).expect("Invalid UTF-8")
+    }
+
+    #[test]
+    fn exports_metrics() {
+        let mut srv = test::TestServer::with_factory(|| {
+            let weather_actor = SyncArbiter::start(1, || TestWeatherActor {});
+            let aggregator = Aggregator::new()
+                .add_api("test", weather_actor.recipient())
+                .start();
+            let metrics = PrometheusMetrics::new(&Metrics::new().start(), &aggregator);
+
+            WebAPI::new(aggregator.recipient())
+                .metrics(metrics)
+                .app()
+        });
+
+        get(&mut srv, "/forecast/weekly/UK/London");
+        get(&mut srv, "/forecast/weekly/UK/London");
+        get(&mut srv, "/no/such/route");
+
+        let text = get(&mut srv, "/metrics");
+        let route = r#"route="/forecast/weekly/{country}/{city}",method="GET""#;
+        assert!(text.contains(&format!(
+            "http_requests_total{{{},status=\"200\"}} 2\n",
+            route
+        )));
+        assert!(text.contains(&format!(
+            "http_request_duration_seconds_count{{{},status=\"200\"}} 2\n",
+            route
+        )));
+        assert!(
+            text.contains(r#"http_requests_total{route="unmatched",method="GET",status="404"} 1"#)
+        );
+        assert!(text.contains("forecast_cache_hits_total 1\n"));
+        assert!(text.contains("forecast_cache_misses_total 1\n"));
+        assert!(text.contains("forecast_cache_size 1\n"));
+        assert!(text.contains("provider_pending_requests{provider=\"test\"} 0\n"));
+    }
+}
@@ -0,0 +1,1213 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix::{Addr, MailboxError, Recipient};
+use actix_web::http::header;
+use actix_web::{
+    error, http, App, FromRequest, HttpMessage, HttpRequest, HttpResponse, Path,
+    Query, Responder,
+};
+use chrono::{DateTime, NaiveDate, ParseError, Utc};
+use failure::Error;
+use futures::future;
+use futures::Future;
+
+use aggregator::{
+    Forecast, ForecastQuery, ListProviders, ProviderExists, StreamForecast, UnknownLocation,
+    UnknownProvider,
+};
+use apis::{WeatherData, WeatherQuery};
+use clients::CheckClient;
+use logging::LogFormat;
+use otlp::Span;
+
+mod admin;
+mod batch;
+mod clients;
+mod format;
+mod graphql;
+mod health;
+mod ical;
+mod metrics;
+mod openapi;
+mod providers;
+mod sse;
+mod tracing;
+mod v2;
+mod ws;
+
+pub use self::admin::CacheAdmin;
+pub use self::graphql::GraphQLExecutor;
+pub use self::health::HealthCheck;
+pub use self::metrics::PrometheusMetrics;
+pub use self::ws::ForecastSubscriptions;
+
+use self::format::{Days, Document, Format, Negotiated, Render, Table};
+use self::clients::ClientAuth;
+use self::tracing::{traced, AccessLog, RequestTracing};
+
+/// Перечисление с ошибками API. `UnexpectedError` логируются
+/// полностью, а наружу отдаются без подробностей.
+#[derive(Fail, Debug)]
+enum APIError {
+    #[fail(display = "failed to parse date - {}", _0)]
+    InvalidDate(ParseError),
+    #[fail(display = "invalid parameters - {}", _0)]
+    BadRequest(error::Error),
+    #[fail(display = "invalid date range - {}", _0)]
+    InvalidRange(String),
+    #[fail(display = "weather data not found for given day - {}", _0)]
+    NotFound(NaiveDate),
+    #[fail(display = "location not found - {}", _0)]
+    UnknownLocation(WeatherQuery),
+    #[fail(display = "insufficient weather data for weekly forecast")]
+    InsufficientData,
+    #[fail(display = "missing or invalid admin token")]
+    Unauthorized,
+    #[fail(display = "missing or invalid API key")]
+    InvalidApiKey,
+    #[fail(display = "rate limit exceeded, retry in {} s", _0)]
+    RateLimited(u64),
+    #[fail(display = "{}", _0)]
+    InvalidConfig(Error),
+    #[fail(display = "{}", _0)]
+    UnknownProvider(UnknownProvider),
+    #[fail(display = "{}", _0)]
+    ProviderExists(ProviderExists),
+    #[fail(display = "unexpected error during request - {}", _0)]
+    UnexpectedError(Error),
+}
+
+/// Самый дальний горизонт прогноза среди API (у WeatherBit - 16 дней,
+/// включая сегодняшний).
+const MAX_FORECAST_DAYS: i64 = 16;
+
+/// Длина недельного прогноза по умолчанию.
+const WEEKLY_FORECAST_DAYS: i64 = 5;
+
+/// Параметры запроса недельного прогноза.
+#[derive(Deserialize)]
+struct WeeklyParams {
+    days: Option<i64>,
+}
+
+/// Параметры запроса прогноза на диапазон дат.
+#[derive(Deserialize)]
+struct RangeParams {
+    from: String,
+    to: String,
+}
+
+/// Вспомогательная структура для упаковки ошибок в JSON.
+#[derive(Serialize, Deserialize)]
+struct APIErrorResponse {
+    error: String,
+}
+
+impl APIError {
+    fn status_code(&self) -> http::StatusCode {
+        match *self {
+            APIError::InvalidDate(_) | APIError::BadRequest(_) | APIError::InvalidRange(_) => {
+                http::StatusCode::BAD_REQUEST
+            }
+            APIError::Unauthorized | APIError::InvalidApiKey => http::StatusCode::UNAUTHORIZED,
+            APIError::RateLimited(_) => http::StatusCode::TOO_MANY_REQUESTS,
+            APIError::InvalidConfig(_) => http::StatusCode::UNPROCESSABLE_ENTITY,
+            APIError::UnknownProvider(_) => http::StatusCode::NOT_FOUND,
+            APIError::ProviderExists(_) => http::StatusCode::CONFLICT,
+            APIError::NotFound(_) | APIError::UnknownLocation(_) => http::StatusCode::NOT_FOUND,
+            APIError::InsufficientData => http::StatusCode::SERVICE_UNAVAILABLE,
+            APIError::UnexpectedError(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Машиночитаемый код ошибки.
+    fn code(&self) -> &'static str {
+        match *self {
+            APIError::InvalidDate(_) => "invalid_date",
+            APIError::BadRequest(_) => "bad_request",
+            APIError::InvalidRange(_) => "invalid_range",
+            APIError::NotFound(_) => "not_found",
+            APIError::UnknownLocation(_) => "unknown_location",
+            APIError::InsufficientData => "insufficient_data",
+            APIError::Unauthorized => "unauthorized",
+            APIError::InvalidApiKey => "invalid_api_key",
+            APIError::RateLimited(_) => "rate_limited",
+            APIError::InvalidConfig(_) => "invalid_config",
+            APIError::UnknownProvider(_) => "unknown_provider",
+            APIError::ProviderExists(_) => "provider_exists",
+            APIError::UnexpectedError(_) => "internal_error",
+        }
+    }
+
+    /// Описание ошибки для клиента.
+    fn message(&self) -> String {
+        match *self {
+            APIError::UnexpectedError(_) => {
+                "An internal error occurred. Please try again later.".to_string()
+            }
+            _ => format!("{}", self),
+        }
+    }
+
+    /// Заголовки, которые отдаются вместе с ошибкой.
+    fn set_headers(&self, resp: &mut HttpResponse) {
+        if let APIError::RateLimited(secs) = *self {
+            resp.headers_mut()
+                .insert(header::RETRY_AFTER, header::HeaderValue::from(secs));
+        }
+    }
+
+    fn into_responder<R: 'static>(self) -> APIFuture<R> {
+        Box::new(future::err(self))
+    }
+
+    /// Неизвестный город отдаётся клиенту как есть, остальные
+    /// ошибки агрегатора считаются непредвиденными.
+    fn from_aggregator(err: Error) -> Self {
+        match err.downcast::<UnknownLocation>() {
+            Ok(UnknownLocation(query)) => APIError::UnknownLocation(query),
+            Err(err) => APIError::UnexpectedError(err),
+        }
+    }
+
+    /// Ошибки перезагрузки конфигурации и изменения набора погодных API:
+    /// неизвестный или уже добавленный API, сбой актора или неверные
+    /// настройки.
+    fn from_config_change(err: Error) -> Self {
+        let err = match err.downcast::<UnknownProvider>() {
+            Ok(err) => return APIError::UnknownProvider(err),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<ProviderExists>() {
+            Ok(err) => return APIError::ProviderExists(err),
+            Err(err) => err,
+        };
+
+        match err.downcast::<MailboxError>() {
+            Ok(err) => APIError::UnexpectedError(Error::from(err)),
+            Err(err) => APIError::InvalidConfig(err),
+        }
+    }
+}
+
+impl Document for APIErrorResponse {
+    fn table(&self) -> Table {
+        Table {
+            columns: &["error"],
+            rows: vec![vec![self.error.clone()]],
+        }
+    }
+}
+
+impl Render for APIError {
+    fn render(&self, format: Format) -> HttpResponse {
+        let mut resp = format.respond(
+            self.status_code(),
+            &APIErrorResponse {
+                error: self.message(),
+            },
+        );
+        self.set_headers(&mut resp);
+
+        resp
+    }
+}
+
+impl error::ResponseError for APIError {
+    fn error_response(&self) -> HttpResponse {
+        self.render(Format::Json)
+    }
+}
+
+/// Ответ с данными из кэша агрегатора. Кроме самих данных в согласованном
+/// формате отдаёт заголовки `Cache-Control`, `Last-Modified` и `ETag`, а на
+/// условные запросы с `If-None-Match` или `If-Modified-Since` отвечает
+/// `304 Not Modified` без тела.
+struct Cached<D> {
+    data: D,
+    format: Format,
+    last_modified: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+impl<D> Cached<D> {
+    fn new(data: D, forecast: &Forecast, format: Format) -> Self {
+        Self {
+            data,
+            format,
+            last_modified: forecast.fetched_at,
+            expires_at: forecast.expires_at,
+        }
+    }
+
+    /// Проверяет, есть ли у клиента актуальная версия ответа.
+    fn is_fresh<S>(&self, req: &HttpRequest<S>, etag: &header::EntityTag) -> bool {
+        match req.get_header::<header::IfNoneMatch>() {
+            Some(header::IfNoneMatch::Any) => true,
+            Some(header::IfNoneMatch::Items(ref items)) => {
+                items.iter().any(|item| item.weak_eq(etag))
+            }
+            None => match req.get_header::<header::IfModifiedSince>() {
+                Some(header::IfModifiedSince(since)) => {
+                    let since = SystemTime::from(since)
+                        .duration_since(UNIX_EPOCH)
+                        .map(|since| since.as_secs() as i64)
+                        .unwrap_or(0);
+                    self.last_modified.timestamp() <= since
+                }
+                None => false,
+            },
+        }
+    }
+}
+
+impl<D: Document> Responder for Cached<D> {
+    type Item = HttpResponse;
+    type Error = error::Error;
+
+    fn respond_to<S>(self, req: &HttpRequest<S>) -> Result<HttpResponse, error::Error> {
+        let body = self
+            .format
+            .serialize(&self.data)
+            .map_err(|err| error::ErrorInternalServerError(err.compat()))?;
+
+        let etag = {
+            let mut hasher = DefaultHasher::new();
+            body.hash(&mut hasher);
+            header::EntityTag::strong(format!("{:016x}", hasher.finish()))
+        };
+
+        let last_modified =
+            UNIX_EPOCH + Duration::from_secs(self.last_modified.timestamp().max(0) as u64);
+        let max_age = self
+            .expires_at
+            .signed_duration_since(Utc::now())
+            .num_seconds()
+            .max(0) as u32;
+
+        let not_modified = self.is_fresh(req, &etag);
+
+        let mut builder = if not_modified {
+            HttpResponse::NotModified()
+        } else {
+            HttpResponse::Ok()
+        };
+
+        builder
+            .set(header::CacheControl(vec![
+                header::CacheDirective::Public,
+                header::CacheDirective::MaxAge(max_age),
+            ])).set(header::LastModified(last_modified.into()))
+            .set(header::ETag(etag))
+            .header(header::VARY, "Accept");
+
+        if not_modified {
+            Ok(builder.finish())
+        } else {
+            Ok(builder.content_type(self.format.content_type()).body(body))
+        }
+    }
+}
+
+type APIFuture<R> = Box<Future<Item = Result<R, APIError>, Error = APIError>>;
+
+type APIResponder<D> = Box<Future<Item = Cached<D>, Error = Negotiated<APIError>>>;
+
+/// Префиксы маршрутов API v1. Пути без версии оставлены для совместимости
+/// и работают так же, как v1.
+pub(super) const V1_PREFIXES: [&str; 2] = ["", "/v1"];
+
+/// Состояние для Actix' App. `WebAPI` требуется актор, который
+/// будет отвечать на запросы о погоде. Если задан `CacheAdmin`,
+/// то доступны и маршруты для администрирования кэша, если
+/// `GraphQLExecutor` - то `/graphql`, если актор, отдающий прогноз
+/// потоком событий, - то `/forecast/stream`, если
+/// `ForecastSubscriptions` - то подписки через `/forecast/subscribe`,
+/// если `HealthCheck` - то проверки `/health/live` и `/health/ready`,
+/// если `PrometheusMetrics` - то метрики всех запросов на `/metrics`,
+/// если актор, знающий список погодных API, - то `/providers`,
+/// если актор, проверяющий ключи клиентов, - то запросы к прогнозам
+/// выполняются только с ключом и в пределах его лимита, а если `tracer` -
+/// то каждый запрос отправляется ему как участок трассировки. Строки лога
+/// запросов пишутся в формате `log_format`.
+pub struct WebAPI {
+    aggregator: Recipient<ForecastQuery>,
+    admin: Option<CacheAdmin>,
+    graphql: Option<Addr<GraphQLExecutor>>,
+    stream: Option<Recipient<StreamForecast>>,
+    subscriptions: Option<ForecastSubscriptions>,
+    health: Option<HealthCheck>,
+    metrics: Option<PrometheusMetrics>,
+    providers: Option<Recipient<ListProviders>>,
+    clients: Option<Recipient<CheckClient>>,
+    tracer: Option<Recipient<Span>>,
+    log_format: LogFormat,
+}
+
+impl WebAPI {
+    pub fn new(aggregator: Recipient<ForecastQuery>) -> Self {
+        Self {
+            aggregator,
+            admin: None,
+            graphql: None,
+            stream: None,
+            subscriptions: None,
+            health: None,
+            metrics: None,
+            providers: None,
+            clients: None,
+            tracer: None,
+            log_format: LogFormat::Text,
+        }
+    }
+
+    pub fn admin(mut self, admin: CacheAdmin) -> Self {
+        self.admin = Some(admin);
+
+        self
+    }
+
+    pub fn graphql(mut self, graphql: Addr<GraphQLExecutor>) -> Self {
+        self.graphql = Some(graphql);
+
+        self
+    }
+
+    pub fn stream(mut self, stream: Recipient<StreamForecast>) -> Self {
+        self.stream = Some(stream);
+
+        self
+    }
+
+    pub fn subscriptions(mut self, subscriptions: ForecastSubscriptions) -> Self {
+        self.subscriptions = Some(subscriptions);
+
+        self
+    }
+
+    pub fn health(mut self, health: HealthCheck) -> Self {
+        self.health = Some(health);
+
+        self
+    }
+
+    pub fn metrics(mut self, metrics: PrometheusMetrics) -> Self {
+        self.metrics = Some(metrics);
+
+        self
+    }
+
+    pub fn providers(mut self, providers: Recipient<ListProviders>) -> Self {
+        self.providers = Some(providers);
+
+        self
+    }
+
+    pub fn clients(mut self, clients: Recipient<CheckClient>) -> Self {
+        self.clients = Some(clients);
+
+        self
+    }
+
+    pub fn tracer(mut self, tracer: Recipient<Span>) -> Self {
+        self.tracer = Some(tracer);
+
+        self
+    }
+
+    pub fn log_format(mut self, log_format: LogFormat) -> Self {
+        self.log_format = log_format;
+
+        self
+    }
+
+    /// Приложение со всеми маршрутами, для которых заданы акторы.
+    pub fn app(self) -> App<Self> {
+        let has_admin = self.admin.is_some();
+        let has_graphql = self.graphql.is_some();
+        let has_stream = self.stream.is_some();
+        let has_subscriptions = self.subscriptions.is_some();
+        let has_health = self.health.is_some();
+        let has_metrics = self.metrics.is_some();
+        let has_providers = self.providers.is_some();
+        let recorder = self.metrics.as_ref().map(PrometheusMetrics::recorder);
+        let auth = self.clients.clone().map(ClientAuth);
+        let tracing = RequestTracing(self.tracer.clone());
+        let log_format = self.log_format;
+
+        let mut app = App::with_state(self)
+            .middleware(tracing)
+            .middleware(AccessLog(log_format));
+        if let Some(recorder) = recorder {
+            app = app.middleware(recorder);
+        }
+        if let Some(auth) = auth {
+            app = app.middleware(auth);
+        }
+
+        for prefix in &V1_PREFIXES {
+            app = app
+                .resource(
+                    &format!("{}/forecast/daily/{{country}}/{{city}}/{{day}}", prefix),
+                    |r| r.method(http::Method::GET).f(Self::daily_forecast),
+                ).resource(
+                    &format!("{}/forecast/weekly/{{country}}/{{city}}", prefix),
+                    |r| r.method(http::Method::GET).f(Self::weekly_forecast),
+                ).resource(
+                    &format!("{}/forecast/range/{{country}}/{{city}}", prefix),
+                    |r| r.method(http::Method::GET).f(Self::range_forecast),
+                ).resource(&format!("{}/forecast/batch", prefix), |r| {
+                    r.method(http::Method::POST).f(Self::batch_forecast)
+                }).resource(
+                    &format!("{}/forecast/ical/{{country}}/{{city}}.ics", prefix),
+                    |r| r.method(http::Method::GET).f(Self::ical_forecast),
+                );
+        }
+
+        let app = Self::v2_routes(app);
+        let app = Self::openapi_routes(app);
+
+        let app = if has_graphql {
+            Self::graphql_routes(app)
+        } else {
+            app
+        };
+
+        let app = if has_stream {
+            Self::sse_routes(app)
+        } else {
+            app
+        };
+
+        let app = if has_subscriptions {
+            Self::ws_routes(app)
+        } else {
+            app
+        };
+
+        let app = if has_health {
+            Self::health_routes(app)
+        } else {
+            app
+        };
+
+        let app = if has_metrics {
+            Self::metrics_routes(app)
+        } else {
+            app
+        };
+
+        let app = if has_providers {
+            Self::providers_routes(app)
+        } else {
+            app
+        };
+
+        if has_admin {
+            Self::admin_routes(app)
+        } else {
+            app
+        }
+    }
+
+    fn daily_forecast(req: &HttpRequest<Self>) -> APIResponder<WeatherData> {
+        Self::respond(req, Self::fetch_daily)
+    }
+
+    fn weekly_forecast(req: &HttpRequest<Self>) -> APIResponder<Days> {
+        Self::respond(req, Self::fetch_weekly)
+    }
+
+    fn range_forecast(req: &HttpRequest<Self>) -> APIResponder<Days> {
+        Self::respond(req, Self::fetch_range)
+    }
+
+    /// Согласует формат ответа и только потом запрашивает прогноз,
+    /// чтобы и данные, и ошибки отдавались в этом формате.
+    fn respond<D: 'static>(
+        req: &HttpRequest<Self>,
+        fetch: fn(&HttpRequest<Self>) -> APIFuture<(D, Forecast)>,
+    ) -> APIResponder<D> {
+        let format = match Format::negotiate(req) {
+            Ok(format) => format,
+            Err(reason) => return Box::new(future::err(Negotiated(reason, Format::Json))),
+        };
+
+        let data = fetch(req).then(move |res| match res {
+            Ok(Ok((data, forecast))) => Ok(Cached::new(data, &forecast, format)),
+            Ok(Err(reason)) | Err(reason) => Err(Negotiated(reason, format)),
+        });
+
+        Box::new(data)
+    }
+
+    /// Прогноз на день вместе с агрегатом, из которого он взят.
+    fn fetch_daily(req: &HttpRequest<Self>) -> APIFuture<(WeatherData, Forecast)> {
+        let (country, city, day) = match Path::<(String, String, String)>::extract(req) {
+            Ok(params) => params.into_inner(),
+            Err(reason) => return APIError::BadRequest(reason).into_responder(),
+        };
+
+        let day = match NaiveDate::parse_from_str(&day, "%Y-%m-%d") {
+            Ok(day) => day,
+            Err(reason) => return APIError::InvalidDate(reason).into_responder(),
+        };
+
+        let query = traced(req, WeatherQuery::new(country, city));
+
+        Self::daily(&req.state().aggregator, query, day)
+    }
+
+    fn fetch_range(req: &HttpRequest<Self>) -> APIFuture<(Days, Forecast)> {
+        let query = match Path::<WeatherQuery>::extract(req) {
+            Ok(query) => traced(req, query.into_inner()),
+            Err(reason) => return APIError::BadRequest(reason).into_responder(),
+        };
+
+        let params = match Query::<RangeParams>::extract(req) {
+            Ok(params) => params.into_inner(),
+            Err(reason) => return APIError::BadRequest(reason).into_responder(),
+        };
+
+        let (from, to) = match (
+            NaiveDate::parse_from_str(&params.from, "%Y-%m-%d"),
+            NaiveDate::parse_from_str(&params.to, "%Y-%m-%d"),
+        ) {
+            (Ok(from), Ok(to)) => (from, to),
+            (Err(reason), _) | (_, Err(reason)) => {
+                return APIError::InvalidDate(reason).into_responder()
+            }
+        };
+
+        Self::range(&req.state().aggregator, query, from, to)
+    }
+
+    fn fetch_weekly(req: &HttpRequest<Self>) -> APIFuture<(Days, Forecast)> {
+        let query = match Path::<WeatherQuery>::extract(req) {
+            Ok(query) => traced(req, query.into_inner()),
+            Err(reason) => return APIError::BadRequest(reason).into_responder(),
+        };
+
+        let days = match Query::<WeeklyParams>::extract(req) {
+            Ok(params) => params.days.unwrap_or(WEEKLY_FORECAST_DAYS),
+            Err(reason) => return APIError::BadRequest(reason).into_responder(),
+        };
+
+        Self::weekly(&req.state().aggregator, query, days)
+    }
+
+    fn daily(
+        aggregator: &Recipient<ForecastQuery>,
+        query: WeatherQuery,
+        day: NaiveDate,
+    ) -> APIFuture<(WeatherData, Forecast)> {
+        let data = aggregator
+            .send(ForecastQuery(query))
+            .map(move |res| match res {
+                Ok(forecast) => forecast
+                    .data
+                    .iter()
+                    .find(|e| e.date == day)
+                    .cloned()
+                    .ok_or(APIError::NotFound(day))
+                    .map(|res| (res, forecast)),
+                Err(reason) => Err(APIError::from_aggregator(reason)),
+            }).map_err(|err| APIError::UnexpectedError(Error::from(err)));
+
+        Box::new(data)
+    }
+
+    /// Возвращает по записи на каждый день диапазона `from..=to`. Дни, на
+    /// которые прогноза нет, отдаются как `null`. Диапазон не может выходить
+    /// за горизонт прогноза самого дальнобойного API, отсчитанный от
+    /// сегодняшнего дня по местному времени города.
+    fn range(
+        aggregator: &Recipient<ForecastQuery>,
+        query: WeatherQuery,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> APIFuture<(Days, Forecast)> {
+        if from > to {
+            let reason = format!("{} is after {}", from, to);
+            return APIError::InvalidRange(reason).into_responder();
+        }
+
+        let data = aggregator
+            .send(ForecastQuery(query))
+            .map(move |res| match res {
+                Ok(forecast) => {
+                    let today = forecast.today();
+                    let horizon = today + chrono::Duration::days(MAX_FORECAST_DAYS - 1);
+                    if from < today {
+                        let reason = format!("forecast is only available from {}", today);
+                        return Err(APIError::InvalidRange(reason));
+                    }
+                    if to > horizon {
+                        let reason = format!("forecast is only available until {}", horizon);
+                        return Err(APIError::InvalidRange(reason));
+                    }
+
+                    let days = (to - from).num_days() + 1;
+                    let data = (0..days)
+                        .map(|offset| from + chrono::Duration::days(offset))
+                        .map(|day| forecast.data.iter().find(|e| e.date == day).cloned())
+                        .collect();
+                    Ok((Days { from, data }, forecast))
+                }
+                Err(reason) => Err(APIError::from_aggregator(reason)),
+            }).map_err(|err| APIError::UnexpectedError(Error::from(err)));
+
+        Box::new(data)
+    }
+
+    /// Возвращает по записи на каждый из `days` дней начиная с сегодняшнего
+    /// по местному времени города (по умолчанию 5). Дни без прогноза отдаются
+    /// как `null`, ошибка возвращается, только если прогноза нет ни на один день.
+    fn weekly(
+        aggregator: &Recipient<ForecastQuery>,
+        query: WeatherQuery,
+        days: i64,
+    ) -> APIFuture<(Days, Forecast)> {
+        if !(1..=MAX_FORECAST_DAYS).contains(&days) {
+            let reason = format!("length must be between 1 and {} days", MAX_FORECAST_DAYS);
+            return APIError::InvalidRange(reason).into_responder();
+        }
+
+        let data = aggregator
+            .send(ForecastQuery(query))
+            .map(move |res| match res {
+                Ok(forecast) => {
+                    let today = forecast.today();
+                    let data: Vec<_> = (0..days)
+                        .map(|offset| today + chrono::Duration::days(offset))
+                        .map(|day| forecast.data.iter().find(|e| e.date == day).cloned())
+                        .collect();
+                    if data.iter().all(|e| e.is_none()) {
+                        return Err(APIError::InsufficientData);
+                    }
+                    Ok((Days { from: today, data }, forecast))
+                }
+                Err(reason) => Err(APIError::from_aggregator(reason)),
+            }).map_err(|err| APIError::UnexpectedError(Error::from(err)));
+
+        Box::new(data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix::prelude::*;
+    use actix_web::test;
+    use chrono::Utc;
+    use failure::err_msg;
+
+    use super::*;
+    use apis::WeatherDataVec;
+
+    struct TestWeatherActor;
+
+    impl Actor for TestWeatherActor {
+        type Context = SyncContext<Self>;
+    }
+
+    fn forecast(data: WeatherDataVec) -> Forecast {
+        Forecast {
+            query: WeatherQuery::new("UK".to_string(), "London".to_string()),
+            data,
+            sources: vec!["test".to_string()],
+            utc_offset: None,
+            fetched_at: Utc::now() - chrono::Duration::hours(1),
+            expires_at: Utc::now() + chrono::Duration::hours(1),
+        }
+    }
+
+    impl Handler<ForecastQuery> for TestWeatherActor {
+        type Result = Result<Forecast, Error>;
+
+        fn handle(&mut self, _msg: ForecastQuery, _ctx: &mut Self::Context) -> Self::Result {
+            let today = Utc::now().naive_utc().date();
+
+            // Прогноз на 5 дней с пропущенным третьим днём.
+            let vec = [0, 1, 3, 4]
+                .iter()
+                .map(|day| WeatherData {
+                    temperature: 10.0,
+                    date: today + chrono::Duration::days(*day),
+                }).collect();
+
+            Ok(forecast(vec))
+        }
+    }
+
+    struct EmptyWeatherActor;
+
+    impl Actor for EmptyWeatherActor {
+        type Context = SyncContext<Self>;
+    }
+
+    impl Handler<ForecastQuery> for EmptyWeatherActor {
+        type Result = Result<Forecast, Error>;
+
+        fn handle(&mut self, _msg: ForecastQuery, _ctx: &mut Self::Context) -> Self::Result {
+            let vec = WeatherDataVec::new();
+            Ok(forecast(vec))
+        }
+    }
+
+    struct FailingWeatherActor;
+
+    impl Actor for FailingWeatherActor {
+        type Context = SyncContext<Self>;
+    }
+
+    impl Handler<ForecastQuery> for FailingWeatherActor {
+        type Result = Result<Forecast, Error>;
+
+        fn handle(&mut self, _msg: ForecastQuery, _ctx: &mut Self::Context) -> Self::Result {
+            Err(err_msg("test"))
+        }
+    }
+
+    struct UnknownLocationActor;
+
+    impl Actor for UnknownLocationActor {
+        type Context = SyncContext<Self>;
+    }
+
+    impl Handler<ForecastQuery> for UnknownLocationActor {
+        type Result = Result<Forecast, Error>;
+
+        fn handle(&mut self, msg: ForecastQuery, _ctx: &mut Self::Context) -> Self::Result {
+            let ForecastQuery(query) = msg;
+            Err(Error::from(UnknownLocation(query)))
+        }
+    }
+
+    fn init_test_server<F: Fn() -> WebAPI + Sync + Send + 'static>(init_fn: F) -> test::TestServer {
+        test::TestServer::build_with_state(init_fn).start(|app: &mut test::TestApp<WebAPI>| {
+            app.resource("/forecast/daily/{country}/{city}/{day}", |r| {
+                r.method(http::Method::GET).f(WebAPI::daily_forecast)
+            }).resource("/forecast/weekly/{country}/{city}", |r| {
+                r.method(http::Method::GET).f(WebAPI::weekly_forecast)
+            }).resource("/forecast/range/{country}/{city}", |r| {
+                r.method(http::Method::GET).f(WebAPI::range_forecast)
+            });
+        })
+    }
+
+    #[test]
+    fn normal_path() {
+        let mut srv = init_test_server(|| {
+            let weather_actor = SyncArbiter::start(1, || TestWeatherActor {});
+            WebAPI::new(weather_actor.recipient())
+        });
+
+        let now = Utc::now().format("%Y-%m-%d");
+
+        let request = srv
+            .client(
+                http::Method::GET,
+                &format!("/forecast/daily/UK/London/{}", now),
+            ).finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert!(response.status().is_success());
+
+        let data: WeatherData = srv
+            .execute(response.json())
+            .expect("Failed to parse response as JSON");
+
+        assert_eq!(data.temperature, 10.0);
+
+        let request = srv
+            .client(http::Method::GET, "/forecast/weekly/UK/London")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert!(response.status().is_success());
+
+        let data: Vec<Option<WeatherData>> = srv
+            .execute(response.json())
+            .expect("Failed to parse response as JSON");
+        assert_eq!(data.len(), 5);
+        assert_eq!(data[0].as_ref().map(|e| e.temperature), Some(10.0));
+        assert!(data[2].is_none());
+        assert_eq!(data[4].as_ref().map(|e| e.temperature), Some(10.0));
+
+        let request = srv
+            .client(http::Method::GET, "/forecast/weekly/UK/London?days=7")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert!(response.status().is_success());
+
+        let data: Vec<Option<WeatherData>> = srv
+            .execute(response.json())
+            .expect("Failed to parse response as JSON");
+        assert_eq!(data.len(), 7);
+        assert!(data[5].is_none() && data[6].is_none());
+
+        let request = srv
+            .client(http::Method::GET, "/forecast/weekly/UK/London?days=0")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn error_path() {
+        let mut srv = init_test_server(|| {
+            let weather_actor = SyncArbiter::start(1, || TestWeatherActor {});
+            WebAPI::new(weather_actor.recipient())
+        });
+
+        let request = srv
+            .client(http::Method::GET, "/forecast/daily/UK/London/invalid-date")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert!(response.status().is_client_error());
+
+        let data: APIErrorResponse = srv
+            .execute(response.json())
+            .expect("Failed to parse response as JSON");
+
+        assert_eq!(
+            data.error,
+            "failed to parse date - input contains invalid characters"
+        );
+
+        let request = srv
+            .client(http::Method::GET, "/forecast/daily/UK/London/2077-01-01")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert!(response.status().is_client_error());
+
+        let data: APIErrorResponse = srv
+            .execute(response.json())
+            .expect("Failed to parse response as JSON");
+
+        assert_eq!(
+            data.error,
+            "weather data not found for given day - 2077-01-01"
+        );
+    }
+
+    #[test]
+    fn empty_data() {
+        let mut srv = init_test_server(|| {
+            let weather_actor = SyncArbiter::start(1, || EmptyWeatherActor {});
+            WebAPI::new(weather_actor.recipient())
+        });
+
+        let request = srv
+            .client(http::Method::GET, "/forecast/weekly/UK/London")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert!(response.status().is_server_error());
+
+        let data: APIErrorResponse = srv
+            .execute(response.json())
+            .expect("Failed to parse response as JSON");
+        assert_eq!(
+            data.error,
+            "insufficient weather data for weekly forecast"
+        )
+    }
+
+    #[test]
+    fn failing_actor() {
+        let mut srv = init_test_server(|| {
+            let weather_actor = SyncArbiter::start(1, || FailingWeatherActor {});
+            WebAPI::new(weather_actor.recipient())
+        });
+
+        let request = srv
+            .client(http::Method::GET, "/forecast/weekly/UK/London")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert!(response.status().is_server_error());
+
+        let data: APIErrorResponse = srv
+            .execute(response.json())
+            .expect("Failed to parse response as JSON");
+        assert_eq!(
+            data.error,
+            "An internal error occurred. Please try again later."
+        )
+    }
+
+    #[test]
+    fn conditional_requests() {
+        let mut srv = init_test_server(|| {
+            let weather_actor = SyncArbiter::start(1, || TestWeatherActor {});
+            WebAPI::new(weather_actor.recipient())
+        });
+
+        let request = srv
+            .client(http::Method::GET, "/forecast/weekly/UK/London")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert!(response.status().is_success());
+
+        let etag = response
+            .headers()
+            .get(header::ETAG)
+            .expect("ETag is missing")
+            .clone();
+        let last_modified = response
+            .headers()
+            .get(header::LAST_MODIFIED)
+            .expect("Last-Modified is missing")
+            .clone();
+        let cache_control = response
+            .headers()
+            .get(header::CACHE_CONTROL)
+            .expect("Cache-Control is missing")
+            .to_str()
+            .expect("Cache-Control is not a string")
+            .to_string();
+
+        assert!(cache_control.contains("max-age="));
+
+        let request = srv
+            .client(http::Method::GET, "/forecast/weekly/UK/London")
+            .header(header::IF_NONE_MATCH, etag.clone())
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert_eq!(response.status(), http::StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers().get(header::ETAG), Some(&etag));
+
+        let request = srv
+            .client(http::Method::GET, "/forecast/weekly/UK/London")
+            .header(header::IF_MODIFIED_SINCE, last_modified)
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert_eq!(response.status(), http::StatusCode::NOT_MODIFIED);
+
+        let request = srv
+            .client(http::Method::GET, "/forecast/weekly/UK/London")
+            .header(header::IF_NONE_MATCH, "\"outdated\"")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+    }
+
+    #[test]
+    fn unknown_location() {
+        let mut srv = init_test_server(|| {
+            let weather_actor = SyncArbiter::start(1, || UnknownLocationActor {});
+            WebAPI::new(weather_actor.recipient())
+        });
+
+        let request = srv
+            .client(http::Method::GET, "/forecast/weekly/UK/Londn")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+
+        let data: APIErrorResponse = srv
+            .execute(response.json())
+            .expect("Failed to parse response as JSON");
+        assert_eq!(data.error, "location not found - UK/Londn");
+    }
+
+    #[test]
+    fn content_negotiation() {
+        let mut srv = init_test_server(|| {
+            let weather_actor = SyncArbiter::start(1, || TestWeatherActor {});
+            WebAPI::new(weather_actor.recipient())
+        });
+
+        let request = srv
+            .client(http::Method::GET, "/forecast/weekly/UK/London?days=2&format=csv")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert!(response.status().is_success());
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE),
+            Some(&header::HeaderValue::from_static("text/csv; charset=utf-8"))
+        );
+
+        let body = srv
+            .execute(response.body())
+            .expect("Failed to read response body");
+        let body = String::from_utf8(body.to_vec()).expect("CSV is not UTF-8");
+        assert!(body.starts_with("date,temperature\n"));
+        assert_eq!(body.lines().count(), 3);
+
+        let request = srv
+            .client(http::Method::GET, "/forecast/daily/UK/London/2077-01-01")
+            .header(header::ACCEPT, "application/xml")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE),
+            Some(&header::HeaderValue::from_static("application/xml"))
+        );
+
+        let body = srv
+            .execute(response.body())
+            .expect("Failed to read response body");
+        assert_eq!(
+            String::from_utf8(body.to_vec()).expect("XML is not UTF-8"),
+            "<response><error>weather data not found for given day - 2077-01-01</error></response>"
+        );
+
+        let request = srv
+            .client(http::Method::GET, "/forecast/weekly/UK/London?format=yaml")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn range_forecast() {
+        let mut srv = init_test_server(|| {
+            let weather_actor = SyncArbiter::start(1, || TestWeatherActor {});
+            WebAPI::new(weather_actor.recipient())
+        });
+
+        let today = Utc::now().naive_utc().date();
+        let uri = format!(
+            "/forecast/range/UK/London?from={}&to={}",
+            today,
+            today + chrono::Duration::days(2)
+        );
+
+        let request = srv
+            .client(http::Method::GET, &uri)
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert!(response.status().is_success());
+
+        let data: Vec<Option<WeatherData>> = srv
+            .execute(response.json())
+            .expect("Failed to parse response as JSON");
+
+        assert_eq!(data.len(), 3);
+        assert_eq!(data[0].as_ref().map(|e| e.date), Some(today));
+        assert!(data[1].is_some());
+        assert!(data[2].is_none());
+
+        let uri = format!(
+            "/forecast/range/UK/London?from={}&to={}",
+            today,
+            today + chrono::Duration::days(MAX_FORECAST_DAYS)
+        );
+
+        let request = srv
+            .client(http::Method::GET, &uri)
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+
+        let uri = format!(
+            "/forecast/range/UK/London?from={}&to={}",
+            today - chrono::Duration::days(1),
+            today
+        );
+
+        let request = srv
+            .client(http::Method::GET, &uri)
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+
+        let uri = format!(
+            "/forecast/range/UK/London?from={}&to={}",
+            today + chrono::Duration::days(1),
+            today
+        );
+
+        let request = srv
+            .client(http::Method::GET, &uri)
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert_eq!(response.status(), http::StatusCode::BAD_REQUEST);
+
+        let data: APIErrorResponse = srv
+            .execute(response.json())
+            .expect("Failed to parse response as JSON");
+        assert!(data.error.starts_with("invalid date range"));
+    }
+}
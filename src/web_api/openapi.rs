@@ -0,0 +1,411 @@
+use actix_web::{http, App, HttpRequest, HttpResponse};
+
+use super::WebAPI;
+
+/// Описание HTTP API в формате OpenAPI 3. Тесты ниже проверяют, что оно
+/// не расходится с маршрутами `WebAPI` и типами ответов.
+const OPENAPI: &str = include_str!("openapi.json");
+
+impl WebAPI {
+    pub(super) fn openapi_routes(app: App<Self>) -> App<Self> {
+        app.resource("/openapi.json", |r| {
+            r.method(http::Method::GET).f(Self::openapi)
+        })
+    }
+
+    fn openapi(_req: &HttpRequest<Self>) -> HttpResponse {
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .body(OPENAPI)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix::prelude::*;
+    use actix_web::{test, HttpMessage};
+    use actix_web::{error::ResponseError, Body};
+    use chrono::{DateTime, Duration, NaiveDate, Utc};
+    use failure::Error;
+    use serde::Serialize;
+    use serde_json::{self, Value};
+
+    use super::*;
+    use aggregator::{Aggregator, CacheKeyInfo, CacheStats};
+    use apis::{WeatherData, WeatherQuery, WeatherReport};
+    use clients::{ClientKey, Clients};
+    use metrics::Metrics;
+    use web_api::v2::V2Error;
+    use web_api::{
+        APIError, CacheAdmin, ForecastSubscriptions, GraphQLExecutor, HealthCheck, PrometheusMetrics,
+        V1_PREFIXES,
+    };
+
+    /// Исходники модулей, регистрирующих маршруты `WebAPI`.
+    const ROUTE_SOURCES: [&str; 10] = [
+        include_str!("mod.rs"),
+        include_str!("admin.rs"),
+        include_str!("graphql.rs"),
+        include_str!("health.rs"),
+        include_str!("metrics.rs"),
+        include_str!("openapi.rs"),
+        include_str!("providers.rs"),
+        include_str!("sse.rs"),
+        include_str!("v2.rs"),
+        include_str!("ws.rs"),
+    ];
+
+    struct TestWeatherActor;
+
+    impl Actor for TestWeatherActor {
+        type Context = SyncContext<Self>;
+    }
+
+    impl Handler<WeatherQuery> for TestWeatherActor {
+        type Result = Result<WeatherReport, Error>;
+
+        fn handle(&mut self, _msg: WeatherQuery, _ctx: &mut Self::Context) -> Self::Result {
+            let today = Utc::now().naive_utc().date();
+
+            Ok(WeatherReport {
+                data: (0..16)
+                    .map(|day| WeatherData {
+                        temperature: 10.0,
+                        date: today + Duration::days(day),
+                    }).collect(),
+                hourly: Vec::new(),
+                utc_offset: None,
+            })
+        }
+    }
+
+    fn spec() -> Value {
+        serde_json::from_str(OPENAPI).expect("Failed to parse OpenAPI document")
+    }
+
+    /// Разворачивает `$ref` на компонент того же документа.
+    fn resolve<'a>(spec: &'a Value, value: &'a Value) -> &'a Value {
+        match value.get("$ref").and_then(|reference| reference.as_str()) {
+            Some(reference) => {
+                let target = spec
+                    .pointer(reference.trim_start_matches('#'))
+                    .unwrap_or_else(|| panic!("Unresolved reference {}", reference));
+                resolve(spec, target)
+            }
+            None => value,
+        }
+    }
+
+    /// Проверяет значение на соответствие схеме. Поддерживается только то
+    /// подмножество JSON Schema, которое используется в документе.
+    fn check(spec: &Value, schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+        let schema = resolve(spec, schema);
+
+        if value.is_null() && schema["nullable"].as_bool().unwrap_or(false) {
+            return Ok(());
+        }
+
+        if let Some(all_of) = schema["allOf"].as_array() {
+            for schema in all_of {
+                check(spec, schema, value, path)?;
+            }
+            return Ok(());
+        }
+
+        if let Some(one_of) = schema["oneOf"].as_array() {
+            let matched = one_of
+                .iter()
+                .filter(|schema| check(spec, schema, value, path).is_ok())
+                .count();
+            return if matched == 1 {
+                Ok(())
+            } else {
+                Err(format!("{}: {} matches {} of oneOf schemas", path, value, matched))
+            };
+        }
+
+        match schema["type"].as_str() {
+            Some("object") => {
+                let object = value
+                    .as_object()
+                    .ok_or_else(|| format!("{}: expected object, got {}", path, value))?;
+                let properties = schema["properties"].as_object();
+
+                for required in schema["required"].as_array().into_iter().flatten() {
+                    let required = required.as_str().expect("Invalid required property");
+                    if !object.contains_key(required) {
+                        return Err(format!("{}: missing property {}", path, required));
+                    }
+                }
+
+                for (key, property) in object {
+                    match properties.and_then(|properties| properties.get(key)) {
+                        Some(property_schema) => {
+                            check(spec, property_schema, property, &format!("{}.{}", path, key))?
+                        }
+                        None => {
+                            if !schema["additionalProperties"].as_bool().unwrap_or(true) {
+                                return Err(format!("{}: undocumented property {}", path, key));
+                            }
+                        }
+                    }
+                }
+            }
+            Some("array") => {
+                let array = value
+                    .as_array()
+                    .ok_or_else(|| format!("{}: expected array, got {}", path, value))?;
+
+                if let Some(min_items) = schema["minItems"].as_u64() {
+                    if (array.len() as u64) < min_items {
+                        return Err(format!("{}: too few items", path));
+                    }
+                }
+                if let Some(max_items) = schema["maxItems"].as_u64() {
+                    if array.len() as u64 > max_items {
+                        return Err(format!("{}: too many items", path));
+                    }
+                }
+
+                for (i, item) in array.iter().enumerate() {
+                    check(spec, &schema["items"], item, &format!("{}[{}]", path, i))?;
+                }
+            }
+            Some("string") => {
+                let string = value
+                    .as_str()
+                    .ok_or_else(|| format!("{}: expected string, got {}", path, value))?;
+
+                match schema["format"].as_str() {
+                    Some("date") => {
+                        NaiveDate::parse_from_str(string, "%Y-%m-%d")
+                            .map_err(|_| format!("{}: invalid date {}", path, string))?;
+                    }
+                    Some("date-time") => {
+                        DateTime::parse_from_rfc3339(string)
+                            .map_err(|_| format!("{}: invalid date-time {}", path, string))?;
+                    }
+                    _ => {}
+                }
+            }
+            Some("number") if value.is_number() => {}
+            Some("integer") if value.is_i64() || value.is_u64() => {}
+            Some("boolean") if value.is_boolean() => {}
+            Some(expected @ "number") | Some(expected @ "integer") | Some(expected @ "boolean") => {
+                return Err(format!("{}: expected {}, got {}", path, expected, value))
+            }
+            _ => panic!("{}: unsupported schema {}", path, schema),
+        }
+
+        Ok(())
+    }
+
+    fn validate(spec: &Value, schema: &Value, value: &Value, path: &str) {
+        check(spec, schema, value, path).unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    fn validate_type<T: Serialize>(spec: &Value, name: &str, value: &T) {
+        let schema = json!({ "$ref": format!("#/components/schemas/{}", name) });
+        let value = serde_json::to_value(value).expect("Failed to serialize value");
+
+        validate(spec, &schema, &value, name);
+    }
+
+    fn validate_error<E: ResponseError>(spec: &Value, name: &str, err: &E) {
+        let response = err.error_response();
+        let value: Value = match *response.body() {
+            Body::Binary(ref body) => {
+                serde_json::from_slice(body.as_ref()).expect("Failed to parse error body")
+            }
+            _ => panic!("{}: unexpected error body", name),
+        };
+
+        validate_type(spec, name, &value);
+    }
+
+    /// Подставляет в шаблон пути и строку запроса примеры параметров из
+    /// документа. Вместо примеров дат берётся сегодняшняя, чтобы прогноз нашёлся.
+    fn example_uri(spec: &Value, path: &str, operation: &Value) -> String {
+        let today = Utc::now().format("%Y-%m-%d").to_string();
+
+        let mut uri = path.to_string();
+        let mut query = Vec::new();
+
+        let parameters = operation["parameters"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|parameter| resolve(spec, parameter));
+
+        for parameter in parameters {
+            let name = parameter["name"].as_str().expect("Parameter without name");
+            let example = match (parameter["schema"]["format"].as_str(), &parameter["example"]) {
+                (Some("date"), _) => today.clone(),
+                (_, Value::String(example)) => example.clone(),
+                (_, Value::Number(example)) => example.to_string(),
+                _ => panic!("Parameter {} has no example", name),
+            };
+
+            match parameter["in"].as_str() {
+                Some("path") => uri = uri.replace(&format!("{{{}}}", name), &example),
+                Some("query") => query.push(format!("{}={}", name, example)),
+                _ => panic!("Parameter {} has unsupported location", name),
+            }
+        }
+
+        if !query.is_empty() {
+            uri.push('?');
+            uri.push_str(&query.join("&"));
+        }
+
+        uri
+    }
+
+    /// Шаблоны путей, которые модули `WebAPI` (без их тестов) передают
+    /// в `App::resource`. Шаблоны вида `format!("{}/...", prefix)`
+    /// регистрируются для каждого префикса из `V1_PREFIXES`.
+    fn registered_routes() -> Vec<String> {
+        let mut routes = Vec::new();
+
+        for source in ROUTE_SOURCES.iter() {
+            let code = source.split("#[cfg(test)]").next().unwrap_or_default();
+
+            for call in code.split(".resource(").skip(1) {
+                let call = call.trim_start();
+                let prefixed = call.starts_with("&format!(");
+                if !prefixed && !call.starts_with('"') {
+                    continue;
+                }
+
+                let template = call.split('"').nth(1).expect("Unterminated route template");
+                if prefixed {
+                    let template = template
+                        .trim_start_matches("{}")
+                        .replace("{{", "{")
+                        .replace("}}", "}");
+                    let prefixed = V1_PREFIXES.iter().map(|prefix| format!("{}{}", prefix, template));
+                    routes.extend(prefixed);
+                } else {
+                    routes.push(template.to_string());
+                }
+            }
+        }
+
+        routes
+    }
+
+    #[test]
+    fn routes_match_spec() {
+        let spec = spec();
+
+        let paths = spec["paths"].as_object().expect("No paths in spec");
+        let routes = registered_routes();
+        assert!(routes.contains(&"/v1/forecast/batch".to_string()));
+        for route in &routes {
+            assert!(paths.contains_key(route), "{} is missing from spec", route);
+        }
+
+        let mut srv = test::TestServer::with_factory(|| {
+            let weather_actor = SyncArbiter::start(1, || TestWeatherActor {});
+            let aggregator = Aggregator::new()
+                .add_api("test", weather_actor.recipient())
+                .start();
+            let admin = CacheAdmin::new("secret".to_string(), &aggregator);
+            let subscriptions = ForecastSubscriptions::new(&aggregator);
+            let health = HealthCheck::new(1, &aggregator);
+            let metrics = PrometheusMetrics::new(&Metrics::new().start(), &aggregator);
+            let graphql = {
+                let aggregator = aggregator.clone();
+                SyncArbiter::start(1, move || GraphQLExecutor::new(&aggregator))
+            };
+            let clients = Clients::new(vec![ClientKey {
+                name: "test".to_string(),
+                key: "client-key".to_string(),
+                rate_limit: None,
+            }]).start();
+
+            WebAPI::new(aggregator.clone().recipient())
+                .admin(admin)
+                .graphql(graphql)
+                .stream(aggregator.clone().recipient())
+                .subscriptions(subscriptions)
+                .health(health)
+                .metrics(metrics)
+                .providers(aggregator.recipient())
+                .clients(clients.recipient())
+                .app()
+        });
+
+        for (path, item) in paths {
+            let operations = resolve(&spec, item)
+                .as_object()
+                .expect("Invalid path item");
+
+            for (method, operation) in operations {
+                let uri = example_uri(&spec, path, operation);
+                let method = http::Method::from_bytes(method.to_uppercase().as_bytes())
+                    .expect("Invalid method");
+
+                let mut request = srv.client(method.clone(), &uri);
+                request.header(http::header::AUTHORIZATION, "Bearer secret");
+                request.header("x-api-key", "client-key");
+
+                let body = &operation["requestBody"]["content"]["application/json"]["example"];
+                let request = if body.is_null() {
+                    request.finish()
+                } else {
+                    request.json(body)
+                }.expect("Failed to construct test request");
+                let response = srv
+                    .execute(request.send())
+                    .expect("Failed to send test request");
+
+                let status = response.status();
+                let documented = operation["responses"]
+                    .get(status.as_str())
+                    .unwrap_or_else(|| {
+                        panic!("{} {} responded with undocumented {}", method, uri, status)
+                    });
+
+                let schema = &resolve(&spec, documented)["content"]["application/json"]["schema"];
+                if !schema.is_null() {
+                    let body: Value = srv
+                        .execute(response.json())
+                        .unwrap_or_else(|_| panic!("{} {} returned invalid JSON", method, uri));
+                    validate(&spec, schema, &body, &format!("{} {}", method, path));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn types_match_spec() {
+        let spec = spec();
+
+        validate_type(
+            &spec,
+            "WeatherData",
+            &WeatherData {
+                temperature: 10.0,
+                date: Utc::now().naive_utc().date(),
+            },
+        );
+        validate_error(&spec, "APIErrorResponse", &APIError::InsufficientData);
+        validate_error(
+            &spec,
+            "ErrorEnvelope",
+            &V2Error(APIError::InsufficientData),
+        );
+        validate_type(
+            &spec,
+            "CacheKeyInfo",
+            &CacheKeyInfo {
+                query: WeatherQuery::new("UK".to_string(), "London".to_string()),
+                age: 0,
+                providers: 1,
+                size: 5,
+            },
+        );
+        validate_type(&spec, "CacheStats", &CacheStats::default());
+    }
+}
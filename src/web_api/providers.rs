@@ -0,0 +1,102 @@
+use actix_web::{http, App, HttpRequest, Json};
+use failure::Error;
+use futures::Future;
+
+use aggregator::{ListProviders, ProviderInfo};
+
+use super::{APIError, APIFuture, WebAPI};
+
+/// Погодные API, которые опрашивает сервис.
+#[derive(Serialize, Deserialize)]
+pub(super) struct ProviderList {
+    pub(super) providers: Vec<ProviderInfo>,
+}
+
+impl WebAPI {
+    pub(super) fn providers_routes(app: App<Self>) -> App<Self> {
+        app.resource("/providers", |r| {
+            r.method(http::Method::GET).f(Self::list_providers)
+        })
+    }
+
+    /// API, с которыми сервис запущен: те, для которых не хватило ключей,
+    /// сюда не попадают.
+    fn list_providers(req: &HttpRequest<Self>) -> APIFuture<Json<ProviderList>> {
+        let providers = match req.state().providers {
+            Some(ref providers) => providers,
+            None => {
+                let reason = format_err!("provider list is not configured");
+                return APIError::UnexpectedError(reason).into_responder();
+            }
+        };
+
+        let list = providers
+            .send(ListProviders)
+            .map(|providers| Ok(Json(ProviderList { providers })))
+            .map_err(|err| APIError::UnexpectedError(Error::from(err)));
+
+        Box::new(list)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix::prelude::*;
+    use actix_web::{test, HttpMessage};
+    use chrono::Utc;
+
+    use super::*;
+    use aggregator::Aggregator;
+    use apis::{WeatherData, WeatherQuery, WeatherReport};
+
+    struct TestWeatherActor;
+
+    impl Actor for TestWeatherActor {
+        type Context = SyncContext<Self>;
+    }
+
+    impl Handler<WeatherQuery> for TestWeatherActor {
+        type Result = Result<WeatherReport, Error>;
+
+        fn handle(&mut self, _msg: WeatherQuery, _ctx: &mut Self::Context) -> Self::Result {
+            Ok(WeatherReport::from(smallvec![WeatherData {
+                temperature: 10.0,
+                date: Utc::now().naive_utc().date(),
+            }]))
+        }
+    }
+
+    #[test]
+    fn lists_active_providers() {
+        let mut srv = test::TestServer::with_factory(|| {
+            let first = SyncArbiter::start(1, || TestWeatherActor {});
+            let second = SyncArbiter::start(1, || TestWeatherActor {});
+            let aggregator = Aggregator::new()
+                .add_api("first", first.recipient())
+                .add_api("second", second.recipient())
+                .weight("second", 2.0)
+                .start();
+
+            WebAPI::new(aggregator.clone().recipient())
+                .providers(aggregator.recipient())
+                .app()
+        });
+
+        let request = srv
+            .client(http::Method::GET, "/providers")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+        assert!(response.status().is_success());
+
+        let list: ProviderList = srv.execute(response.json()).expect("Invalid provider list");
+        let providers: Vec<(&str, f32)> = list
+            .providers
+            .iter()
+            .map(|provider| (provider.name.as_str(), provider.weight))
+            .collect();
+        assert_eq!(providers, vec![("first", 1.0), ("second", 2.0)]);
+    }
+}
@@ -0,0 +1,230 @@
+use actix_web::http::header;
+use actix_web::{error, http, App, FromRequest, HttpRequest, HttpResponse, Path};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use failure::Error;
+use futures::{Future, Stream};
+use serde::Serialize;
+use serde_json;
+
+use aggregator::{Forecast, ForecastEvent, StreamForecast};
+use apis::{WeatherData, WeatherQuery};
+
+use super::tracing::traced;
+use super::v2::EnvelopeError;
+use super::{APIError, APIFuture, WebAPI, V1_PREFIXES};
+
+/// Агрегат на момент события.
+#[derive(Serialize, Deserialize)]
+struct Aggregate {
+    data: Vec<WeatherData>,
+    sources: Vec<String>,
+    fetched_at: DateTime<Utc>,
+}
+
+impl<'a> From<&'a Forecast> for Aggregate {
+    fn from(forecast: &'a Forecast) -> Self {
+        Self {
+            data: forecast.data.to_vec(),
+            sources: forecast.sources.clone(),
+            fetched_at: forecast.fetched_at,
+        }
+    }
+}
+
+/// Событие `provider`: данные одного API (`null`, если он не ответил)
+/// и агрегат по всем полученным на этот момент данным.
+#[derive(Serialize, Deserialize)]
+struct ProviderEvent {
+    provider: String,
+    data: Option<Vec<WeatherData>>,
+    aggregate: Aggregate,
+}
+
+/// Одно событие в формате `text/event-stream`.
+fn event<D: Serialize>(name: &str, data: &D) -> Result<Bytes, Error> {
+    let data = serde_json::to_string(data)?;
+
+    Ok(Bytes::from(format!("event: {}\ndata: {}\n\n", name, data)))
+}
+
+fn encode(forecast_event: ForecastEvent) -> Result<Bytes, Error> {
+    match forecast_event {
+        ForecastEvent::Provider {
+            name,
+            data,
+            aggregate,
+        } => event(
+            "provider",
+            &ProviderEvent {
+                provider: name,
+                data,
+                aggregate: Aggregate::from(&aggregate),
+            },
+        ),
+        ForecastEvent::Done(Ok(forecast)) => event("forecast", &Aggregate::from(&forecast)),
+        ForecastEvent::Done(Err(reason)) => {
+            let reason = APIError::from_aggregator(reason);
+            if let APIError::UnexpectedError(ref err) = reason {
+                error!("Forecast stream failed: {}", err);
+            }
+
+            event("error", &EnvelopeError::from(&reason))
+        }
+    }
+}
+
+impl WebAPI {
+    pub(super) fn sse_routes(app: App<Self>) -> App<Self> {
+        let mut app = app;
+
+        for prefix in &V1_PREFIXES {
+            app = app.resource(
+                &format!("{}/forecast/stream/{{country}}/{{city}}", prefix),
+                |r| r.method(http::Method::GET).f(Self::stream_forecast),
+            );
+        }
+
+        app
+    }
+
+    /// Прогноз в виде потока Server-Sent Events: событие `provider` на каждый
+    /// ответ API и в конце `forecast` с итоговым агрегатом или `error`.
+    fn stream_forecast(req: &HttpRequest<Self>) -> APIFuture<HttpResponse> {
+        let stream = match req.state().stream {
+            Some(ref stream) => stream.clone(),
+            None => {
+                let reason = format_err!("forecast streaming is not configured");
+                return APIError::UnexpectedError(reason).into_responder();
+            }
+        };
+
+        let query = match Path::<WeatherQuery>::extract(req) {
+            Ok(query) => traced(req, query.into_inner()),
+            Err(reason) => return APIError::BadRequest(reason).into_responder(),
+        };
+
+        let response = stream
+            .send(StreamForecast(query))
+            .map(|events| {
+                let body = events
+                    .map_err(|()| error::ErrorInternalServerError("forecast stream closed"))
+                    .and_then(|forecast_event| {
+                        encode(forecast_event).map_err(error::ErrorInternalServerError)
+                    });
+
+                Ok(HttpResponse::Ok()
+                    .content_type("text/event-stream")
+                    .set(header::CacheControl(vec![header::CacheDirective::NoCache]))
+                    .streaming(body))
+            }).map_err(|err| APIError::UnexpectedError(Error::from(err)));
+
+        Box::new(response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    use actix::prelude::*;
+    use actix_web::test;
+
+    use super::*;
+    use aggregator::Aggregator;
+    use apis::WeatherReport;
+
+    struct TestWeatherActor;
+
+    impl Actor for TestWeatherActor {
+        type Context = SyncContext<Self>;
+    }
+
+    impl Handler<WeatherQuery> for TestWeatherActor {
+        type Result = Result<WeatherReport, Error>;
+
+        fn handle(&mut self, msg: WeatherQuery, _ctx: &mut Self::Context) -> Self::Result {
+            if msg.city() != "London" {
+                return Ok(WeatherReport::from(smallvec![]));
+            }
+
+            Ok(WeatherReport::from(smallvec![WeatherData {
+                temperature: 10.0,
+                date: Utc::now().naive_utc().date(),
+            }]))
+        }
+    }
+
+    fn init_test_server() -> test::TestServer {
+        test::TestServer::with_factory(|| {
+            let weather_actor = SyncArbiter::start(1, || TestWeatherActor {});
+            let aggregator = Aggregator::new()
+                .add_api("first", weather_actor.clone().recipient())
+                .add_api("second", weather_actor.recipient())
+                .start();
+
+            WebAPI::new(aggregator.clone().recipient())
+                .stream(aggregator.recipient())
+                .app()
+        })
+    }
+
+    /// Имена и данные событий из ответа. Ответ читается из сокета
+    /// напрямую: тестовый клиент actix-web замечает конец потока
+    /// с задержкой в несколько секунд.
+    fn read_events(srv: &test::TestServer, uri: &str) -> Vec<(String, serde_json::Value)> {
+        let mut socket = TcpStream::connect(srv.addr()).expect("Failed to connect");
+        write!(socket, "GET {} HTTP/1.1\r\nConnection: close\r\n\r\n", uri)
+            .expect("Failed to send test request");
+
+        let mut response = String::new();
+        socket
+            .read_to_string(&mut response)
+            .expect("Failed to read response");
+
+        let body_start = response.find("\r\n\r\n").expect("No body") + 4;
+        let (head, mut chunked) = response.split_at(body_start);
+        assert!(head.starts_with("HTTP/1.1 200"));
+        assert!(head.contains("content-type: text/event-stream"));
+
+        let mut body = String::new();
+        while let Some(end) = chunked.find("\r\n") {
+            let size = usize::from_str_radix(&chunked[..end], 16).expect("Invalid chunk size");
+            body.push_str(&chunked[end + 2..end + 2 + size]);
+            chunked = &chunked[end + 4 + size..];
+        }
+
+        body.split("\n\n")
+            .filter(|event| !event.is_empty())
+            .map(|event| {
+                let mut lines = event.lines();
+                let name = lines.next().expect("No event name");
+                let data = lines.next().expect("No event data");
+
+                (
+                    name.trim_start_matches("event: ").to_string(),
+                    serde_json::from_str(data.trim_start_matches("data: "))
+                        .expect("Invalid event data"),
+                )
+            }).collect()
+    }
+
+    #[test]
+    fn streams_forecast() {
+        let srv = init_test_server();
+
+        let events = read_events(&srv, "/forecast/stream/UK/London");
+        let names: Vec<_> = events.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["provider", "provider", "forecast"]);
+
+        let sources = &events[0].1["aggregate"]["sources"];
+        assert_eq!(sources.as_array().map(Vec::len), Some(1));
+        assert_eq!(events[1].1["aggregate"]["sources"], json!(["first", "second"]));
+        assert_eq!(events[2].1["data"][0]["temperature"], 10.0);
+
+        let events = read_events(&srv, "/v1/forecast/stream/UK/Londn");
+        assert_eq!(events.last().map(|(name, _)| name.as_str()), Some("error"));
+        assert_eq!(events.last().map(|(_, data)| &data["code"]), Some(&json!("unknown_location")));
+    }
+}
@@ -0,0 +1,268 @@
+use std::time::{Duration, SystemTime};
+
+use actix::Recipient;
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::middleware::{Finished, Middleware, Response, Started};
+use actix_web::{HttpRequest, HttpResponse, Result};
+use uuid::Uuid;
+
+use apis::WeatherQuery;
+use logging::LogFormat;
+use otlp::{Span, SpanKind};
+
+/// Заголовок с идентификатором запроса.
+pub(super) const REQUEST_ID: &str = "x-request-id";
+
+/// Самый длинный идентификатор запроса, который принимается от клиента.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Идентификатор запроса и время начала его обработки.
+struct RequestId(String, SystemTime);
+
+/// Промежуточный обработчик, присваивающий каждому запросу идентификатор:
+/// из заголовка `X-Request-Id`, если клиент его передал, или новый UUID.
+/// Идентификатор возвращается в том же заголовке ответа, а если задан
+/// `tracer`, то запрос отправляется ему как участок трассировки.
+pub(super) struct RequestTracing(pub(super) Option<Recipient<Span>>);
+
+/// Идентификатор из заголовка запроса, если он не слишком длинный
+/// и состоит из видимых символов ASCII.
+fn provided_id<S>(req: &HttpRequest<S>) -> Option<String> {
+    let id = req.headers().get(REQUEST_ID)?.to_str().ok()?;
+
+    let valid = !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.bytes().all(|b| b.is_ascii_graphic());
+
+    if valid {
+        Some(id.to_string())
+    } else {
+        None
+    }
+}
+
+impl<S> Middleware<S> for RequestTracing {
+    fn start(&self, req: &HttpRequest<S>) -> Result<Started> {
+        let id = provided_id(req).unwrap_or_else(|| Uuid::new_v4().to_string());
+        req.extensions_mut()
+            .insert(RequestId(id, SystemTime::now()));
+
+        Ok(Started::Done)
+    }
+
+    fn response(&self, req: &HttpRequest<S>, mut resp: HttpResponse) -> Result<Response> {
+        if let Some(RequestId(ref id, start)) = req.extensions().get::<RequestId>() {
+            if let Ok(value) = HeaderValue::from_str(id) {
+                resp.headers_mut()
+                    .insert(HeaderName::from_static(REQUEST_ID), value);
+            }
+
+            if let Some(ref tracer) = self.0 {
+                let route = req
+                    .resource()
+                    .rdef()
+                    .map_or("unmatched", |rdef| rdef.pattern());
+
+                let _ = tracer.do_send(Span {
+                    request_id: id.clone(),
+                    name: format!("{} {}", req.method(), route),
+                    kind: SpanKind::Server,
+                    start: *start,
+                    end: SystemTime::now(),
+                    attributes: vec![
+                        ("http.request.method", req.method().to_string()),
+                        ("http.route", route.to_string()),
+                        (
+                            "http.response.status_code",
+                            resp.status().as_u16().to_string(),
+                        ),
+                    ],
+                    failed: resp.status().is_server_error(),
+                });
+            }
+        }
+
+        Ok(Response::Done(resp))
+    }
+}
+
+/// Промежуточный обработчик, пишущий в лог каждый запрос с его
+/// идентификатором. В тексте строка такая же, как у `middleware::Logger`
+/// по умолчанию, в JSON маршрут, код ответа и время обработки выводятся
+/// в отдельных полях. Строка запроса в лог не попадает: в ней может быть
+/// ключ клиента `api_key`.
+pub(super) struct AccessLog(pub(super) LogFormat);
+
+/// Строка лога запроса в текстовом формате.
+fn text_line<S>(
+    id: &str, req: &HttpRequest<S>, resp: &HttpResponse, elapsed: Duration,
+) -> String {
+    let header = |name: HeaderName| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("-")
+    };
+
+    format!(
+        "[{}] {} \"{} {} {:?}\" {} {} \"{}\" \"{}\" {:.6}",
+        id,
+        req.connection_info().remote().unwrap_or("-"),
+        req.method(),
+        req.path(),
+        req.version(),
+        resp.status().as_u16(),
+        resp.response_size(),
+        header(header::REFERER),
+        header(header::USER_AGENT),
+        elapsed.as_secs_f64()
+    )
+}
+
+impl<S> Middleware<S> for AccessLog {
+    fn finish(&self, req: &HttpRequest<S>, resp: &HttpResponse) -> Finished {
+        if let Some(RequestId(ref id, start)) = req.extensions().get::<RequestId>() {
+            let elapsed = start.elapsed().unwrap_or_default();
+            if self.0 == LogFormat::Text {
+                info!("{}", text_line(id, req, resp, elapsed));
+                return Finished::Done;
+            }
+
+            let route = req
+                .resource()
+                .rdef()
+                .map_or("unmatched", |rdef| rdef.pattern());
+            let duration_ms = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+
+            info!(
+                request_id = id.as_str(),
+                method = req.method().as_str(),
+                route = route,
+                path = req.path(),
+                status = resp.status().as_u16(),
+                duration_ms = duration_ms;
+                "{} {} {} {} ms",
+                req.method(),
+                req.path(),
+                resp.status().as_u16(),
+                duration_ms
+            );
+        }
+
+        Finished::Done
+    }
+}
+
+/// Идентификатор, присвоенный запросу.
+pub(super) fn request_id<S>(req: &HttpRequest<S>) -> Option<String> {
+    req.extensions()
+        .get::<RequestId>()
+        .map(|RequestId(id, _)| id.clone())
+}
+
+/// Запрос к агрегатору с идентификатором HTTP-запроса, ради которого
+/// он выполняется.
+pub(super) fn traced<S>(req: &HttpRequest<S>, query: WeatherQuery) -> WeatherQuery {
+    match request_id(req) {
+        Some(id) => query.with_request_id(id),
+        None => query,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use actix::prelude::*;
+    use actix_web::{http, test, HttpMessage};
+    use chrono::Utc;
+    use failure::Error;
+
+    use super::*;
+    use aggregator::Aggregator;
+    use apis::{WeatherData, WeatherReport};
+    use web_api::WebAPI;
+
+    struct TestWeatherActor {
+        seen: Arc<Mutex<Vec<Option<String>>>>,
+    }
+
+    impl Actor for TestWeatherActor {
+        type Context = SyncContext<Self>;
+    }
+
+    impl Handler<WeatherQuery> for TestWeatherActor {
+        type Result = Result<WeatherReport, Error>;
+
+        fn handle(&mut self, msg: WeatherQuery, _ctx: &mut Self::Context) -> Self::Result {
+            let id = msg.request_id().map(str::to_string);
+            self.seen.lock().unwrap().push(id);
+
+            Ok(WeatherReport::from(smallvec![WeatherData {
+                temperature: 10.0,
+                date: Utc::now().naive_utc().date(),
+            }]))
+        }
+    }
+
+    #[test]
+    fn propagates_request_id() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+
+        let mut srv = {
+            let seen = seen.clone();
+            test::TestServer::with_factory(move || {
+                let seen = seen.clone();
+                let weather_actor =
+                    SyncArbiter::start(1, move || TestWeatherActor { seen: seen.clone() });
+
+                let aggregator = Aggregator::new()
+                    .add_api("test", weather_actor.recipient())
+                    .start();
+
+                WebAPI::new(aggregator.recipient()).app()
+            })
+        };
+
+        let request = srv
+            .client(http::Method::GET, "/forecast/weekly/UK/London")
+            .header(REQUEST_ID, "client-id-1")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+        assert_eq!(response.headers()[REQUEST_ID], "client-id-1");
+
+        let request = srv
+            .client(http::Method::GET, "/forecast/weekly/UK/Paris")
+            .header(REQUEST_ID, "invalid id")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+        let generated = response.headers()[REQUEST_ID].to_str().unwrap().to_string();
+        assert!(Uuid::parse_str(&generated).is_ok());
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(
+            *seen,
+            vec![Some("client-id-1".to_string()), Some(generated)]
+        );
+    }
+
+    #[test]
+    fn hides_query_in_access_log() {
+        let req = test::TestRequest::with_uri("/forecast/weekly/UK/London?api_key=secret")
+            .header("user-agent", "curl")
+            .finish();
+        let resp = HttpResponse::Ok().finish();
+
+        let line = text_line("id-1", &req, &resp, Duration::from_millis(5));
+        assert!(line.starts_with("[id-1] "));
+        assert!(line.contains("\"GET /forecast/weekly/UK/London HTTP/1.1\" 200 "));
+        assert!(line.contains("\"curl\""));
+        assert!(!line.contains("secret"));
+    }
+}
@@ -0,0 +1,314 @@
+use actix_web::{error, http, App, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use futures::future;
+use futures::Future;
+
+use aggregator::Forecast;
+use apis::{WeatherData, WeatherQuery};
+
+use super::batch::BatchResponse;
+use super::format::{Days, Document, Format, Formatted, Negotiated, Render, Table};
+use super::{APIError, APIFuture, Cached, WebAPI};
+
+/// Единицы измерения значений в ответе.
+#[derive(Serialize, Deserialize)]
+struct Units {
+    temperature: String,
+}
+
+/// Сведения о прогнозе: город, единицы измерения, API, из ответов которых
+/// собран прогноз, и время его последнего обновления.
+#[derive(Serialize, Deserialize)]
+struct Meta {
+    location: WeatherQuery,
+    units: Units,
+    sources: Vec<String>,
+    generated_at: DateTime<Utc>,
+}
+
+/// Описание ошибки: машиночитаемый код и сообщение для клиента.
+#[derive(Serialize, Deserialize)]
+pub(super) struct EnvelopeError {
+    pub(super) code: String,
+    pub(super) message: String,
+}
+
+impl<'a> From<&'a APIError> for EnvelopeError {
+    fn from(reason: &'a APIError) -> Self {
+        Self {
+            code: reason.code().to_string(),
+            message: reason.message(),
+        }
+    }
+}
+
+/// Конверт, в который упакованы все ответы API v2. При ошибке `data`
+/// и `meta` пусты, а в `errors` лежит её описание.
+#[derive(Serialize, Deserialize)]
+struct Envelope<D> {
+    data: Option<D>,
+    meta: Option<Meta>,
+    errors: Vec<EnvelopeError>,
+}
+
+impl<D> Envelope<D> {
+    fn new(data: D, forecast: &Forecast) -> Self {
+        Self {
+            data: Some(data),
+            meta: Some(Meta {
+                location: forecast.query.clone(),
+                units: Units {
+                    temperature: "celsius".to_string(),
+                },
+                sources: forecast.sources.clone(),
+                generated_at: forecast.fetched_at,
+            }),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Конверт с данными сразу по нескольким городам: сведения о каждом
+    /// лежат в самих данных, поэтому `meta` нет.
+    fn without_meta(data: D) -> Self {
+        Self {
+            data: Some(data),
+            meta: None,
+            errors: Vec::new(),
+        }
+    }
+}
+
+/// В CSV попадают только данные, а если их нет - то ошибки.
+impl<D: Document> Document for Envelope<D> {
+    fn table(&self) -> Table {
+        match self.data {
+            Some(ref data) => data.table(),
+            None => Table {
+                columns: &["code", "message"],
+                rows: self
+                    .errors
+                    .iter()
+                    .map(|err| vec![err.code.clone(), err.message.clone()])
+                    .collect(),
+            },
+        }
+    }
+}
+
+/// Пустые данные в конверте с ошибкой.
+impl Document for () {
+    fn table(&self) -> Table {
+        Table {
+            columns: &[],
+            rows: Vec::new(),
+        }
+    }
+}
+
+/// Ошибка API v2 - та же `APIError`, но отданная в конверте.
+#[derive(Fail, Debug)]
+#[fail(display = "{}", _0)]
+pub(super) struct V2Error(pub(super) APIError);
+
+impl Render for V2Error {
+    fn render(&self, format: Format) -> HttpResponse {
+        let V2Error(ref reason) = *self;
+
+        let mut resp = format.respond(
+            reason.status_code(),
+            &Envelope::<()> {
+                data: None,
+                meta: None,
+                errors: vec![EnvelopeError::from(reason)],
+            },
+        );
+        reason.set_headers(&mut resp);
+
+        resp
+    }
+}
+
+impl error::ResponseError for V2Error {
+    fn error_response(&self) -> HttpResponse {
+        self.render(Format::Json)
+    }
+}
+
+type V2Responder<D> = Box<Future<Item = Cached<Envelope<D>>, Error = Negotiated<V2Error>>>;
+
+type V2BatchResponder =
+    Box<Future<Item = Formatted<Envelope<BatchResponse>>, Error = Negotiated<V2Error>>>;
+
+impl WebAPI {
+    /// Маршруты прогнозов с ответом в конверте. Календаря и потока
+    /// Server-Sent Events среди них нет: их в конверт не упаковать.
+    pub(super) fn v2_routes(app: App<Self>) -> App<Self> {
+        app.resource("/v2/forecast/daily/{country}/{city}/{day}", |r| {
+            r.method(http::Method::GET).f(Self::daily_forecast_v2)
+        }).resource("/v2/forecast/weekly/{country}/{city}", |r| {
+            r.method(http::Method::GET).f(Self::weekly_forecast_v2)
+        }).resource("/v2/forecast/range/{country}/{city}", |r| {
+            r.method(http::Method::GET).f(Self::range_forecast_v2)
+        }).resource("/v2/forecast/batch", |r| {
+            r.method(http::Method::POST).f(Self::batch_forecast_v2)
+        })
+    }
+
+    fn daily_forecast_v2(req: &HttpRequest<Self>) -> V2Responder<WeatherData> {
+        Self::envelope(req, Self::fetch_daily)
+    }
+
+    fn weekly_forecast_v2(req: &HttpRequest<Self>) -> V2Responder<Days> {
+        Self::envelope(req, Self::fetch_weekly)
+    }
+
+    fn range_forecast_v2(req: &HttpRequest<Self>) -> V2Responder<Days> {
+        Self::envelope(req, Self::fetch_range)
+    }
+
+    /// Пакетный прогноз в конверте. Ошибки по отдельным городам остаются
+    /// в их результатах, а в `errors` попадают только ошибки всего запроса.
+    fn batch_forecast_v2(req: &HttpRequest<Self>) -> V2BatchResponder {
+        let format = match Format::negotiate(req) {
+            Ok(format) => format,
+            Err(reason) => return Box::new(future::err(Negotiated(V2Error(reason), Format::Json))),
+        };
+
+        let envelope = Self::fetch_batch(req)
+            .map(move |response| Formatted(Envelope::without_meta(response), format))
+            .map_err(move |reason| Negotiated(V2Error(reason), format));
+
+        Box::new(envelope)
+    }
+
+    fn envelope<D: 'static>(
+        req: &HttpRequest<Self>,
+        fetch: fn(&HttpRequest<Self>) -> APIFuture<(D, Forecast)>,
+    ) -> V2Responder<D> {
+        let format = match Format::negotiate(req) {
+            Ok(format) => format,
+            Err(reason) => return Box::new(future::err(Negotiated(V2Error(reason), Format::Json))),
+        };
+
+        let envelope = fetch(req).then(move |res| match res {
+            Ok(Ok((data, forecast))) => {
+                let envelope = Envelope::new(data, &forecast);
+                Ok(Cached::new(envelope, &forecast, format))
+            }
+            Ok(Err(reason)) | Err(reason) => Err(Negotiated(V2Error(reason), format)),
+        });
+
+        Box::new(envelope)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix::prelude::*;
+    use actix_web::{test, HttpMessage};
+    use failure::Error;
+
+    use super::*;
+    use aggregator::{ForecastQuery, UnknownLocation};
+
+    struct TestWeatherActor;
+
+    impl Actor for TestWeatherActor {
+        type Context = SyncContext<Self>;
+    }
+
+    impl Handler<ForecastQuery> for TestWeatherActor {
+        type Result = Result<Forecast, Error>;
+
+        fn handle(&mut self, msg: ForecastQuery, _ctx: &mut Self::Context) -> Self::Result {
+            let ForecastQuery(query) = msg;
+
+            if query != WeatherQuery::new("UK".to_string(), "London".to_string()) {
+                return Err(Error::from(UnknownLocation(query)));
+            }
+
+            Ok(Forecast {
+                query,
+                data: smallvec![WeatherData {
+                    temperature: 10.0,
+                    date: Utc::now().naive_utc().date(),
+                }],
+                sources: vec!["test".to_string()],
+                utc_offset: None,
+                fetched_at: Utc::now(),
+                expires_at: Utc::now(),
+            })
+        }
+    }
+
+    fn init_test_server() -> test::TestServer {
+        test::TestServer::build_with_state(|| {
+            let weather_actor = SyncArbiter::start(1, || TestWeatherActor {});
+            WebAPI::new(weather_actor.recipient())
+        }).start(|app: &mut test::TestApp<WebAPI>| {
+            app.resource("/v2/forecast/daily/{country}/{city}/{day}", |r| {
+                r.method(http::Method::GET).f(WebAPI::daily_forecast_v2)
+            });
+        })
+    }
+
+    #[test]
+    fn wraps_data_in_envelope() {
+        let mut srv = init_test_server();
+
+        let now = Utc::now().format("%Y-%m-%d");
+
+        let request = srv
+            .client(
+                http::Method::GET,
+                &format!("/v2/forecast/daily/UK/London/{}", now),
+            ).finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert!(response.status().is_success());
+
+        let envelope: Envelope<WeatherData> = srv
+            .execute(response.json())
+            .expect("Failed to parse response as JSON");
+
+        assert_eq!(envelope.data.map(|data| data.temperature), Some(10.0));
+        assert!(envelope.errors.is_empty());
+
+        let meta = envelope.meta.expect("Meta is missing");
+        assert_eq!(meta.location.to_string(), "UK/London");
+        assert_eq!(meta.units.temperature, "celsius");
+        assert_eq!(meta.sources, vec!["test".to_string()]);
+    }
+
+    #[test]
+    fn wraps_errors_in_envelope() {
+        let mut srv = init_test_server();
+
+        let now = Utc::now().format("%Y-%m-%d");
+
+        let request = srv
+            .client(
+                http::Method::GET,
+                &format!("/v2/forecast/daily/UK/Londn/{}", now),
+            ).finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+
+        assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+
+        let envelope: Envelope<WeatherData> = srv
+            .execute(response.json())
+            .expect("Failed to parse response as JSON");
+
+        assert!(envelope.data.is_none());
+        assert!(envelope.meta.is_none());
+        assert_eq!(envelope.errors.len(), 1);
+        assert_eq!(envelope.errors[0].code, "unknown_location");
+        assert_eq!(envelope.errors[0].message, "location not found - UK/Londn");
+    }
+}
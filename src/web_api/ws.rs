@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use actix::fut;
+use actix::prelude::*;
+use actix_web::{error, http, ws, App, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde_json;
+
+use aggregator::{Aggregator, DayChange, ForecastUpdated, Subscribe, Unsubscribe};
+use apis::{WeatherData, WeatherQuery};
+
+use super::v2::EnvelopeError;
+use super::{APIError, WebAPI};
+
+/// Максимальное число городов, на которые подписано одно соединение.
+const MAX_SUBSCRIPTIONS: usize = 50;
+
+/// Интервал, с которым сервер пингует клиента, чтобы простаивающее
+/// соединение не закрывали прокси.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Доступ к подпискам агрегатора на обновления прогноза.
+pub struct ForecastSubscriptions {
+    subscribe: Recipient<Subscribe>,
+    unsubscribe: Recipient<Unsubscribe>,
+}
+
+impl ForecastSubscriptions {
+    pub fn new(aggregator: &Addr<Aggregator>) -> Self {
+        Self {
+            subscribe: aggregator.clone().recipient(),
+            unsubscribe: aggregator.clone().recipient(),
+        }
+    }
+}
+
+/// Сообщение клиента.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ClientMessage {
+    Subscribe { location: WeatherQuery },
+    Unsubscribe { location: WeatherQuery },
+}
+
+/// Сообщение сервера. `subscribed` содержит закэшированный на момент
+/// подписки прогноз, `update` - прогноз после обновления кэша и дни,
+/// прогноз на которые изменился.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ServerMessage {
+    Subscribed {
+        location: WeatherQuery,
+        data: Vec<WeatherData>,
+    },
+    Unsubscribed {
+        location: WeatherQuery,
+    },
+    Update {
+        location: WeatherQuery,
+        data: Vec<WeatherData>,
+        changes: Vec<DayChange>,
+        fetched_at: DateTime<Utc>,
+    },
+    Error(EnvelopeError),
+}
+
+/// Соединение WebSocket с подписками на города.
+struct Session {
+    subscribe: Recipient<Subscribe>,
+    unsubscribe: Recipient<Unsubscribe>,
+    subscriptions: HashMap<WeatherQuery, usize>,
+}
+
+impl Session {
+    fn send(ctx: &mut ws::WebsocketContext<Self, WebAPI>, message: &ServerMessage) {
+        match serde_json::to_string(message) {
+            Ok(text) => ctx.text(text),
+            Err(err) => error!("Failed to serialize subscription message: {}", err),
+        }
+    }
+
+    fn send_error(ctx: &mut ws::WebsocketContext<Self, WebAPI>, reason: &APIError) {
+        if let APIError::UnexpectedError(ref err) = reason {
+            error!("Subscription failed: {}", err);
+        }
+
+        Self::send(ctx, &ServerMessage::Error(EnvelopeError::from(reason)));
+    }
+
+    fn bad_request(ctx: &mut ws::WebsocketContext<Self, WebAPI>, reason: String) {
+        Self::send_error(ctx, &APIError::BadRequest(error::ErrorBadRequest(reason)));
+    }
+
+    fn subscribe(
+        &mut self,
+        location: WeatherQuery,
+        ctx: &mut ws::WebsocketContext<Self, WebAPI>,
+    ) {
+        if !self.subscriptions.contains_key(&location)
+            && self.subscriptions.len() >= MAX_SUBSCRIPTIONS
+        {
+            let reason = format!("at most {} locations per connection", MAX_SUBSCRIPTIONS);
+            return Self::bad_request(ctx, reason);
+        }
+
+        let subscribe = Subscribe {
+            query: location.clone(),
+            subscriber: ctx.address().recipient(),
+        };
+
+        self.subscribe
+            .send(subscribe)
+            .into_actor(self)
+            .then(move |res, session, ctx| {
+                match res {
+                    Ok((id, forecast)) => {
+                        if let Some(previous) = session.subscriptions.insert(location.clone(), id) {
+                            let _ = session.unsubscribe.do_send(Unsubscribe(previous));
+                        }
+
+                        let subscribed = ServerMessage::Subscribed {
+                            location,
+                            data: forecast.data.to_vec(),
+                        };
+                        Self::send(ctx, &subscribed);
+                    }
+                    Err(err) => Self::send_error(ctx, &APIError::UnexpectedError(err.into())),
+                }
+
+                fut::ok(())
+            }).wait(ctx);
+    }
+
+    fn unsubscribe(
+        &mut self,
+        location: WeatherQuery,
+        ctx: &mut ws::WebsocketContext<Self, WebAPI>,
+    ) {
+        if let Some(id) = self.subscriptions.remove(&location) {
+            let _ = self.unsubscribe.do_send(Unsubscribe(id));
+        }
+
+        Self::send(ctx, &ServerMessage::Unsubscribed { location });
+    }
+}
+
+impl Actor for Session {
+    type Context = ws::WebsocketContext<Self, WebAPI>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(PING_INTERVAL, |_session, ctx| ctx.ping(""));
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        for id in self.subscriptions.values() {
+            let _ = self.unsubscribe.do_send(Unsubscribe(*id));
+        }
+    }
+}
+
+impl StreamHandler<ws::Message, ws::ProtocolError> for Session {
+    fn handle(&mut self, msg: ws::Message, ctx: &mut Self::Context) {
+        match msg {
+            ws::Message::Text(text) => match serde_json::from_str(&text) {
+                Ok(ClientMessage::Subscribe { location }) => self.subscribe(location, ctx),
+                Ok(ClientMessage::Unsubscribe { location }) => self.unsubscribe(location, ctx),
+                Err(err) => Self::bad_request(ctx, format!("invalid message - {}", err)),
+            },
+            ws::Message::Binary(_) => Self::bad_request(ctx, "expected JSON text".to_string()),
+            ws::Message::Ping(msg) => ctx.pong(&msg),
+            ws::Message::Pong(_) => {}
+            ws::Message::Close(_) => ctx.stop(),
+        }
+    }
+}
+
+impl Handler<ForecastUpdated> for Session {
+    type Result = ();
+
+    fn handle(&mut self, msg: ForecastUpdated, ctx: &mut Self::Context) -> Self::Result {
+        let ForecastUpdated { forecast, changes } = msg;
+
+        // Обновление могло прийти уже после отписки.
+        if !self.subscriptions.contains_key(&forecast.query) {
+            return;
+        }
+
+        let update = ServerMessage::Update {
+            data: forecast.data.to_vec(),
+            location: forecast.query,
+            changes,
+            fetched_at: forecast.fetched_at,
+        };
+        Self::send(ctx, &update);
+    }
+}
+
+impl WebAPI {
+    pub(super) fn ws_routes(app: App<Self>) -> App<Self> {
+        app.resource("/forecast/subscribe", |r| {
+            r.method(http::Method::GET).f(Self::subscribe)
+        })
+    }
+
+    /// Подписки на обновления прогноза через WebSocket. Клиент отправляет
+    /// `{"type": "subscribe", "location": {...}}` и получает `update`
+    /// каждый раз, когда агрегатор обновляет прогноз по этому городу.
+    fn subscribe(req: &HttpRequest<Self>) -> Result<HttpResponse, error::Error> {
+        let session = match req.state().subscriptions {
+            Some(ref subscriptions) => Session {
+                subscribe: subscriptions.subscribe.clone(),
+                unsubscribe: subscriptions.unsubscribe.clone(),
+                subscriptions: HashMap::new(),
+            },
+            None => return Err(error::ErrorNotFound("subscriptions are not configured")),
+        };
+
+        ws::start(req, session)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use actix_web::test;
+    use failure::Error;
+    use futures::Stream;
+    use serde_json::Value;
+
+    use super::*;
+    use apis::WeatherReport;
+    use web_api::CacheAdmin;
+
+    /// Тестовый API, у которого прогноз теплеет с каждым запросом.
+    struct WarmingWeatherActor {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Actor for WarmingWeatherActor {
+        type Context = SyncContext<Self>;
+    }
+
+    impl Handler<WeatherQuery> for WarmingWeatherActor {
+        type Result = Result<WeatherReport, Error>;
+
+        fn handle(&mut self, _msg: WeatherQuery, _ctx: &mut Self::Context) -> Self::Result {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+
+            Ok(WeatherReport::from(smallvec![WeatherData {
+                temperature: 10.0 + call as f32,
+                date: Utc::now().naive_utc().date(),
+            }]))
+        }
+    }
+
+    fn init_test_server() -> test::TestServer {
+        test::TestServer::with_factory(|| {
+            let calls = Arc::new(AtomicUsize::new(0));
+            let weather_actor = SyncArbiter::start(1, move || WarmingWeatherActor {
+                calls: calls.clone(),
+            });
+            let aggregator = Aggregator::new()
+                .add_api("test", weather_actor.recipient())
+                .start();
+            let admin = CacheAdmin::new("secret".to_string(), &aggregator);
+            let subscriptions = ForecastSubscriptions::new(&aggregator);
+
+            WebAPI::new(aggregator.recipient())
+                .admin(admin)
+                .subscriptions(subscriptions)
+                .app()
+        })
+    }
+
+    fn next_message(
+        srv: &mut test::TestServer,
+        reader: ws::ClientReader,
+    ) -> (Value, ws::ClientReader) {
+        let (message, reader) = srv
+            .execute(reader.into_future())
+            .map_err(|(err, _)| err)
+            .expect("Failed to read message");
+
+        match message {
+            Some(ws::Message::Text(text)) => (
+                serde_json::from_str(&text).expect("Invalid message"),
+                reader,
+            ),
+            message => panic!("Unexpected message {:?}", message),
+        }
+    }
+
+    #[test]
+    fn pushes_updates() {
+        let mut srv = init_test_server();
+        let (reader, mut writer) = srv.ws_at("/forecast/subscribe").expect("Failed to connect");
+
+        writer.text(r#"{"type": "subscribe", "location": {"country": "UK", "city": "London"}}"#);
+        let (subscribed, reader) = next_message(&mut srv, reader);
+        assert_eq!(subscribed["type"], "subscribed");
+        assert_eq!(subscribed["location"]["city"], "London");
+
+        // Кэш пуст, поэтому прогноз запрашивается сразу после подписки.
+        let (update, reader) = next_message(&mut srv, reader);
+        assert_eq!(update["type"], "update");
+        assert_eq!(update["changes"][0]["previous"], Value::Null);
+        assert_eq!(update["changes"][0]["current"], 10.0);
+
+        let request = srv
+            .client(http::Method::POST, "/admin/cache/UK/London/refresh")
+            .header(http::header::AUTHORIZATION, "Bearer secret")
+            .finish()
+            .expect("Failed to construct test request");
+        let response = srv
+            .execute(request.send())
+            .expect("Failed to send test request");
+        assert!(response.status().is_success());
+
+        let (update, reader) = next_message(&mut srv, reader);
+        assert_eq!(update["type"], "update");
+        assert_eq!(update["data"][0]["temperature"], 11.0);
+        assert_eq!(update["changes"][0]["previous"], 10.0);
+        assert_eq!(update["changes"][0]["current"], 11.0);
+
+        writer.text(r#"{"type": "unsubscribe", "location": {"country": "UK", "city": "London"}}"#);
+        let (unsubscribed, reader) = next_message(&mut srv, reader);
+        assert_eq!(unsubscribed["type"], "unsubscribed");
+
+        writer.text("{}");
+        let (error, _) = next_message(&mut srv, reader);
+        assert_eq!(error["type"], "error");
+        assert_eq!(error["code"], "bad_request");
+    }
+}